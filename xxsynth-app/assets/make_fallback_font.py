@@ -0,0 +1,230 @@
+#!/usr/bin/env python3
+# 从 GNU Unifont 的 unifont.hex 生成内置的备用中文字体 fallback-cjk-subset.ttf
+# 只收录 src 中出现的中文字符与常用的全角标点，界面文字有新增的汉字时需要重新生成：
+#   python3 assets/make_fallback_font.py path/to/unifont.hex
+# unifont.hex 可以从 Unifont 官网或 crates.io 上的 unifont crate (data/unifont.hex) 获得
+# 点阵中每个像素转换成一个方块轮廓 (相邻的行合并为矩形)，不依赖 fontTools
+
+import glob
+import os
+import struct
+import sys
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+OUTPUT = os.path.join(HERE, "fallback-cjk-subset.ttf")
+
+PIXEL = 8  # 每个像素的字体单位
+UNITS_PER_EM = 16 * PIXEL
+ASCENT = 14 * PIXEL  # Unifont 的基线在倒数第 2 行之上
+DESCENT = 2 * PIXEL
+FAMILY = "XXSynth Fallback CJK"
+VERSION = "Version 1.0 (GNU Unifont 15.1.05)"
+COPYRIGHT = "Glyphs from GNU Unifont, Copyright (C) 1998-2023 Roman Czyborra, Paul Hardy, Qianqian Fang, Andrew Miller, Johnnie Weaver, David Corbett, Nils Moskopp, Rebecca Bettencourt, et al."
+LICENSE = "SIL Open Font License, Version 1.1"
+LICENSE_URL = "https://openfontlicense.org"
+
+
+def wanted_chars():
+    chars = set()
+    for path in glob.glob(os.path.join(HERE, "..", "src", "**", "*.rs"), recursive=True):
+        with open(path, encoding="utf-8") as f:
+            chars.update(c for c in f.read() if 0x2E80 <= ord(c) <= 0xFFFF)
+    # 中文标点与全角 ASCII 全部收录
+    chars.update(chr(c) for c in range(0x3000, 0x3040))
+    chars.update(chr(c) for c in range(0xFF01, 0xFF5F))
+    return sorted(ord(c) for c in chars)
+
+
+def load_hex(path):
+    glyphs = {}
+    with open(path) as f:
+        for line in f:
+            code, bits = line.strip().split(":")
+            glyphs[int(code, 16)] = bits
+    return glyphs
+
+
+# 把点阵转换为矩形 (x0, x1, 起始行, 结束行)，同一列范围的相邻行合并
+def rectangles(bits):
+    width = len(bits) // 4  # 每行 2 或 4 个十六进制字符
+    row_len = width // 4
+    open_rects = {}
+    rects = []
+    for row in range(16):
+        value = int(bits[row * row_len:(row + 1) * row_len], 16)
+        runs = []
+        x = 0
+        while x < width:
+            if value >> (width - 1 - x) & 1:
+                start = x
+                while x < width and value >> (width - 1 - x) & 1:
+                    x += 1
+                runs.append((start, x))
+            else:
+                x += 1
+        next_open = {}
+        for run in runs:
+            rect = open_rects.pop(run, None)
+            if rect is None:
+                rect = [run[0], run[1], row, row + 1]
+                rects.append(rect)
+            rect[3] = row + 1
+            next_open[run] = rect
+        open_rects = next_open
+    return width, rects
+
+
+def encode_glyph(rects):
+    if not rects:
+        return b"", (0, 0, 0, 0), 0
+    points = []
+    ends = []
+    for x0, x1, r0, r1 in rects:
+        top = ASCENT - r0 * PIXEL
+        bottom = ASCENT - r1 * PIXEL
+        left = x0 * PIXEL
+        right = x1 * PIXEL
+        # 顺时针：左上 -> 右上 -> 右下 -> 左下
+        points += [(left, top), (right, top), (right, bottom), (left, bottom)]
+        ends.append(len(points) - 1)
+    xs = [p[0] for p in points]
+    ys = [p[1] for p in points]
+    bbox = (min(xs), min(ys), max(xs), max(ys))
+
+    flags = bytearray()
+    xdata = bytearray()
+    ydata = bytearray()
+    px = py = 0
+    for x, y in points:
+        flag = 0x01
+        dx, dy = x - px, y - py
+        if dx == 0:
+            flag |= 0x10
+        elif abs(dx) <= 255:
+            flag |= 0x02 | (0x10 if dx > 0 else 0)
+            xdata.append(abs(dx))
+        else:
+            xdata += struct.pack(">h", dx)
+        if dy == 0:
+            flag |= 0x20
+        elif abs(dy) <= 255:
+            flag |= 0x04 | (0x20 if dy > 0 else 0)
+            ydata.append(abs(dy))
+        else:
+            ydata += struct.pack(">h", dy)
+        flags.append(flag)
+        px, py = x, y
+
+    data = struct.pack(">h4h", len(ends), *bbox)
+    data += b"".join(struct.pack(">H", e) for e in ends)
+    data += struct.pack(">H", 0)  # 没有 hinting 指令
+    data += bytes(flags) + bytes(xdata) + bytes(ydata)
+    return data, bbox, len(points)
+
+
+def cmap_table(codes):
+    # 格式 4：按码位排序后字形编号连续，每段用 idDelta 映射
+    segments = []
+    for gid, code in enumerate(codes, start=1):
+        if segments and segments[-1][1] == code - 1 and segments[-1][2] == gid - 1:
+            segments[-1][1] = code
+            segments[-1][2] = gid
+        else:
+            segments.append([code, code, gid])
+    ends = [s[1] for s in segments] + [0xFFFF]
+    starts = [s[0] for s in segments] + [0xFFFF]
+    deltas = [(s[2] - s[1]) & 0xFFFF for s in segments] + [1]
+    seg_count = len(ends)
+    search_range = 2 * (1 << (seg_count.bit_length() - 1))
+    sub = struct.pack(">HHHHHHH", 4, 0, 0, seg_count * 2, search_range, (seg_count.bit_length() - 1), seg_count * 2 - search_range)
+    sub += b"".join(struct.pack(">H", e) for e in ends) + struct.pack(">H", 0)
+    sub += b"".join(struct.pack(">H", s) for s in starts)
+    sub += b"".join(struct.pack(">H", d) for d in deltas)
+    sub += b"".join(struct.pack(">H", 0) for _ in range(seg_count))
+    sub = sub[:2] + struct.pack(">H", len(sub)) + sub[4:]
+    header = struct.pack(">HH", 0, 2) + struct.pack(">HHI", 0, 3, 20) + struct.pack(">HHI", 3, 1, 20)
+    return header + sub
+
+
+def name_table():
+    names = {0: COPYRIGHT, 1: FAMILY, 2: "Regular", 3: FAMILY + " Regular", 4: FAMILY, 5: VERSION,
+             6: FAMILY.replace(" ", "") + "-Regular", 13: LICENSE, 14: LICENSE_URL}
+    records = b""
+    strings = b""
+    for name_id, text in names.items():
+        encoded = text.encode("utf-16-be")
+        records += struct.pack(">HHHHHH", 3, 1, 0x409, name_id, len(encoded), len(strings))
+        strings += encoded
+    return struct.pack(">HHH", 0, len(names), 6 + len(records)) + records + strings
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def build(hex_path):
+    glyphs = load_hex(hex_path)
+    codes = [c for c in wanted_chars() if c in glyphs]
+
+    glyf = bytearray()
+    loca = [0]
+    metrics = [(UNITS_PER_EM // 2, 0)]  # .notdef 为空字形
+    bounds = [0, -DESCENT, 0, ASCENT]
+    max_points = max_contours = 0
+    for code in codes:
+        width, rects = rectangles(glyphs[code])
+        data, bbox, point_count = encode_glyph(rects)
+        glyf += data + b"\0" * (-len(data) % 4)
+        loca.append(len(glyf))
+        metrics.append((width * PIXEL, bbox[0]))
+        bounds = [min(bounds[0], bbox[0]), min(bounds[1], bbox[1]), max(bounds[2], bbox[2]), max(bounds[3], bbox[3])]
+        max_points = max(max_points, point_count)
+        max_contours = max(max_contours, len(rects))
+    loca.insert(0, 0)
+    num_glyphs = len(codes) + 1
+
+    head = struct.pack(">IIIIHHqqhhhhHHhhh", 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM,
+                       0, 0, *bounds, 0, 8, 2, 1, 0)
+    hhea = struct.pack(">IhhhHhhhhhhhhhhhH", 0x00010000, ASCENT, -DESCENT, 0, UNITS_PER_EM, 0, 0, bounds[2],
+                       1, 0, 0, 0, 0, 0, 0, 0, num_glyphs)
+    maxp = struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, num_glyphs, max_points, max_contours, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0)
+    hmtx = b"".join(struct.pack(">Hh", adv, lsb) for adv, lsb in metrics)
+    os2 = struct.pack(">HhHHHhhhhhhhhhhh", 4, UNITS_PER_EM, 400, 5, 0, 64, 56, 0, 16, 64, 56, 0, 56, 8, 32, 0)
+    os2 += bytes(10)  # PANOSE
+    os2 += struct.pack(">IIII", 0, 1 << 27, 0, 0)  # CJK Unified Ideographs
+    os2 += b"NONE" + struct.pack(">HHHhhhHHII", 0x40, min(codes), max(codes), ASCENT, -DESCENT, 0, ASCENT, DESCENT, 1 << 18, 0)
+    os2 += struct.pack(">hhHHH", 64, 96, 0, 32, 1)
+    post = struct.pack(">IIhhIIIII", 0x00030000, 0, -PIXEL, PIXEL, 0, 0, 0, 0, 0)
+    loca_data = b"".join(struct.pack(">I", offset) for offset in loca)
+
+    tables = {
+        b"OS/2": os2, b"cmap": cmap_table(codes), b"glyf": bytes(glyf), b"head": head, b"hhea": hhea,
+        b"hmtx": hmtx, b"loca": loca_data, b"maxp": maxp, b"name": name_table(), b"post": post,
+    }
+    count = len(tables)
+    entry_selector = count.bit_length() - 1
+    search_range = 16 * (1 << entry_selector)
+    font = bytearray(struct.pack(">IHHHH", 0x00010000, count, search_range, entry_selector, count * 16 - search_range))
+    offset = 12 + 16 * count
+    body = bytearray()
+    head_offset = 0
+    for tag in sorted(tables):
+        data = tables[tag]
+        font += struct.pack(">4sIII", tag, checksum(data), offset + len(body), len(data))
+        if tag == b"head":
+            head_offset = offset + len(body)
+        body += data + b"\0" * (-len(data) % 4)
+    font += body
+    adjustment = (0xB1B0AFBA - checksum(bytes(font))) & 0xFFFFFFFF
+    font[head_offset + 8:head_offset + 12] = struct.pack(">I", adjustment)
+
+    with open(OUTPUT, "wb") as f:
+        f.write(font)
+    print("%d 个字形，%d 字节 -> %s" % (len(codes), len(font), OUTPUT))
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        sys.exit("用法: make_fallback_font.py path/to/unifont.hex")
+    build(sys.argv[1])
@@ -0,0 +1,43 @@
+// 从工作区的 Cargo.lock 中读取实际链接的 xsynth 各组件版本，编译进程序供 "关于" 与诊断信息显示
+// 读不到 Cargo.lock (例如作为依赖被单独编译) 时显示为 "未知"；
+// 读到了却找不到某个直接依赖，或同一组件锁定了多个版本时直接报错，避免显示过期的版本号
+use std::path::PathBuf;
+
+const XSYNTH_CRATES: [(&str, &str); 3] = [
+    ("xsynth-core", "XSYNTH_CORE_VERSION"),
+    ("xsynth-realtime", "XSYNTH_REALTIME_VERSION"),
+    ("xsynth-soundfonts", "XSYNTH_SOUNDFONTS_VERSION"),
+];
+
+fn main() {
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    let lock_path = manifest_dir.join("..").join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+
+    let Ok(lock) = std::fs::read_to_string(&lock_path) else {
+        for (_, env) in XSYNTH_CRATES {
+            println!("cargo:rustc-env={}=未知", env);
+        }
+        return;
+    };
+    for (name, env) in XSYNTH_CRATES {
+        let versions = locked_versions(&lock, name);
+        let [version] = versions.as_slice() else {
+            panic!("{} 中 {} 的版本应当恰好有一个，实际为 {:?}，请运行 cargo update -w 重新生成", lock_path.display(), name, versions);
+        };
+        println!("cargo:rustc-env={}={}", env, version);
+    }
+}
+
+// Cargo.lock 中每个包是一段 [[package]]，name 行后紧跟 version 行
+fn locked_versions(lock: &str, name: &str) -> Vec<String> {
+    let name_line = format!("name = \"{}\"", name);
+    let mut versions = Vec::new();
+    let mut lines = lock.lines();
+    while lines.any(|line| line.trim() == name_line) {
+        if let Some(version) = lines.next().and_then(|line| line.trim().strip_prefix("version = ")) {
+            versions.push(version.trim_matches('"').to_string());
+        }
+    }
+    versions
+}
@@ -1,52 +1,823 @@
-use std::net::UdpSocket;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use xsynth_core::channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent};
+use cpal::traits::{DeviceTrait, HostTrait};
+use xsynth_core::channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent, ControlEvent};
 use xsynth_core::channel_group::{SynthEvent, SynthFormat};
 use xsynth_core::soundfont::{SampleSoundfont, SoundfontBase, SoundfontInitOptions};
-use xsynth_core::{AudioStreamParams, ChannelCount};
+use xsynth_core::AudioStreamParams;
 use xsynth_realtime::{RealtimeSynth, XSynthRealtimeConfig};
 
-use crate::config::RealtimeConfig;
+use crate::config::{AudioChannels, InterpolatorWrapper, RealtimeConfig, SoundfontEntry, Transport, VelocityCurve, MAX_GAIN, PERCUSSION_CHANNEL};
+use crate::benchmark::BenchmarkTarget;
+use crate::midithru;
+use crate::pcm_sink;
+use crate::midicapture::{CaptureEvent, MidiCapture};
+use crate::recorder::{Recorder, TapEvent};
+use crate::sfinfo::{self, SoundfontFormat, SoundfontInfo};
+
+// 长消息 (SysEx) 封包的标记字节，需与 xxsynth-winmm 保持一致
+const PACKET_SYSEX: u8 = 0x80;
+// 紧急静音封包的标记字节，需与 xxsynth-winmm 保持一致
+const PACKET_PANIC: u8 = 0x81;
+const PANIC_ALL_PORTS: u8 = 0xFF;
+// 带序号封包的标记字节，需与 xxsynth-winmm 保持一致：[PACKET_SEQUENCED, 序号, 原封包...]
+// 驱动在 UDP 模式下给每个封包编号 (0 ~ 255 循环)，引擎据此统计丢包
+const PACKET_SEQUENCED: u8 = 0x83;
+// MIDI 2.0 通用 MIDI 封包 (UMP)：[PACKET_UMP, UMP 字 (32 位大端)...]，UMP 的组 (group) 对应端口ID
+// 由支持 MIDI 2.0 的宿主或工具直接发送，引擎转换为等价的短消息后按原有路径处理
+const PACKET_UMP: u8 = 0x84;
+// 带时间戳的短消息在末尾附带的字节数 (驱动打开设备以来的微秒数，小端 u64)
+// 短消息为 2 ~ 4 字节，带时间戳的为 10 ~ 12 字节，按长度区分，旧版驱动不受影响
+const TIMESTAMP_BYTES: usize = 8;
+
+// 常见的系统复位 SysEx
+const SYSEX_GM_ON: &[u8] = &[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7];
+const SYSEX_GM2_ON: &[u8] = &[0xF0, 0x7E, 0x7F, 0x09, 0x03, 0xF7];
+const SYSEX_GS_RESET: &[u8] = &[0xF0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7];
+const SYSEX_XG_ON: &[u8] = &[0xF0, 0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7];
+
+// 测试声音：通道 0 上的 C 大三和弦 (C4 - E4 - G4)，按住约 1 秒
+const TEST_CHORD: [u8; 3] = [60, 64, 67];
+const TEST_CHORD_DURATION: Duration = Duration::from_secs(1);
+
+// 试听预设：普通预设在通道 0 上弹 C4，打击乐 (Bank 128) 在通道 9 上打军鼓
+const AUDITION_KEY: u8 = 60;
+const AUDITION_DRUM_KEY: u8 = 38;
+const PERCUSSION_BANK: u16 = 128;
+
+// 封包处理循环每次最多取出的封包数
+// 设上限是为了在持续的封包洪流中也能及时处理 UI 指令、更新统计
+const MAX_PACKET_BATCH: usize = 1024;
+// UDP 接收缓冲区大小：处理循环忙碌时，突发的大量封包先积压在系统缓冲区而不是被丢弃 (Windows 默认只有 64 KB)
+const UDP_RECV_BUFFER_SIZE: i32 = 8 * 1024 * 1024;
+
+// 启动失败时寻找空闲端口的最大尝试次数
+const MAX_PORT_PROBES: u16 = 100;
+
+// 回环自检：发出测试和弦后等待复音数更新的时间 (复音数约每 100ms 更新一次)
+const SELF_TEST_WAIT: Duration = Duration::from_millis(500);
+
+// 每个已启用音色库的加载结果，与 UI 共享，成功时附带元数据，失败的文件会被标记
+pub type SoundfontLoadResults = Arc<Mutex<Vec<(PathBuf, Result<SoundfontInfo, String>)>>>;
+
+// 发往封包处理循环的一个封包：(端口序号, 封包原始字节)，格式与 UDP 封包相同
+pub type Packet = (usize, Vec<u8>);
+
+// 全局快捷键发送紧急静音的目标：(封包发送端, 监听端口数)，与当前运行的引擎共享
+pub type PanicTarget = Arc<Mutex<Option<(Sender<Packet>, usize)>>>;
+
+// 监听中的端口，按配置的传输协议二选一
+enum Listener {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+// UI 线程发往音频线程的指令，在接收循环中处理，无需重启引擎
+pub enum EngineCommand {
+    SetSoundfonts(Vec<Arc<dyn SoundfontBase>>),
+    SetGain(f32),
+    SetLayerLimit(Option<usize>),
+    SetAftertouch(bool),
+    SetVelocityCurve(VelocityCurve),
+    SetKeyRange(u8, u8),
+    SetGmDrums(bool),
+    StartRecording(Sender<TapEvent>),
+    StopRecording,
+    StartMidiCapture(Sender<CaptureEvent>),
+    StopMidiCapture,
+}
+
+// 每个键当前的按下状态，供键盘可视化读取，全部用原子量以免拖慢音频线程
+pub struct KeyActivity {
+    counts: [AtomicU32; 128],       // 按住该键的通道数
+    last_channel: [AtomicU32; 128], // 最近按下该键的通道，用于着色
+}
+
+impl KeyActivity {
+    fn new() -> Self {
+        Self {
+            counts: std::array::from_fn(|_| AtomicU32::new(0)),
+            last_channel: std::array::from_fn(|_| AtomicU32::new(0)),
+        }
+    }
+
+    /// 该键被按住时返回最近按下它的通道
+    pub fn held_channel(&self, key: usize) -> Option<u32> {
+        if self.counts[key].load(Ordering::Relaxed) > 0 {
+            Some(self.last_channel[key].load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+}
+
+// 音频线程内部记录每个通道按住的键，只在状态变化时更新共享的 KeyActivity
+struct KeyTracker {
+    held: Vec<u128>, // 每个通道一个位图，第 n 位表示键 n 被按住
+    activity: Arc<KeyActivity>,
+}
+
+impl KeyTracker {
+    fn note_on(&mut self, channel: u32, key: u8) {
+        let key = (key & 0x7F) as usize;
+        let held = &mut self.held[channel as usize];
+        if *held & (1u128 << key) == 0 {
+            *held |= 1u128 << key;
+            self.activity.counts[key].fetch_add(1, Ordering::Relaxed);
+        }
+        self.activity.last_channel[key].store(channel, Ordering::Relaxed);
+    }
+
+    fn note_off(&mut self, channel: u32, key: u8) {
+        let key = (key & 0x7F) as usize;
+        let held = &mut self.held[channel as usize];
+        if *held & (1u128 << key) != 0 {
+            *held &= !(1u128 << key);
+            self.activity.counts[key].fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn release_channels(&mut self, channels: Range<u32>) {
+        for ch in channels {
+            let held = std::mem::take(&mut self.held[ch as usize]);
+            for key in 0..128 {
+                if held & (1u128 << key) != 0 {
+                    self.activity.counts[key].fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+// GM 规定的默认弯音范围 (半音)
+const DEFAULT_BEND_RANGE: (u8, u8) = (2, 0);
+
+// RPN 空值 (127, 127)：取消选择，之后的数据输入不再生效
+const RPN_NULL: (u8, u8) = (127, 127);
+
+// 每个通道的 RPN 选择与弯音范围
+// 弯音范围由 RPN 0 (CC 101 = 0, CC 100 = 0) 之后的数据输入设置：CC 6 为半音，CC 38 为音分
+struct RpnTracker {
+    selected: Vec<(u8, u8)>,   // 当前选择的 RPN (MSB, LSB)，选择 NRPN 时为 RPN_NULL
+    bend_range: Vec<(u8, u8)>, // (半音, 音分)
+}
+
+impl RpnTracker {
+    fn new(total_channels: u32) -> Self {
+        Self {
+            selected: vec![RPN_NULL; total_channels as usize],
+            bend_range: vec![DEFAULT_BEND_RANGE; total_channels as usize],
+        }
+    }
+
+    // 处理与 RPN 相关的控制器，弯音范围变化时返回新的范围 (半音)
+    fn control(&mut self, channel: u32, cc: u8, value: u8) -> Option<f32> {
+        let ch = channel as usize;
+        match cc {
+            101 => self.selected[ch].0 = value,
+            100 => self.selected[ch].1 = value,
+            // 选择 NRPN 时 RPN 的数据输入不再生效
+            98 | 99 => self.selected[ch] = RPN_NULL,
+            6 | 38 if self.selected[ch] == (0, 0) => {
+                if cc == 6 {
+                    self.bend_range[ch].0 = value;
+                } else {
+                    self.bend_range[ch].1 = value.min(99);
+                }
+                return Some(self.semitones(channel));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn semitones(&self, channel: u32) -> f32 {
+        let (semitones, cents) = self.bend_range[channel as usize];
+        semitones as f32 + cents as f32 / 100.0
+    }
+
+    // 系统复位 / 紧急静音后恢复默认
+    fn reset_channels(&mut self, channels: Range<u32>) {
+        for ch in channels {
+            self.selected[ch as usize] = RPN_NULL;
+            self.bend_range[ch as usize] = DEFAULT_BEND_RANGE;
+        }
+    }
+}
+
+// GM2 的 Bank MSB：120 为打击乐，121 为旋律乐器 (变体由 LSB 选择)
+const GM2_RHYTHM_BANK: u8 = 120;
+const GM2_MELODY_BANK: u8 = 121;
+
+// 每个通道的 Bank Select (CC 0 = MSB，CC 32 = LSB)，按 MIDI 规范在随后的音色切换时才生效
+// GS / XG 用 MSB 选择变体 (LSB 为音色映射，忽略)；GM2 的 MSB 120 切换为打击乐 (音色库的 Bank 128)，121 用 LSB 选择变体
+struct BankTracker {
+    msb: Vec<u8>,
+    lsb: Vec<u8>,
+    percussion: Vec<bool>,         // 当前是否为打击乐模式
+    default_percussion: Vec<bool>, // 没有指定时的打击乐模式，开启 GM 打击乐通道时为各端口的通道 10
+}
+
+impl BankTracker {
+    fn new(total_channels: u32) -> Self {
+        Self {
+            msb: vec![0; total_channels as usize],
+            lsb: vec![0; total_channels as usize],
+            percussion: vec![false; total_channels as usize],
+            default_percussion: vec![false; total_channels as usize],
+        }
+    }
+
+    fn control(&mut self, channel: u32, cc: u8, value: u8) {
+        if cc == 0 {
+            self.msb[channel as usize] = value;
+        } else {
+            self.lsb[channel as usize] = value;
+        }
+    }
+
+    // 音色切换时使用的 (xsynth Bank, 是否为打击乐)，打击乐模式下 xsynth 固定使用音色库的 Bank 128
+    fn resolve(&self, channel: u32) -> (u8, bool) {
+        let ch = channel as usize;
+        match self.msb[ch] {
+            GM2_RHYTHM_BANK => (0, true),
+            GM2_MELODY_BANK => (self.lsb[ch], false),
+            msb => (msb, self.default_percussion[ch]),
+        }
+    }
+
+    // 开关 GM 打击乐通道，同时修改这些通道当前的打击乐模式
+    fn set_default_percussion(&mut self, channels: &[u32], enabled: bool) {
+        for &ch in channels {
+            self.default_percussion[ch as usize] = enabled;
+            self.percussion[ch as usize] = enabled;
+        }
+    }
+
+    fn percussion_channels(&self) -> Vec<u32> {
+        (0..self.percussion.len() as u32).filter(|&ch| self.percussion[ch as usize]).collect()
+    }
+
+    // 系统复位后恢复默认，返回打击乐模式发生变化的通道
+    fn reset_channels(&mut self, channels: Range<u32>) -> Vec<u32> {
+        let mut changed = Vec::new();
+        for ch in channels {
+            let ch_index = ch as usize;
+            self.msb[ch_index] = 0;
+            self.lsb[ch_index] = 0;
+            if self.percussion[ch_index] != self.default_percussion[ch_index] {
+                self.percussion[ch_index] = self.default_percussion[ch_index];
+                changed.push(ch);
+            }
+        }
+        changed
+    }
+}
+
+// MIDI 规范中表情 (CC 11) 的默认值
+const DEFAULT_EXPRESSION: u8 = 127;
+
+// 每个通道的表情与触后：xsynth 没有触后事件，触后按压力缩放表情 (CC 11) 后下发
+// 通道从未收到触后时表情按原值发送，不使用触后的乐曲不受影响
+struct ExpressionTracker {
+    expression: Vec<u8>,
+    pressure: Vec<Option<u8>>,
+}
+
+impl ExpressionTracker {
+    fn new(total_channels: u32) -> Self {
+        Self {
+            expression: vec![DEFAULT_EXPRESSION; total_channels as usize],
+            pressure: vec![None; total_channels as usize],
+        }
+    }
+
+    // 按触后缩放后的表情值
+    fn scaled(&self, channel: u32) -> u8 {
+        let expression = self.expression[channel as usize] as u32;
+        match self.pressure[channel as usize] {
+            Some(pressure) => ((expression * pressure as u32 + 63) / 127) as u8,
+            None => expression as u8,
+        }
+    }
+
+    // 触后封包记录压力，返回需要重新下发的表情值；关闭触后或不是触后封包时返回 None
+    // 通道触后 (0xD0) 的压力在 data1；复音触后 (0xA0) 的 data1 为按键、data2 为压力，
+    // xsynth 无法单独调制某个按键，这里作用于整个通道
+    fn aftertouch(&mut self, enabled: bool, channel: u32, status: u8, data1: u8, data2: u8) -> Option<u8> {
+        let pressure = match status & 0xF0 {
+            0xD0 => data1,
+            0xA0 => data2,
+            _ => return None,
+        };
+        if !enabled {
+            return None;
+        }
+        self.pressure[channel as usize] = Some(pressure);
+        Some(self.scaled(channel))
+    }
+
+    // 复位控制器 / 紧急静音 / 系统复位后恢复默认 (xsynth 的 ResetControl 同样复位表情)
+    fn reset_channels(&mut self, channels: Range<u32>) {
+        for ch in channels {
+            self.expression[ch as usize] = DEFAULT_EXPRESSION;
+            self.pressure[ch as usize] = None;
+        }
+    }
+}
+
+// 一个通道当前的音色与控制器，None 表示从未收到 (保持 xsynth 的默认值)
+#[derive(Clone, Default)]
+struct ChannelState {
+    program: Option<u8>,
+    controls: HashMap<u8, u8>,
+    bend_range: Option<f32>,
+    bend: Option<f32>,
+}
+
+// RPN / NRPN 的选择与数据输入不记录：弯音范围已经单独记录，乱序重放数据输入会改到错误的参数
+const UNTRACKED_CONTROLS: [u8; 8] = [6, 38, 96, 97, 98, 99, 100, 101];
+
+// 记录发往合成器的音色切换与控制器，重新打开输出设备或开始录音时重放，让新的合成器从相同的状态开始
+struct ChannelStateTracker {
+    channels: Vec<ChannelState>,
+}
+
+impl ChannelStateTracker {
+    fn new(total_channels: u32) -> Self {
+        Self { channels: vec![ChannelState::default(); total_channels as usize] }
+    }
+
+    fn observe(&mut self, event: &SynthEvent) {
+        match event {
+            SynthEvent::Channel(ch, ChannelEvent::Audio(audio)) => {
+                if let Some(state) = self.channels.get_mut(*ch as usize) {
+                    Self::apply(state, audio);
+                }
+            }
+            SynthEvent::AllChannels(ChannelEvent::Audio(audio)) => {
+                for state in &mut self.channels {
+                    Self::apply(state, audio);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply(state: &mut ChannelState, event: &ChannelAudioEvent) {
+        match event {
+            ChannelAudioEvent::ProgramChange(program) => state.program = Some(*program),
+            ChannelAudioEvent::Control(ControlEvent::Raw(cc, value)) if !UNTRACKED_CONTROLS.contains(cc) => {
+                state.controls.insert(*cc, *value);
+            }
+            ChannelAudioEvent::Control(ControlEvent::PitchBendSensitivity(semitones)) => state.bend_range = Some(*semitones),
+            ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value)) => state.bend = Some(*value),
+            // 复位控制器不影响音色
+            ChannelAudioEvent::ResetControl => {
+                state.controls.clear();
+                state.bend_range = None;
+                state.bend = None;
+            }
+            _ => {}
+        }
+    }
+
+    // 重建当前状态的事件：先控制器 (包括 Bank Select)，再音色切换，最后弯音
+    fn events(&self) -> Vec<SynthEvent> {
+        let mut events = Vec::new();
+        for (ch, state) in self.channels.iter().enumerate() {
+            let mut send = |event| events.push(SynthEvent::Channel(ch as u32, ChannelEvent::Audio(event)));
+            let mut controls: Vec<_> = state.controls.iter().map(|(&cc, &value)| (cc, value)).collect();
+            controls.sort_unstable();
+            for (cc, value) in controls {
+                send(ChannelAudioEvent::Control(ControlEvent::Raw(cc, value)));
+            }
+            if let Some(program) = state.program {
+                send(ChannelAudioEvent::ProgramChange(program));
+            }
+            if let Some(semitones) = state.bend_range {
+                send(ChannelAudioEvent::Control(ControlEvent::PitchBendSensitivity(semitones)));
+            }
+            if let Some(value) = state.bend {
+                send(ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value)));
+            }
+        }
+        events
+    }
+}
+
+// 带旁路的合成器：录音或 PCM 输出时发往合成器的每个事件都会复制一份给对应的线程
+struct TappedSynth {
+    synth: RealtimeSynth,
+    tap: Option<Sender<TapEvent>>,  // 录音
+    sink: Option<Sender<TapEvent>>, // PCM 输出
+    state: ChannelStateTracker,
+}
+
+impl TappedSynth {
+    fn send_event(&mut self, event: SynthEvent) {
+        self.state.observe(&event);
+        let now = Instant::now();
+        for tap in [&mut self.tap, &mut self.sink] {
+            if tap.as_ref().is_some_and(|t| t.send((now, event.clone())).is_err()) {
+                // 录音 / PCM 输出线程已退出
+                *tap = None;
+            }
+        }
+        self.synth.send_event(event);
+    }
+}
+
+// 等待中的事件：(到期时刻, 序号, 接收端口, 去掉时间戳的封包)，按到期时刻从早到晚出队
+type PendingEvent = Reverse<(Instant, u64, usize, Vec<u8>)>;
+
+// 带时间戳短消息的调度：把驱动的时间戳换算到本地时钟，延迟一个渲染窗口后按原有间隔送进合成器
+// 驱动打开时刻在本地时钟上的位置取观测到的最早值 (传输最快的封包)，因此接收抖动只会被吸收，不会让事件提前
+struct TimedScheduler {
+    delay: Duration,
+    clocks: HashMap<(usize, u8), (Instant, u64)>, // (接收端口, 驱动端口) -> (驱动打开的时刻, 上一个时间戳)
+    pending: BinaryHeap<PendingEvent>,
+    // (接收端口, 驱动端口) -> 该端口最后入队事件的 (到期时刻, 序号)，端口的事件全部出队后删除
+    // 同一端口后入队的事件到期不早于之前的，因此它总是该端口最后出队的一个
+    latest: HashMap<(usize, u8), (Instant, u64)>,
+    next_seq: u64,
+}
+
+impl TimedScheduler {
+    fn new(delay: Duration) -> Self {
+        Self { delay, clocks: HashMap::new(), pending: BinaryHeap::new(), latest: HashMap::new(), next_seq: 0 }
+    }
+
+    // 已经到期 (或无法换算) 的封包直接返回给调用方处理，否则加入等待队列
+    fn schedule(&mut self, socket_index: usize, packet: Vec<u8>, timestamp: u64, now: Instant) -> Option<Packet> {
+        let Some(opened_at) = now.checked_sub(Duration::from_micros(timestamp)) else {
+            return Some((socket_index, packet));
+        };
+        let clock = self.clocks.entry((socket_index, packet[0])).or_insert((opened_at, timestamp));
+        // 时间戳倒退说明宿主重新打开了设备，需要重新对时
+        if timestamp < clock.1 || opened_at < clock.0 {
+            clock.0 = opened_at;
+        }
+        clock.1 = timestamp;
+
+        // 不早于同一端口上还在等待的事件，保证按到达顺序处理
+        let due = clock.0 + Duration::from_micros(timestamp) + self.delay;
+        let latest = self.latest_due(socket_index, packet[0]);
+        if due <= now && latest.is_none() {
+            return Some((socket_index, packet));
+        }
+        // 序号保证同一时刻的事件按到达顺序处理
+        let due = latest.map_or(due, |latest| due.max(latest));
+        self.push(due, socket_index, packet[0], packet);
+        None
+    }
+
+    // 不带时间戳的封包 (SysEx、未带时间戳的短消息) 在同一端口还有等待中的事件时排到它们后面，否则直接返回
+    fn hold_back(&mut self, socket_index: usize, packet: Vec<u8>) -> Option<Packet> {
+        let Some(port) = packet_port(&packet) else {
+            return Some((socket_index, packet));
+        };
+        match self.latest_due(socket_index, port) {
+            Some(due) => {
+                self.push(due, socket_index, port, packet);
+                None
+            }
+            None => Some((socket_index, packet)),
+        }
+    }
+
+    fn push(&mut self, due: Instant, socket_index: usize, port: u8, packet: Vec<u8>) {
+        self.pending.push(Reverse((due, self.next_seq, socket_index, packet)));
+        self.latest.insert((socket_index, port), (due, self.next_seq));
+        self.next_seq += 1;
+    }
+
+    // 同一 (接收端口, 驱动端口) 上最晚到期的等待中事件
+    fn latest_due(&self, socket_index: usize, port: u8) -> Option<Instant> {
+        self.latest.get(&(socket_index, port)).map(|(due, _)| *due)
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Option<Packet> {
+        if self.pending.peek().is_none_or(|Reverse((due, ..))| *due > now) {
+            return None;
+        }
+        let Reverse((_, seq, socket_index, packet)) = self.pending.pop()?;
+        let key = (socket_index, packet_port(&packet).unwrap_or(packet[0]));
+        if self.latest.get(&key).is_some_and(|(_, latest_seq)| *latest_seq == seq) {
+            self.latest.remove(&key);
+        }
+        Some((socket_index, packet))
+    }
+
+    // 距离下一个事件到期的时间，不超过 max
+    fn wait_time(&self, now: Instant, max: Duration) -> Duration {
+        match self.pending.peek() {
+            Some(Reverse((due, ..))) => due.saturating_duration_since(now).min(max),
+            None => max,
+        }
+    }
+
+    // 紧急静音时丢弃该接收端口尚未到期的事件，port 为 None 时丢弃该接收端口的全部
+    fn discard(&mut self, socket_index: usize, port: Option<u8>) {
+        self.pending.retain(|Reverse((_, _, index, packet))| {
+            *index != socket_index || port.is_some_and(|p| packet_port(packet) != Some(p))
+        });
+        self.latest.retain(|(index, p), _| *index != socket_index || port.is_some_and(|port| *p != port));
+    }
+}
+
+// 短消息与 SysEx 封包所属的驱动端口，其他封包 (紧急静音等) 不参与排队
+fn packet_port(packet: &[u8]) -> Option<u8> {
+    match packet {
+        [port, _, ..] if *port < PACKET_SYSEX => Some(*port),
+        [PACKET_SYSEX, port, ..] => Some(*port),
+        _ => None,
+    }
+}
+
+// 输出设备断开后重连的最长间隔，每次失败后等待时间翻倍直到这个上限
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+// 连续重连失败这么多次 (约 8 分钟) 后放弃，直到默认输出设备再次变化 (例如插回设备) 才重新尝试
+const MAX_RECONNECT_ATTEMPTS: u32 = 20;
+
+// 音频流这么长时间没有任何进展时视为已出错
+const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+// 后台查询默认输出设备的间隔
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// MIDI 规范中通道音量 (CC 7) 的默认值
+const DEFAULT_CHANNEL_VOLUME: u8 = 100;
 
 pub struct AudioEngineHandle {
     pub is_running: Arc<AtomicBool>,
     pub thread_handle: Option<thread::JoinHandle<()>>,
+    pub config: RealtimeConfig, // 启动本引擎时使用的配置
+    pub soundfonts: Vec<SoundfontEntry>, // 最近一次交给引擎加载的音色库列表，用于显示哪些修改已经生效
+    pub voice_count: Arc<AtomicU64>, // 当前活跃复音数，由音频线程约每 100ms 更新一次
+    pub packet_rate: Arc<AtomicU64>, // 最近一秒从监听端口收到的封包数
+    pub packets_dropped: Arc<AtomicU64>, // 按序号检测到的 UDP 丢包总数
+    pub packets_received: Arc<AtomicU64>, // 从监听端口收到的封包总数
+    pub render_load: Arc<AtomicU32>, // xsynth 渲染线程的平均负载 (f32 位模式，1.0 为用满可用时间)
+    pub underruns: Arc<AtomicU64>, // 检测到缓冲区被读空 (会听到爆音) 的次数，每 100ms 最多计一次
+    pub key_activity: Arc<KeyActivity>, // 当前按住的键，由音频线程实时更新
+    command_tx: Sender<EngineCommand>,
+    packet_tx: Sender<Packet>, // 内置 MIDI 播放器等本地来源直接把封包送进处理循环
+    audio_params: Arc<Mutex<Option<AudioStreamParams>>>, // 音频线程打开设备后填入
+    loader: SoundfontLoader,
 }
 
 impl AudioEngineHandle {
+    /// 在后台线程重新加载音色库，加载完成后热替换到所有通道，音频流不中断
+    pub fn reload_soundfonts(&mut self, soundfonts: Vec<SoundfontEntry>) {
+        let Some(audio_params) = *self.audio_params.lock().unwrap() else {
+            return;
+        };
+        if let Ok(mut p) = self.loader.progress.lock() { *p = 0.05; }
+        self.soundfonts = soundfonts.clone();
+        self.loader.spawn(soundfonts, audio_params, self.config.get_soundfont_options());
+    }
+
+    /// xsynth 渲染线程的平均负载，1.0 表示渲染一个缓冲区用满了全部可用时间
+    pub fn renderer_load(&self) -> f32 {
+        f32::from_bits(self.render_load.load(Ordering::Relaxed))
+    }
+
+    /// 正在加载的音色库 (序号与文件名)，没有在加载时返回 None
+    pub fn loading_file(&self) -> Option<String> {
+        self.loader.current_file.lock().ok()?.clone()
+    }
+
+    /// 实时调整全局音量，同时更新 config 以免“保存并应用”误判为参数已修改
+    pub fn set_gain(&mut self, gain: f32) {
+        let gain = gain.clamp(0.0, MAX_GAIN);
+        self.config.gain = gain;
+        let _ = self.command_tx.send(EngineCommand::SetGain(gain));
+    }
+
+    /// 实时调整每个通道的复音层数上限 (0 为不限制)
+    /// 调低后只限制之后的新音符，已经在发声的音符不会被切断
+    pub fn set_layer_limit(&mut self, layer_limit: u32) {
+        self.config.layer_limit = layer_limit;
+        let _ = self.command_tx.send(EngineCommand::SetLayerLimit(self.config.get_layer_count()));
+    }
+
+    /// 实时开关触后处理，关闭时各通道的表情恢复为 MIDI 端发来的原值
+    pub fn set_aftertouch(&mut self, aftertouch: bool) {
+        self.config.aftertouch = aftertouch;
+        let _ = self.command_tx.send(EngineCommand::SetAftertouch(aftertouch));
+    }
+
+    /// 实时切换力度曲线，只影响之后的新音符
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurve) {
+        self.config.velocity_curve = curve;
+        let _ = self.command_tx.send(EngineCommand::SetVelocityCurve(curve));
+    }
+
+    /// 实时调整保留的音高范围，只影响之后的新音符
+    pub fn set_key_range(&mut self, min: u8, max: u8) {
+        self.config.ignore_key_min = min;
+        self.config.ignore_key_max = max;
+        let _ = self.command_tx.send(EngineCommand::SetKeyRange(min, max));
+    }
+
+    /// 实时开关 GM 打击乐通道，开启后各端口的通道 10 使用音色库的打击乐 (Bank 128)
+    pub fn set_gm_drums(&mut self, enabled: bool) {
+        self.config.gm_drums = enabled;
+        let _ = self.command_tx.send(EngineCommand::SetGmDrums(enabled));
+    }
+
+    /// 切换音色库效果，需要按新的选项重新加载音色库，音频流不中断
+    pub fn set_use_effects(&mut self, use_effects: bool, soundfonts: Vec<SoundfontEntry>) {
+        self.config.use_effects = use_effects;
+        self.reload_soundfonts(soundfonts);
+    }
+
+    /// 开始把实时输出录制到 WAV 文件
+    pub fn start_recording(&self, path: &Path) -> Result<Recorder, String> {
+        let Some(audio_params) = *self.audio_params.lock().unwrap() else {
+            return Err("引擎尚未就绪，无法录音。".to_string());
+        };
+        let (recorder, tap) = Recorder::start(path, audio_params, self.config.total_channels, self.config.get_thread_count())?;
+        let _ = self.command_tx.send(EngineCommand::StartRecording(tap));
+        Ok(recorder)
+    }
+
+    /// 停止录音，之后调用 Recorder::finish 等待文件写完
+    pub fn stop_recording(&self) {
+        let _ = self.command_tx.send(EngineCommand::StopRecording);
+    }
+
+    /// 开始把收到的 MIDI 事件录制为 MIDI 文件
+    pub fn start_midi_capture(&self, path: &Path) -> Result<MidiCapture, String> {
+        let (capture, capture_tx) = MidiCapture::start(path)?;
+        let _ = self.command_tx.send(EngineCommand::StartMidiCapture(capture_tx));
+        Ok(capture)
+    }
+
+    /// 停止 MIDI 录制，之后调用 MidiCapture::finish 等待文件写完
+    pub fn stop_midi_capture(&self) {
+        let _ = self.command_tx.send(EngineCommand::StopMidiCapture);
+    }
+
+    /// 在通道 0 上播放一个 C 大三和弦，约 1 秒后自动松开，用于确认能否正常出声
+    pub fn play_test_chord(&self) {
+        let packet_tx = self.packet_tx.clone();
+        thread::spawn(move || {
+            for key in TEST_CHORD {
+                let _ = packet_tx.send((0, vec![0, 0x90, key, 100]));
+            }
+            thread::sleep(TEST_CHORD_DURATION);
+            for key in TEST_CHORD {
+                let _ = packet_tx.send((0, vec![0, 0x80, key, 0]));
+            }
+        });
+    }
+
+    /// 切换到指定的 Bank / Program 并弹一个音试听，会改变端口 1 对应通道的音色
+    pub fn audition_preset(&self, bank: u16, program: u16) {
+        let (channel, key) = if bank == PERCUSSION_BANK {
+            (PERCUSSION_CHANNEL, AUDITION_DRUM_KEY)
+        } else {
+            (0, AUDITION_KEY)
+        };
+        let packet_tx = self.packet_tx.clone();
+        thread::spawn(move || {
+            // 打击乐通道固定使用 Bank 128，不需要发送 Bank Select
+            if bank != PERCUSSION_BANK {
+                let _ = packet_tx.send((0, vec![0, 0xB0 | channel, 0, bank.min(127) as u8]));
+            }
+            let _ = packet_tx.send((0, vec![0, 0xC0 | channel, program.min(127) as u8]));
+            let _ = packet_tx.send((0, vec![0, 0x90 | channel, key, 100]));
+            thread::sleep(TEST_CHORD_DURATION);
+            let _ = packet_tx.send((0, vec![0, 0x80 | channel, key, 0]));
+        });
+    }
+
+    /// 获取封包发送端，发送的封包与从监听端口收到的走同一条处理路径
+    pub fn packet_sender(&self) -> Sender<Packet> {
+        self.packet_tx.clone()
+    }
+
+    /// 压力测试需要的封包发送端与统计数据
+    pub fn benchmark_target(&self) -> BenchmarkTarget {
+        BenchmarkTarget {
+            config: self.config.clone(),
+            packet_tx: self.packet_tx.clone(),
+            voice_count: self.voice_count.clone(),
+            render_load: self.render_load.clone(),
+            underruns: self.underruns.clone(),
+        }
+    }
+
     pub fn stop(&mut self) {
         if self.is_running.load(Ordering::Relaxed) {
-            println!("正在停止音频引擎...");
+            log::info!("正在停止音频引擎...");
             self.is_running.store(false, Ordering::Relaxed);
             if let Some(handle) = self.thread_handle.take() {
                 let _ = handle.join(); // 等待线程安全退出
             }
-            println!("音频引擎已停止。");
+            log::info!("音频引擎已停止。");
         }
     }
 }
 
 pub fn spawn_audio_thread(
     config: RealtimeConfig,
-    soundfonts: Vec<PathBuf>,
+    soundfonts: Vec<SoundfontEntry>,
     load_progress: Arc<Mutex<f32>>, // 用于向 UI 上报加载进度
+    engine_message: Arc<Mutex<Option<String>>>, // 用于向 UI 上报警告信息
+    load_results: SoundfontLoadResults, // 用于向 UI 上报每个音色库的加载结果
 ) -> Result<AudioEngineHandle, String> {
+    // 单声道需要设备接受 1 个声道的流，否则 xsynth 只能按设备的声道数渲染，无法真正混成单声道
+    if config.audio_channels == AudioChannels::Mono && !default_output_supports_mono() {
+        return Err("输出设备不支持单声道输出，请在【实时设置】中改用立体声。".to_string());
+    }
     let is_running = Arc::new(AtomicBool::new(true));
     let is_running_clone = is_running.clone();
+    let (command_tx, command_rx) = mpsc::channel::<EngineCommand>();
+    let (packet_tx, packet_rx) = mpsc::channel::<Packet>();
+    let handle_packet_tx = packet_tx.clone();
+    let audio_params_shared = Arc::new(Mutex::new(None));
+    let audio_params_clone = audio_params_shared.clone();
+    let handle_config = config.clone();
+    let handle_soundfonts = soundfonts.clone();
+    let loader = SoundfontLoader {
+        command_tx: command_tx.clone(),
+        is_running: is_running.clone(),
+        progress: load_progress.clone(),
+        results: load_results,
+        current_file: Arc::new(Mutex::new(None)),
+        generation: Arc::new(AtomicU64::new(0)),
+    };
+    let thread_loader = loader.clone();
+    let voice_count = Arc::new(AtomicU64::new(0));
+    let voice_count_clone = voice_count.clone();
+    let packet_rate = Arc::new(AtomicU64::new(0));
+    let packet_rate_clone = packet_rate.clone();
+    let packets_dropped = Arc::new(AtomicU64::new(0));
+    let packets_dropped_clone = packets_dropped.clone();
+    let packets_received = Arc::new(AtomicU64::new(0));
+    let packets_received_clone = packets_received.clone();
+    let render_load = Arc::new(AtomicU32::new(0));
+    let render_load_clone = render_load.clone();
+    let underruns = Arc::new(AtomicU64::new(0));
+    let underruns_clone = underruns.clone();
+    let key_activity = Arc::new(KeyActivity::new());
+    let mut key_tracker = KeyTracker {
+        held: vec![0; config.total_channels as usize],
+        activity: key_activity.clone(),
+    };
 
-    // 尝试提前绑定 UDP 端口，如果被占用直接报错
-    let socket = UdpSocket::bind(format!("127.0.0.1:{}", config.udp_port))
-        .map_err(|e| format!("无法绑定 UDP 端口 {}: {}", config.udp_port, e))?;
-    // 设置超时，让 recv_from 不会永久阻塞，从而能响应停止信号
-    socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+    // 尝试提前绑定所有端口，只有全部失败时才直接报错
+    // 保留每个端口在配置中的序号，用于确定它对应的通道区间
+    let mut sockets = Vec::new();
+    let mut bind_errors = Vec::new();
+    for (socket_index, port) in config.udp_ports().into_iter().enumerate() {
+        let addr = format!("127.0.0.1:{}", port);
+        let listener = match config.transport {
+            // 设置超时 / 非阻塞，让接收线程不会永久阻塞，从而能响应停止信号
+            Transport::Udp => UdpSocket::bind(addr)
+                .and_then(|socket| socket.set_read_timeout(Some(Duration::from_millis(10))).map(|()| socket))
+                .map(|socket| {
+                    match set_recv_buffer_size(&socket, UDP_RECV_BUFFER_SIZE) {
+                        // 系统可能按上限截断 (Linux 的 net.core.rmem_max)，或为内部开销翻倍，记录实际大小
+                        Ok(granted) => log::info!("UDP 端口 {} 的接收缓冲区: 请求 {} 字节，实际 {} 字节", port, UDP_RECV_BUFFER_SIZE, granted),
+                        Err(e) => log::warn!("无法调整 UDP 端口 {} 的接收缓冲区: {}", port, e),
+                    }
+                    Listener::Udp(socket)
+                }),
+            Transport::Tcp => TcpListener::bind(addr)
+                .and_then(|listener| listener.set_nonblocking(true).map(|()| Listener::Tcp(listener))),
+        };
+        match listener {
+            Ok(listener) => sockets.push((socket_index, port, listener)),
+            Err(e) => bind_errors.push(format!("无法绑定 {} 端口 {}: {}", config.transport.label(), port, e)),
+        }
+    }
+    if sockets.is_empty() {
+        return Err(bind_errors.join("\n"));
+    }
+    if !bind_errors.is_empty() {
+        let bound: Vec<String> = sockets.iter().map(|(_, port, _)| port.to_string()).collect();
+        let msg = format!("警告：{}。已成功监听端口 {}。", bind_errors.join("；"), bound.join(", "));
+        log::warn!("{}", msg);
+        if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
+    }
 
     let thread_handle = thread::spawn(move || {
-        println!("=== 后台音频线程已启动 ===");
+        log::info!("=== 后台音频线程已启动 ===");
 
         // 初始化环境与参数，给予 5% 的基础进度
         if let Ok(mut p) = load_progress.lock() { *p = 0.05; }
@@ -56,107 +827,1597 @@ pub fn spawn_audio_thread(
         synth_cfg.render_window_ms = config.render_window_ms;
         synth_cfg.multithreading = config.get_thread_count();
         synth_cfg.format = SynthFormat::Custom { channels: config.total_channels };
-        
-        synth_cfg.ignore_range = config.ignore_velocity_min..=config.ignore_velocity_max;
+        // 忽略力度范围在接收循环中按原始力度判断，xsynth 收到的是经过力度曲线映射后的力度
+        synth_cfg.ignore_range = std::ops::RangeInclusive::new(1, 0);
+        synth_cfg.channel_init_options.fade_out_killing = config.fade_out_killing;
 
-        let mut synth = RealtimeSynth::open_with_default_output(synth_cfg);
+        // 默认输出设备由单独的线程查询，设备被拔掉或切换时由看门狗重新打开
+        let output_device = Arc::new(Mutex::new(default_output_name()));
+        let device_changed = Arc::new(AtomicBool::new(false));
+        let device_poller = spawn_device_poller(is_running_clone.clone(), output_device.clone(), device_changed.clone());
+        // 合成器只由本线程的处理循环持有，不需要加锁：
+        // send_event 只是把事件放进 xsynth 内部的通道，由各通道的渲染线程与音频回调消费
+        let (output, mono_output) = open_output(synth_cfg.clone(), config.sample_rate, config.audio_channels);
+        let mut synth = TappedSynth {
+            synth: output,
+            tap: None,
+            sink: None,
+            state: ChannelStateTracker::new(config.total_channels),
+        };
+        // 设备声称支持单声道但实际打开失败时只能按立体声输出
+        if config.audio_channels == AudioChannels::Mono && !mono_output {
+            let msg = "警告：输出设备无法以单声道打开，已改为立体声输出。".to_string();
+            log::warn!("{}", msg);
+            if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
+        }
 
         // 2. 加载音色库
-        let audio_params = AudioStreamParams::new(48000, ChannelCount::Stereo);
-        let mut sf_options = SoundfontInitOptions::default();
-        sf_options.interpolator = config.get_interpolator();
-
-        let mut loaded_sfs: Vec<Arc<dyn SoundfontBase>> = Vec::new();
+        // 音色库的采样会被重采样到这里给出的采样率，必须与输出设备实际的采样率一致，否则音高会出错
+        let device_rate = synth.synth.stream_params().sample_rate;
+        let sample_rate = if device_rate != config.sample_rate {
+            let msg = format!(
+                "警告：输出设备不支持设置的 {} Hz，已改用设备采样率 {} Hz。",
+                config.sample_rate, device_rate
+            );
+            log::warn!("{}", msg);
+            if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
+            device_rate
+        } else {
+            config.sample_rate
+        };
+        // 实际打开的声道数，音色库按它加载，录音与 PCM 输出也沿用
+        let output_channels = if mono_output { AudioChannels::Mono } else { AudioChannels::Stereo };
+        let audio_params = AudioStreamParams::new(sample_rate, output_channels.to_channel_count());
+        if let Ok(mut p) = audio_params_clone.lock() { *p = Some(audio_params); }
 
-        // 动态分配剩下的 90% 进度用于音色加载阶段
-        let total_sfs = soundfonts.len();
-        if total_sfs > 0 {
-            for (i, sf_path) in soundfonts.into_iter().enumerate() {
-                println!("正在加载音色库: {}", sf_path.display());
-                match SampleSoundfont::new(&sf_path, audio_params, sf_options.clone()) {
-                    Ok(sf) => loaded_sfs.push(Arc::new(sf)),
-                    Err(e) => eprintln!("加载音色库失败 {}: {:?}", sf_path.display(), e),
+        // PCM 输出在分配音色库之前接上旁路，之后的音色库、层数与音量都会同步给镜像合成器
+        if let Some(target) = &config.pcm_sink {
+            match pcm_sink::start(target, audio_params, config.total_channels, config.get_thread_count()) {
+                Ok(sink) => {
+                    log::info!("PCM 输出: {} ({} Hz，{} f32le)", target, sample_rate, if mono_output { "单声道" } else { "双声道" });
+                    synth.sink = Some(sink);
                 }
-                
-                // 每加载完一个更新一次进度
-                if let Ok(mut p) = load_progress.lock() { 
-                    *p = 0.05 + (0.90 * ((i + 1) as f32 / total_sfs as f32)); 
+                Err(e) => {
+                    let msg = format!("警告：{}", e);
+                    log::warn!("{}", msg);
+                    if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
                 }
             }
-        } else {
-            // 没有音色库的话跳过该阶段，直接拉到 95%
-            if let Ok(mut p) = load_progress.lock() { *p = 0.95; }
-        }
-
-        if !loaded_sfs.is_empty() {
-            println!("正在为 {} 个通道分配音色...", config.total_channels);
-            for ch in 0..config.total_channels {
-                let event = SynthEvent::Channel(
-                    ch,
-                    ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(loaded_sfs.clone())),
-                );
-                synth.send_event(event);
-            }
-        } else {
-            println!("警告：未加载任何有效音色库，将没有声音！");
         }
 
-        let synth_arc = Arc::new(Mutex::new(synth));
-        println!("引擎就绪！正在监听 UDP 端口 {}...", config.udp_port);
+        // 音色库在单独的线程中加载，完成后通过 SetSoundfonts 指令分配给所有通道
+        thread_loader.spawn(soundfonts, audio_params, config.get_soundfont_options());
+        // 记录当前的音色库与层数，开始录音时需要同步给录音用的合成器
+        let mut current_sfs: Vec<Arc<dyn SoundfontBase>> = Vec::new();
+        let mut current_layers = config.get_layer_count();
+        broadcast_channel_config(&mut synth, config.total_channels, ChannelConfigEvent::SetLayerCount(current_layers));
 
-        // 彻底就绪，进度条 100%
-        if let Ok(mut p) = load_progress.lock() { *p = 1.0; }
+        // 统计信息读取器不需要锁住合成器
+        let mut stats = synth.synth.get_stats();
+        let mut last_stats_update = Instant::now();
+        let mut last_rate_update = Instant::now();
+        let mut last_received = 0;
 
-        let mut buf = [0u8; 4];
+        let mut last_device_check = Instant::now();
+        let mut device_lost = false;
+        let mut reconnect_attempts: u32 = 0;
+        let mut next_reconnect = Instant::now();
+        // xsynth 没有累计读取量的计数器：缓冲区的采样数在回调读取与渲染时来回变化，可能恰好停在同一个值，
+        // 但渲染线程每渲染一块都会记录一次耗时，只有回调持续读取才会继续渲染。两者都长时间不变说明音频流已停止
+        let mut last_progress = (stats.buffer().samples(), stats.buffer().last_renderer_load().to_bits());
+        let mut last_progress_at = Instant::now();
 
-        // 3. UDP 监听循环
-        while is_running_clone.load(Ordering::Relaxed) {
-            if let Ok((size, _)) = socket.recv_from(&mut buf) {
-                if size == 4 {
-                    let port_index = buf[0];
-                    let status_byte = buf[1];
-                    let data1 = buf[2];
-                    let data2 = buf[3];
+        // xsynth 没有总音量接口，全局增益通过缩放每个通道的 CC 7 (音量) 实现
+        // 因此需要记录 MIDI 端发来的原始音量，增益变化时重新计算
+        let mut gain = config.gain;
+        let mut channel_volumes = vec![DEFAULT_CHANNEL_VOLUME; config.total_channels as usize];
+        let mut rpn = RpnTracker::new(config.total_channels);
+        let mut aftertouch = config.aftertouch;
+        let mut velocity_curve = config.velocity_curve;
+        let ignore_range = config.get_ignore_range();
+        let mut key_range = config.key_range();
+        let mut expression = ExpressionTracker::new(config.total_channels);
+        // 引擎按自定义通道数创建，xsynth 不会自动把通道 10 设为打击乐，需要逐个通道设置
+        let drum_channels = config.drum_channels();
+        let mut banks = BankTracker::new(config.total_channels);
+        if config.gm_drums {
+            banks.set_default_percussion(&drum_channels, true);
+            set_percussion_mode(&mut synth, &banks.percussion_channels(), true);
+        }
+        // MIDI 录制的旁路，只记录原始消息，与音频录音互不影响
+        let mut midi_capture: Option<Sender<CaptureEvent>> = None;
+        // MIDI 直通：直通通道的消息原样交给转发线程，不在本地合成
+        let thru_table = config.thru_table();
+        let midi_thru = config.midi_thru_port.as_deref()
+            .filter(|_| thru_table.contains(&true))
+            .map(|name| midithru::start(name, engine_message.clone()));
+        if gain != 1.0 {
+            for ch in 0..config.total_channels {
+                send_volume(&mut synth, ch, DEFAULT_CHANNEL_VOLUME, gain);
+            }
+        }
 
-                    if status_byte >= 0x80 && status_byte < 0xF0 {
-                        let original_channel = status_byte & 0x0F;
-                        let target_channel = (port_index as u32 * 16) + original_channel as u32;
+        let ports: Vec<String> = sockets.iter().map(|(_, port, _)| port.to_string()).collect();
+        log::info!("引擎就绪！正在监听 {} 端口 {}...", config.transport.label(), ports.join(", "));
 
-                        if target_channel >= config.total_channels {
-                            continue;
+        // 3. 每个端口一个接收线程，收到的封包连同端口序号转发给本线程统一处理
+        let receivers: Vec<_> = sockets.into_iter().map(|(socket_index, _, listener)| {
+            let packet_tx = packet_tx.clone();
+            let is_running = is_running_clone.clone();
+            let received = packets_received_clone.clone();
+            let dropped = packets_dropped_clone.clone();
+            let high_priority = config.high_priority;
+            thread::spawn(move || {
+                if high_priority && let Err(e) = raise_thread_priority() {
+                    log::warn!("无法提高端口 {} 接收线程的优先级: {}", socket_index, e);
+                }
+                match listener {
+                    Listener::Udp(socket) => {
+                        // 足够容纳单个 UDP 包的最大长度 (SysEx 长消息)
+                        let mut buf = vec![0u8; 65536];
+                        // 每个发送方 (驱动所在的宿主进程) 各自编号，按来源地址分别检查
+                        let mut next_seq = HashMap::new();
+                        'recv: while is_running.load(Ordering::Relaxed) {
+                            // 最多阻塞 10ms 等待第一个封包，之后切换为非阻塞，把系统缓冲区中积压的封包一次取完
+                            let Ok(first) = socket.recv_from(&mut buf) else { continue };
+                            let _ = socket.set_nonblocking(true);
+                            let mut next = Some(first);
+                            let mut drained = 0;
+                            while let Some((size, src)) = next {
+                                received.fetch_add(1, Ordering::Relaxed);
+                                let packet = match &buf[..size] {
+                                    [PACKET_SEQUENCED, seq, inner @ ..] => {
+                                        dropped.fetch_add(count_dropped(&mut next_seq, src, *seq), Ordering::Relaxed);
+                                        inner
+                                    }
+                                    packet => packet,
+                                };
+                                if packet_tx.send((socket_index, packet.to_vec())).is_err() {
+                                    break 'recv;
+                                }
+                                drained += 1;
+                                // WouldBlock (已取完) 或其他错误都结束本轮
+                                next = if drained < MAX_PACKET_BATCH { socket.recv_from(&mut buf).ok() } else { None };
+                            }
+                            let _ = socket.set_nonblocking(false);
                         }
-
-                        if let Ok(mut s) = synth_arc.lock() {
-                            let channel_event = match status_byte & 0xF0 {
-                                0x90 if data2 > 0 => {
-                                    Some(ChannelEvent::Audio(ChannelAudioEvent::NoteOn {
-                                        key: data1,
-                                        vel: data2,
-                                    }))
+                    }
+                    Listener::Tcp(listener) => {
+                        // 每个连接一个读取线程，停止信号到来后各自在超时内退出
+                        while is_running.load(Ordering::Relaxed) {
+                            match listener.accept() {
+                                Ok((stream, _)) => {
+                                    let packet_tx = packet_tx.clone();
+                                    let is_running = is_running.clone();
+                                    let received = received.clone();
+                                    thread::spawn(move || {
+                                        // 连接的读取线程不继承优先级，失败已在上面报告过
+                                        if high_priority {
+                                            let _ = raise_thread_priority();
+                                        }
+                                        read_tcp_packets(stream, socket_index, packet_tx, is_running, received)
+                                    });
                                 }
-                                0x80 | 0x90 => {
-                                    Some(ChannelEvent::Audio(ChannelAudioEvent::NoteOff {
-                                        key: data1,
-                                    }))
+                                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(10)),
+                                Err(e) => {
+                                    log::warn!("接受 TCP 连接失败: {}", e);
+                                    thread::sleep(Duration::from_millis(10));
                                 }
-                                _ => None,
-                            };
-
-                            if let Some(ce) = channel_event {
-                                let event = SynthEvent::Channel(target_channel, ce);
-                                s.send_event(event);
                             }
                         }
                     }
                 }
+            })
+        }).collect();
+
+        // 多个端口时平分全部通道，每个端口 (实例) 使用互不重叠的一段
+        let channels_per_socket = config.total_channels / config.udp_ports().len() as u32;
+
+        let routes = config.channel_route_table();
+        let mut timed = TimedScheduler::new(Duration::from_secs_f64(config.render_window_ms / 1000.0));
+
+        // 处理线程被抢占时事件会成批延迟，负载高时可能断声
+        if config.high_priority {
+            match raise_thread_priority() {
+                Ok(()) => log::info!("已提高音频处理线程的优先级"),
+                Err(e) => log::warn!("无法提高音频处理线程的优先级: {}", e),
             }
         }
 
-        println!("=== 后台音频线程正在退出 ===");
-    });
+        // 4. 封包处理循环
+        while is_running_clone.load(Ordering::Relaxed) {
+            // 定期上报复音数与渲染负载，频率足够让界面显示平滑
+            if last_stats_update.elapsed() >= Duration::from_millis(100) {
+                voice_count_clone.store(stats.voice_count(), Ordering::Relaxed);
+                let buffer = stats.buffer();
+                render_load_clone.store((buffer.average_renderer_load() as f32).to_bits(), Ordering::Relaxed);
+                // 上次音频回调读取后缓冲区为负，说明渲染没跟上，回调只能输出不完整的数据
+                if buffer.last_samples_after_read() < 0 {
+                    underruns_clone.fetch_add(1, Ordering::Relaxed);
+                }
+                let progress = (buffer.samples(), buffer.last_renderer_load().to_bits());
+                if progress != last_progress {
+                    last_progress = progress;
+                    last_progress_at = Instant::now();
+                }
+                last_stats_update = Instant::now();
+            }
+            if last_rate_update.elapsed() >= Duration::from_secs(1) {
+                let received = packets_received_clone.load(Ordering::Relaxed);
+                let rate = (received - last_received) as f64 / last_rate_update.elapsed().as_secs_f64();
+                packet_rate_clone.store(rate.round() as u64, Ordering::Relaxed);
+                last_received = received;
+                last_rate_update = Instant::now();
+            }
 
-    Ok(AudioEngineHandle {
-        is_running,
-        thread_handle: Some(thread_handle),
-    })
-}
\ No newline at end of file
+            // 输出设备看门狗：xsynth 不会上报音频流错误，这里每秒检查一次默认输出设备是否变化、音频流是否停止
+            if last_device_check.elapsed() >= Duration::from_secs(1) {
+                last_device_check = Instant::now();
+                let current = output_device.lock().unwrap().clone();
+                // 一旦变化就视为旧音频流已失效，即使之后插回同一个设备也需要重新打开；放弃重连后也据此重新开始
+                if device_changed.swap(false, Ordering::Relaxed) {
+                    device_lost = true;
+                    reconnect_attempts = 0;
+                    next_reconnect = Instant::now();
+                }
+                if last_progress_at.elapsed() >= STALL_TIMEOUT && !device_lost {
+                    log::warn!("输出设备 {} 的音频流已停止", current.clone().unwrap_or_default());
+                    device_lost = true;
+                }
+                if device_lost && reconnect_attempts < MAX_RECONNECT_ATTEMPTS && Instant::now() >= next_reconnect {
+                    let reopened = if current.is_some() {
+                        // 旧的音频流已经失效，按原配置重新打开，打开失败 (例如设备正在切换) 会 panic
+                        // 音色库按原来的声道数加载，新设备必须沿用相同的声道数
+                        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            open_output(synth_cfg.clone(), audio_params.sample_rate, output_channels)
+                        })).ok().filter(|(_, mono)| *mono == mono_output).map(|(new_synth, _)| new_synth)
+                    } else {
+                        None
+                    };
+
+                    let msg = match reopened {
+                        Some(new_synth) => {
+                            let new_rate = new_synth.stream_params().sample_rate;
+                            synth.synth = new_synth;
+                            // 恢复音色库、层数与打击乐通道，再重放每个通道的音色、控制器与弯音
+                            // 旁路 (录音 / PCM 输出) 的镜像合成器状态没有丢失，只发给新的合成器
+                            assign_soundfonts(&mut synth, config.total_channels, current_sfs.clone());
+                            broadcast_channel_config(&mut synth, config.total_channels, ChannelConfigEvent::SetLayerCount(current_layers));
+                            set_percussion_mode(&mut synth, &banks.percussion_channels(), true);
+                            for event in synth.state.events() {
+                                synth.synth.send_event(event);
+                            }
+                            stats = synth.synth.get_stats();
+                            last_progress_at = Instant::now();
+                            device_lost = false;
+                            reconnect_attempts = 0;
+                            if new_rate != audio_params.sample_rate {
+                                format!("已切换到输出设备 {}，但其采样率为 {} Hz，请重启引擎以免音高错误。", current.unwrap_or_default(), new_rate)
+                            } else {
+                                format!("已重新连接到输出设备 {}。", current.unwrap_or_default())
+                            }
+                        }
+                        None if reconnect_attempts + 1 >= MAX_RECONNECT_ATTEMPTS => {
+                            reconnect_attempts += 1;
+                            format!("输出设备重新连接失败 {} 次，已停止重试。更换或插回输出设备后会自动再次尝试。", reconnect_attempts)
+                        }
+                        None => {
+                            reconnect_attempts += 1;
+                            let interval = Duration::from_secs(1u64 << reconnect_attempts.min(16)).min(MAX_RECONNECT_INTERVAL);
+                            next_reconnect = Instant::now() + interval;
+                            format!(
+                                "输出设备已断开，正在尝试重新连接 (第 {} 次，{} 秒后重试)...",
+                                reconnect_attempts,
+                                interval.as_secs()
+                            )
+                        }
+                    };
+                    log::warn!("{}", msg);
+                    if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
+                }
+            }
+
+            // 先处理 UI 发来的指令
+            while let Ok(cmd) = command_rx.try_recv() {
+                match cmd {
+                    EngineCommand::SetSoundfonts(sfs) => {
+                        current_sfs = sfs.clone();
+                        assign_soundfonts(&mut synth, config.total_channels, sfs);
+                    }
+                    EngineCommand::SetLayerLimit(layers) => {
+                        current_layers = layers;
+                        broadcast_channel_config(&mut synth, config.total_channels, ChannelConfigEvent::SetLayerCount(layers));
+                    }
+                    EngineCommand::SetVelocityCurve(curve) => velocity_curve = curve,
+                    EngineCommand::SetKeyRange(min, max) => key_range = min..=max,
+                    EngineCommand::SetGmDrums(enabled) => {
+                        // 切换前先停掉这些通道上的声音，以免旧音色的音符挂住
+                        for &ch in &drum_channels {
+                            broadcast_audio(&mut synth, ch..ch + 1, ChannelAudioEvent::AllNotesKilled);
+                        }
+                        banks.set_default_percussion(&drum_channels, enabled);
+                        set_percussion_mode(&mut synth, &drum_channels, enabled);
+                    }
+                    EngineCommand::SetAftertouch(enabled) => {
+                        aftertouch = enabled;
+                        if !enabled {
+                            for ch in 0..config.total_channels {
+                                if expression.pressure[ch as usize].take().is_some() {
+                                    send_expression(&mut synth, ch, expression.scaled(ch));
+                                }
+                            }
+                        }
+                    }
+                    EngineCommand::StartRecording(tap) => {
+                        // 先把当前的音色库与层数同步给录音用的合成器，再接上旁路
+                        let now = Instant::now();
+                        for ch in 0..config.total_channels {
+                            let sfs = ChannelConfigEvent::SetSoundfonts(current_sfs.clone());
+                            let _ = tap.send((now, SynthEvent::Channel(ch, ChannelEvent::Config(sfs))));
+                            let layers = ChannelConfigEvent::SetLayerCount(current_layers);
+                            let _ = tap.send((now, SynthEvent::Channel(ch, ChannelEvent::Config(layers))));
+                        }
+                        synth.tap = Some(tap);
+                        set_percussion_mode(&mut synth, &banks.percussion_channels(), true);
+                        // 再重放各通道当前的音色、控制器 (音量与表情为缩放后的值) 与弯音，录音从与实时输出相同的状态开始
+                        // 力度过滤在接收循环中完成，旁路收到的音符与实时合成器一致
+                        if let Some(tap) = &synth.tap {
+                            for event in synth.state.events() {
+                                let _ = tap.send((now, event));
+                            }
+                        }
+                    }
+                    EngineCommand::StopRecording => {
+                        synth.tap = None;
+                    }
+                    EngineCommand::StartMidiCapture(tx) => midi_capture = Some(tx),
+                    EngineCommand::StopMidiCapture => midi_capture = None,
+                    EngineCommand::SetGain(g) => {
+                        gain = g;
+                        for (ch, &vol) in channel_volumes.iter().enumerate() {
+                            send_volume(&mut synth, ch as u32, vol, gain);
+                        }
+                    }
+                }
+            }
+
+            // 收集一批封包：先取已到期的带时间戳事件，没有时等待新的封包 (等待时间不超过下一个事件到期)，
+            // 再把通道中已经排队的封包一并取出，批量处理。标记为 true 的是已经排过队、到期出队的封包
+            let mut batch = Vec::new();
+            while batch.len() < MAX_PACKET_BATCH
+                && let Some(packet) = timed.pop_due(Instant::now())
+            {
+                batch.push((true, packet));
+            }
+            if batch.is_empty() {
+                match packet_rx.recv_timeout(timed.wait_time(Instant::now(), Duration::from_millis(10))) {
+                    Ok(packet) => batch.push((false, packet)),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            while batch.len() < MAX_PACKET_BATCH
+                && let Ok(packet) = packet_rx.try_recv()
+            {
+                batch.push((false, packet));
+            }
+
+            for (released, (socket_index, mut buf)) in batch {
+                if buf.first() == Some(&PACKET_UMP) {
+                    match ump_to_short(&buf[1..]) {
+                        Some(short) => buf = short,
+                        None => continue,
+                    }
+                }
+
+                // 带时间戳的短消息：[端口ID, 状态字节, 数据..., 时间戳]，去掉时间戳后排队，到期后按普通短消息处理
+                if (2 + TIMESTAMP_BYTES..=4 + TIMESTAMP_BYTES).contains(&buf.len()) && buf[0] < PACKET_SYSEX {
+                    let split = buf.len() - TIMESTAMP_BYTES;
+                    let mut timestamp = [0u8; TIMESTAMP_BYTES];
+                    timestamp.copy_from_slice(&buf[split..]);
+                    buf.truncate(split);
+                    match timed.schedule(socket_index, buf, u64::from_le_bytes(timestamp), Instant::now()) {
+                        Some((_, packet)) => buf = packet,
+                        None => continue,
+                    }
+                } else if !released {
+                    // SysEx 等不带时间戳的封包不能越过同一端口上还在等待的事件
+                    match timed.hold_back(socket_index, buf) {
+                        Some((_, packet)) => buf = packet,
+                        None => continue,
+                    }
+                }
+                let size = buf.len();
+
+                // 该端口对应的通道区间，以及其中某个虚拟端口 (port_index) 的 16 个通道 (从通道偏移开始)
+                let socket_first = socket_index as u32 * channels_per_socket;
+                let socket_end = socket_first + channels_per_socket;
+                let port_channels = |port_index: u8| {
+                    let first = socket_first + config.channel_base_offset + port_index as u32 * 16;
+                    first.min(socket_end)..(first + 16).min(socket_end)
+                };
+
+                if (2..=4).contains(&size) && buf[0] < PACKET_SYSEX {
+                    // 短消息：[端口ID, 状态字节, 数据1, 数据2]，只有 1 个数据字节的消息 (音色切换、通道触后) 不带数据2
+                    let port_index = buf[0];
+                    capture_event(&mut midi_capture, socket_index, port_index, &buf[1..]);
+                    let status_byte = buf[1];
+                    let data1 = if size > 2 { buf[2] } else { 0 };
+                    let data2 = if size > 3 { buf[3] } else { 0 };
+
+                    if status_byte >= 0x80 && status_byte < 0xF0 {
+                        let original_channel = status_byte & 0x0F;
+                        if let Some(thru) = &midi_thru
+                            && thru_table.get(port_index as usize * 16 + original_channel as usize) == Some(&true)
+                        {
+                            let _ = thru.send(buf[1..size].to_vec());
+                            continue;
+                        }
+                        // 重映射表中有该 (端口, 通道) 时发往指定的通道，否则按 通道偏移 + 端口ID * 16 + 通道
+                        let target_channel = match routes.get(port_index as usize * 16 + original_channel as usize).copied().flatten() {
+                            Some(target) => socket_first.saturating_add(target),
+                            None => socket_first + config.channel_base_offset + (port_index as u32 * 16) + original_channel as u32,
+                        };
+
+                        if target_channel >= socket_end {
+                            continue;
+                        }
+
+                        let channel_event = match status_byte & 0xF0 {
+                            0x90 if data2 > 0 && (ignore_range.contains(&data2) || !key_range.contains(&data1)) => None,
+                            0x90 if data2 > 0 => {
+                                key_tracker.note_on(target_channel, data1);
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::NoteOn {
+                                    key: data1,
+                                    vel: velocity_curve.apply(data2),
+                                }))
+                            }
+                            0x80 | 0x90 => {
+                                key_tracker.note_off(target_channel, data1);
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::NoteOff {
+                                    key: data1,
+                                }))
+                            }
+                            // CC 120 (All Sound Off)：立即切断所有发声
+                            0xB0 if data1 == 120 => {
+                                key_tracker.release_channels(target_channel..target_channel + 1);
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::AllNotesKilled))
+                            }
+                            // CC 121 (Reset All Controllers)：音量会被复位，随后按增益重新下发
+                            // 按 RP-015，RPN 选择恢复为空值，但弯音范围保持不变，需要重新下发
+                            0xB0 if data1 == 121 => {
+                                synth.send_event(SynthEvent::Channel(target_channel, ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
+                                channel_volumes[target_channel as usize] = DEFAULT_CHANNEL_VOLUME;
+                                send_volume(&mut synth, target_channel, DEFAULT_CHANNEL_VOLUME, gain);
+                                rpn.selected[target_channel as usize] = RPN_NULL;
+                                send_bend_range(&mut synth, target_channel, rpn.semitones(target_channel));
+                                expression.reset_channels(target_channel..target_channel + 1);
+                                None
+                            }
+                            // CC 123 (All Notes Off)：释放所有按键，保留释放尾音
+                            0xB0 if data1 == 123 => {
+                                key_tracker.release_channels(target_channel..target_channel + 1);
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::AllNotesOff))
+                            }
+                            // CC 7 (通道音量)：记录原始值，按全局增益缩放后再发送
+                            0xB0 if data1 == 7 => {
+                                channel_volumes[target_channel as usize] = data2;
+                                send_volume(&mut synth, target_channel, data2, gain);
+                                None
+                            }
+                            // CC 11 (表情)：记录原始值，按触后缩放后再发送
+                            0xB0 if data1 == 11 => {
+                                expression.expression[target_channel as usize] = data2;
+                                send_expression(&mut synth, target_channel, expression.scaled(target_channel));
+                                None
+                            }
+                            // CC 64 (延音踏板)：xsynth 通道内部维护踏板状态，踩下时收到的 NoteOff 会延后到松开踏板时释放
+                            0xB0 if data1 == 64 => Some(ChannelEvent::Audio(sustain_pedal(data2))),
+                            // Bank Select (CC 0 / CC 32)：只记录，到音色切换时才生效
+                            0xB0 if data1 == 0 || data1 == 32 => {
+                                banks.control(target_channel, data1, data2);
+                                None
+                            }
+                            // 控制器 (CC)：声像、表情等由 xsynth 通道内部解析
+                            // RPN 0 (弯音范围) 的数据输入在这里解析，并显式下发给 xsynth
+                            0xB0 => {
+                                if let Some(semitones) = rpn.control(target_channel, data1, data2) {
+                                    send_bend_range(&mut synth, target_channel, semitones);
+                                }
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::Control(
+                                    ControlEvent::Raw(data1, data2),
+                                )))
+                            }
+                            // 音色切换只有 1 个数据字节，位于 data1；先按记录的 Bank Select 切换 Bank 与打击乐模式
+                            0xC0 => {
+                                let (bank, percussion) = banks.resolve(target_channel);
+                                if banks.percussion[target_channel as usize] != percussion {
+                                    banks.percussion[target_channel as usize] = percussion;
+                                    set_percussion_mode(&mut synth, &[target_channel], percussion);
+                                }
+                                synth.send_event(SynthEvent::Channel(
+                                    target_channel,
+                                    ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::Raw(0, bank))),
+                                ));
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::ProgramChange(data1)))
+                            }
+                            // 弯音：14 位数值 (LSB 在 data1)，中心点 8192，归一化到 -1.0..1.0
+                            0xE0 => {
+                                let value = ((data2 as i32) << 7 | data1 as i32) - 8192;
+                                Some(ChannelEvent::Audio(ChannelAudioEvent::Control(
+                                    ControlEvent::PitchBendValue(value as f32 / 8192.0),
+                                )))
+                            }
+                            // 通道触后 / 复音触后：按压力缩放表情
+                            0xD0 | 0xA0 => {
+                                if let Some(value) = expression.aftertouch(aftertouch, target_channel, status_byte, data1, data2) {
+                                    send_expression(&mut synth, target_channel, value);
+                                }
+                                None
+                            }
+                            _ => None,
+                        };
+
+                        if let Some(ce) = channel_event {
+                            let event = SynthEvent::Channel(target_channel, ce);
+                            synth.send_event(event);
+                        }
+                    }
+                } else if size == 2 && buf[0] == PACKET_PANIC {
+                    // 紧急静音：[PACKET_PANIC, 端口ID]，端口ID 为 PANIC_ALL_PORTS 时作用于该监听端口的全部通道
+                    // GUI 的“全部静音”向每个监听端口各发一个 (见 panic_packets)
+                    let port_index = buf[1];
+                    let channels = if port_index == PANIC_ALL_PORTS {
+                        socket_first..socket_end
+                    } else {
+                        port_channels(port_index)
+                    };
+                    timed.discard(socket_index, (port_index != PANIC_ALL_PORTS).then_some(port_index));
+                    // 直通通道上的外部音源也一起静音 (All Sound Off + All Notes Off)
+                    if let Some(thru) = &midi_thru {
+                        for (i, _) in thru_table.iter().enumerate().filter(|&(_, &on)| on) {
+                            if port_index == PANIC_ALL_PORTS || i / 16 == port_index as usize {
+                                let status = 0xB0 | (i % 16) as u8;
+                                let _ = thru.send(vec![status, 120, 0]);
+                                let _ = thru.send(vec![status, 123, 0]);
+                            }
+                        }
+                    }
+
+                    panic_channels(&mut synth, channels.clone());
+                    key_tracker.release_channels(channels.clone());
+                    rpn.reset_channels(channels.clone());
+                    expression.reset_channels(channels.clone());
+                    for ch in channels {
+                        channel_volumes[ch as usize] = DEFAULT_CHANNEL_VOLUME;
+                        send_volume(&mut synth, ch, DEFAULT_CHANNEL_VOLUME, gain);
+                    }
+                } else if size >= 4 && buf[0] == PACKET_SYSEX {
+                    // 长消息：[PACKET_SYSEX, 端口ID, 长度(小端 u16), SysEx 原始字节...]
+                    let channels = port_channels(buf[1]);
+                    let len = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+                    if size < 4 + len {
+                        continue;
+                    }
+                    capture_event(&mut midi_capture, socket_index, buf[1], &buf[4..4 + len]);
+                    // 端口上有直通通道时 SysEx (例如 GS Reset) 也转发一份给外部音源，本地照常处理
+                    if let Some(thru) = &midi_thru
+                        && thru_table.chunks(16).nth(buf[1] as usize).is_some_and(|port| port.contains(&true))
+                    {
+                        let _ = thru.send(buf[4..4 + len].to_vec());
+                    }
+
+                    if handle_sysex(&mut synth, &buf[4..4 + len], channels.clone()) {
+                        key_tracker.release_channels(channels.clone());
+                        rpn.reset_channels(channels.clone());
+                        // 系统复位后 Bank 回到 0，打击乐模式恢复为默认 (GM 打击乐通道)
+                        for ch in banks.reset_channels(channels.clone()) {
+                            set_percussion_mode(&mut synth, &[ch], banks.percussion[ch as usize]);
+                        }
+                        expression.reset_channels(channels.clone());
+                        for ch in channels {
+                            channel_volumes[ch as usize] = DEFAULT_CHANNEL_VOLUME;
+                            send_volume(&mut synth, ch, DEFAULT_CHANNEL_VOLUME, gain);
+                        }
+                    }
+                }
+            }
+        }
+
+        // 停止前释放所有音符并等待尾音淡出，避免音频流关闭时把正在发声的音符直接切断产生爆音
+        if config.stop_fade_ms > 0 {
+            broadcast_audio(&mut synth, 0..config.total_channels, sustain_off());
+            broadcast_audio(&mut synth, 0..config.total_channels, ChannelAudioEvent::AllNotesOff);
+            thread::sleep(Duration::from_millis(config.stop_fade_ms as u64));
+        }
+
+        for receiver in receivers {
+            let _ = receiver.join();
+        }
+        let _ = device_poller.join();
+        log::info!("=== 后台音频线程正在退出 ===");
+    });
+
+    Ok(AudioEngineHandle {
+        is_running,
+        thread_handle: Some(thread_handle),
+        config: handle_config,
+        soundfonts: handle_soundfonts,
+        voice_count,
+        packet_rate,
+        packets_dropped,
+        packets_received,
+        render_load,
+        underruns,
+        key_activity,
+        command_tx,
+        packet_tx: handle_packet_tx,
+        audio_params: audio_params_shared,
+        loader,
+    })
+}
+
+// 已加载的音色库，重启引擎或重新加载时直接复用，避免修改无关设置后重新读取几个 GB 的文件
+// 只保留最近一次加载用到的音色库；用户手动停止引擎时清空，释放内存
+static SOUNDFONT_CACHE: Mutex<Vec<CachedSoundfont>> = Mutex::new(Vec::new());
+
+struct CachedSoundfont {
+    key: SoundfontCacheKey,
+    soundfont: Arc<dyn SoundfontBase>,
+    info: SoundfontInfo,
+    memory: Option<u64>, // 估算的采样内存，无法估算 (SF3) 时为 None
+}
+
+// 已加载音色库的内存占用估算
+pub struct SoundfontMemory {
+    pub loaded: usize,    // 已加载的音色库数 (列表中重复的只算一次)
+    pub bytes: u64,       // 估算的采样内存
+    pub unestimated: usize, // 无法估算的音色库数，未计入 bytes
+}
+
+// 文件路径与修改时间，加上影响加载结果的全部选项
+// SFZ 只检查 .sfz 文件本身，单独替换采样文件时需要手动停止引擎再启动
+#[derive(PartialEq)]
+struct SoundfontCacheKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    options: String, // SoundfontInitOptions 没有实现 PartialEq，用包含全部字段的 Debug 输出比较
+}
+
+impl SoundfontCacheKey {
+    fn new(path: &Path, audio_params: AudioStreamParams, options: &SoundfontInitOptions) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+            options: format!("{:?} {:?}", audio_params, options),
+        }
+    }
+}
+
+/// 清空音色库缓存，释放已停止的引擎占用的内存
+pub fn clear_soundfont_cache() {
+    SOUNDFONT_CACHE.lock().unwrap().clear();
+}
+
+/// 当前加载的音色库 (即缓存中的) 占用的内存估算
+pub fn soundfont_memory() -> SoundfontMemory {
+    let cache = SOUNDFONT_CACHE.lock().unwrap();
+    SoundfontMemory {
+        loaded: cache.len(),
+        bytes: cache.iter().filter_map(|c| c.memory).sum(),
+        unestimated: cache.iter().filter(|c| c.memory.is_none()).count(),
+    }
+}
+
+/// 释放只被缓存持有、已没有通道在使用的音色库，返回释放的数量与估算的内存
+/// 正常情况下每次加载完成后缓存只保留正在使用的音色库，这里用于加载被中止等情况后的兜底清理
+pub fn release_unused_soundfonts() -> (usize, u64) {
+    let mut cache = SOUNDFONT_CACHE.lock().unwrap();
+    let (unused, used): (Vec<_>, Vec<_>) = cache.drain(..).partition(|c| Arc::strong_count(&c.soundfont) == 1);
+    *cache = used;
+    let bytes = unused.iter().filter_map(|c| c.memory).sum();
+    for c in &unused {
+        log::info!("已卸载未使用的音色库: {}", c.key.path.display());
+    }
+    (unused.len(), bytes)
+}
+
+// 音色库加载器：在单独的线程中依次加载，完成后通过指令热替换到所有通道
+// 加载期间音频线程照常运行，停止引擎时在两个文件之间中止，stop() 不会被大型音色库卡住
+#[derive(Clone)]
+struct SoundfontLoader {
+    command_tx: Sender<EngineCommand>,
+    is_running: Arc<AtomicBool>,
+    progress: Arc<Mutex<f32>>,               // 加载进度，1.0 为完成
+    results: SoundfontLoadResults,           // 每个音色库的加载结果
+    current_file: Arc<Mutex<Option<String>>>, // 正在加载的文件，例如 "(2/5) piano.sf2"
+    generation: Arc<AtomicU64>,              // 每次开始加载 +1，连续调整顺序时只采用最后一次的结果
+}
+
+impl SoundfontLoader {
+    fn spawn(&self, soundfonts: Vec<SoundfontEntry>, audio_params: AudioStreamParams, sf_options: SoundfontInitOptions) {
+        let loader = self.clone();
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        thread::spawn(move || {
+            let loaded = loader.load(&soundfonts, audio_params, sf_options, generation);
+            // 加载期间又开始了新的加载，丢弃本次结果，以免较早的列表覆盖较新的
+            if loader.generation.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            if let Ok(mut f) = loader.current_file.lock() { *f = None; }
+            // 取消时不再改动进度，以免影响随后启动的新引擎
+            match loaded {
+                Some(loaded_sfs) => {
+                    let _ = loader.command_tx.send(EngineCommand::SetSoundfonts(loaded_sfs));
+                    if let Ok(mut p) = loader.progress.lock() { *p = 1.0; }
+                }
+                None => log::info!("引擎已停止，取消加载音色库"),
+            }
+        });
+    }
+
+    // 依次加载已启用的音色库，加载进度占 5% ~ 95% 区间；引擎停止时返回 None
+    fn load(
+        &self,
+        soundfonts: &[SoundfontEntry],
+        audio_params: AudioStreamParams,
+        sf_options: SoundfontInitOptions,
+        generation: u64,
+    ) -> Option<Vec<Arc<dyn SoundfontBase>>> {
+        let mut loaded_sfs: Vec<Arc<dyn SoundfontBase>> = Vec::new();
+        let mut results = Vec::new();
+        let mut cache = Vec::new();
+
+        let enabled: Vec<&SoundfontEntry> = soundfonts.iter().filter(|entry| entry.enabled).collect();
+        let total_sfs = enabled.len();
+        for (i, entry) in enabled.into_iter().enumerate() {
+            // 单个音色库的加载无法中断，只能在每个文件前后检查
+            if !self.is_running.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let sf_path = &entry.path;
+            log::info!("正在加载音色库: {}", sf_path.display());
+            if let Ok(mut f) = self.current_file.lock() {
+                let name = sf_path.file_name().unwrap_or_default().to_string_lossy();
+                *f = Some(format!("({}/{}) {}", i + 1, total_sfs, name));
+            }
+
+            // 每个音色库可以只加载指定的 Bank / Preset，并使用各自的包络曲线与插值算法
+            let options = SoundfontInitOptions {
+                bank: entry.bank,
+                preset: entry.preset,
+                vol_envelope_options: entry.envelope.to_options(),
+                interpolator: entry.interpolator.map_or(sf_options.interpolator, InterpolatorWrapper::to_xsynth),
+                ..sf_options
+            };
+            // 文件与选项都没变时复用上次加载的结果，列表中重复的音色库也只加载一次
+            let key = SoundfontCacheKey::new(sf_path, audio_params, &options);
+            let cached = cache.iter()
+                .chain(SOUNDFONT_CACHE.lock().unwrap().iter())
+                .find(|c: &&CachedSoundfont| c.key == key)
+                .map(|c| (c.soundfont.clone(), c.info.clone()));
+            let loaded = match cached {
+                Some(hit) => {
+                    log::info!("音色库未修改，复用已加载的数据: {}", sf_path.display());
+                    Ok(hit)
+                }
+                None => SampleSoundfont::new(sf_path, audio_params, options)
+                    .map(|sf| (Arc::new(sf) as Arc<dyn SoundfontBase>, sfinfo::read_info(sf_path))),
+            };
+            match loaded {
+                Ok((sf, info)) => {
+                    log::info!("已加载音色库: {} ({})", sf_path.display(), info.summary());
+                    if info.format == SoundfontFormat::Sf3 {
+                        log::info!("{} 为 SF3 压缩音色库，采样已解码", sf_path.display());
+                    }
+                    if info.missing_samples > 0 {
+                        log::warn!("{} 中有 {} 个采样文件不存在，这些音符将没有声音", sf_path.display(), info.missing_samples);
+                    }
+                    // 列表中重复的音色库只记一条，否则按引用计数判断是否在用时会算错
+                    if !cache.iter().any(|c: &CachedSoundfont| c.key == key) {
+                        let memory = info.estimated_memory(audio_params.sample_rate);
+                        cache.push(CachedSoundfont { key, soundfont: sf.clone(), info: info.clone(), memory });
+                    }
+                    loaded_sfs.push(sf);
+                    results.push((sf_path.clone(), Ok(info)));
+                }
+                Err(e) => {
+                    log::error!("加载音色库失败 {}: {:?}", sf_path.display(), e);
+                    // SF3 的采样经过 Ogg Vorbis 压缩，xsynth 读取失败时给出明确的原因，而不是笼统的解析错误
+                    let message = if sfinfo::read_info(sf_path).format == SoundfontFormat::Sf3 {
+                        "SF3 压缩音色库暂不支持，请解压为 SF2 (例如用 Polyphone 另存为 .sf2)".to_string()
+                    } else {
+                        format!("{:?}", e)
+                    };
+                    results.push((sf_path.clone(), Err(message)));
+                }
+            }
+            if !self.is_running.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            // 每加载完一个更新一次进度
+            if let Ok(mut p) = self.progress.lock() {
+                *p = 0.05 + (0.90 * ((i + 1) as f32 / total_sfs as f32));
+            }
+        }
+
+        // 已经开始了更新的加载时不改动结果与缓存，以免较早的列表把新加载的音色库挤出缓存、把已移除的留在内存里
+        if self.generation.load(Ordering::SeqCst) == generation {
+            if let Ok(mut r) = self.results.lock() {
+                *r = results;
+            }
+            // 不再使用的音色库随旧缓存一起释放
+            *SOUNDFONT_CACHE.lock().unwrap() = cache;
+        }
+        Some(loaded_sfs)
+    }
+}
+
+// 当前默认输出设备的名称，没有可用设备时返回 None
+pub fn default_output_name() -> Option<String> {
+    cpal::default_host().default_output_device().and_then(|d| d.name().ok())
+}
+
+// 在单独的线程中定期查询默认输出设备 (枚举设备较慢，不放在封包处理循环中)，变化时写入 current 并置位 changed
+fn spawn_device_poller(is_running: Arc<AtomicBool>, current: Arc<Mutex<Option<String>>>, changed: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_poll = Instant::now();
+        while is_running.load(Ordering::Relaxed) {
+            // 短间隔检查停止信号，引擎停止时不必等满一个查询周期
+            thread::sleep(Duration::from_millis(100));
+            if last_poll.elapsed() < DEVICE_POLL_INTERVAL {
+                continue;
+            }
+            last_poll = Instant::now();
+            let name = default_output_name();
+            let mut current = current.lock().unwrap();
+            if *current != name {
+                *current = name;
+                changed.store(true, Ordering::Relaxed);
+            }
+        }
+    })
+}
+
+// 默认输出设备的配置中是否有单声道，没有设备时交给打开时处理
+fn default_output_supports_mono() -> bool {
+    let Some(device) = cpal::default_host().default_output_device() else {
+        return true;
+    };
+    device.supported_output_configs().is_ok_and(|mut configs| configs.any(|c| c.channels() == 1))
+}
+
+// 打开默认输出设备，设备支持时使用设置的采样率，否则沿用设备的默认采样率 (由调用方提示)
+// 要求单声道时改为 1 个声道打开，由系统混到各个扬声器
+// 设备不接受单声道流 (打开时 panic) 时退回默认声道数，返回值的第二项为是否以单声道打开
+fn open_output(synth_cfg: XSynthRealtimeConfig, sample_rate: u32, channels: AudioChannels) -> (RealtimeSynth, bool) {
+    let Some(device) = cpal::default_host().default_output_device() else {
+        return (RealtimeSynth::open_with_default_output(synth_cfg), false);
+    };
+    let Ok(default) = device.default_output_config() else {
+        return (RealtimeSynth::open_with_default_output(synth_cfg), false);
+    };
+    if channels == AudioChannels::Mono {
+        let mono = output_stream_config(&device, &default, 1, sample_rate);
+        let cfg = synth_cfg.clone();
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| RealtimeSynth::open(cfg, &device, mono))) {
+            Ok(synth) => {
+                log::info!("已以单声道打开输出设备");
+                return (synth, true);
+            }
+            Err(_) => log::warn!("输出设备无法以单声道打开，改用默认声道数"),
+        }
+    }
+    let stream_config = output_stream_config(&device, &default, default.channels(), sample_rate);
+    (RealtimeSynth::open(synth_cfg, &device, stream_config), false)
+}
+
+// 在设备支持的配置中查找声道数与采样格式相同、采样率范围包含 sample_rate 的一项
+// 找不到时按默认配置的采样率与格式构造
+fn output_stream_config(
+    device: &cpal::Device,
+    default: &cpal::SupportedStreamConfig,
+    channels: u16,
+    sample_rate: u32,
+) -> cpal::SupportedStreamConfig {
+    let rate = cpal::SampleRate(sample_rate);
+    let supported = device.supported_output_configs().ok().and_then(|mut configs| {
+        configs.find(|c| {
+            c.channels() == channels
+                && c.sample_format() == default.sample_format()
+                && c.min_sample_rate() <= rate
+                && rate <= c.max_sample_rate()
+        })
+    });
+    match supported {
+        Some(range) => range.with_sample_rate(rate),
+        None => {
+            log::info!("输出设备不支持 {} Hz ({} 声道)，使用默认采样率 {} Hz", sample_rate, channels, default.sample_rate().0);
+            cpal::SupportedStreamConfig::new(channels, default.sample_rate(), *default.buffer_size(), default.sample_format())
+        }
+    }
+}
+
+// 将音色库分配给所有通道
+fn assign_soundfonts(synth: &mut TappedSynth, total_channels: u32, sfs: Vec<Arc<dyn SoundfontBase>>) {
+    if sfs.is_empty() {
+        log::warn!("警告：未加载任何有效音色库，将没有声音！");
+    } else {
+        log::info!("正在为 {} 个通道分配音色...", total_channels);
+    }
+    // 即使为空也要下发，以便热重载时能清掉旧的音色
+    broadcast_channel_config(synth, total_channels, ChannelConfigEvent::SetSoundfonts(sfs));
+}
+
+// 向全部通道下发同一个通道配置 (音色库、层数上限等)
+// 新增可实时调整的通道配置时也通过这里下发，避免漏掉部分通道
+fn broadcast_channel_config(synth: &mut TappedSynth, total_channels: u32, event: ChannelConfigEvent) {
+    let event = ChannelEvent::Config(event);
+    for ch in 0..total_channels {
+        synth.send_event(SynthEvent::Channel(ch, event.clone()));
+    }
+}
+
+// 把原始消息交给 MIDI 录制，录制线程已退出时关闭旁路
+fn capture_event(capture: &mut Option<Sender<CaptureEvent>>, socket_index: usize, port_index: u8, bytes: &[u8]) {
+    let track = socket_index as u32 * 16 + port_index as u32;
+    if capture.as_ref().is_some_and(|tx| tx.send((Instant::now(), track, bytes.to_vec())).is_err()) {
+        *capture = None;
+    }
+}
+
+// 设置打击乐模式，打击乐通道使用音色库中 Bank 128 的预设
+fn set_percussion_mode(synth: &mut TappedSynth, channels: &[u32], enabled: bool) {
+    let event = ChannelEvent::Config(ChannelConfigEvent::SetPercussionMode(enabled));
+    for &ch in channels {
+        synth.send_event(SynthEvent::Channel(ch, event.clone()));
+    }
+}
+
+// 向指定范围内的每个通道发送同一个音频事件 (静音、复位等)
+fn broadcast_audio(synth: &mut TappedSynth, channels: Range<u32>, event: ChannelAudioEvent) {
+    let event = ChannelEvent::Audio(event);
+    for ch in channels {
+        synth.send_event(SynthEvent::Channel(ch, event.clone()));
+    }
+}
+
+// 处理 SysEx：xsynth 没有 SysEx 接口，这里只识别 GM/GS/XG 复位并作用到该端口的 16 个通道
+// 发生复位时返回 true
+fn handle_sysex(synth: &mut TappedSynth, data: &[u8], channels: Range<u32>) -> bool {
+    // GS/XG 的设备 ID 字节可能不同，比较时忽略
+    let is_reset = data == SYSEX_GM_ON
+        || data == SYSEX_GM2_ON
+        || (data.len() == SYSEX_GS_RESET.len() && data[..2] == SYSEX_GS_RESET[..2] && data[3..] == SYSEX_GS_RESET[3..])
+        || (data.len() == SYSEX_XG_ON.len() && data[..2] == SYSEX_XG_ON[..2] && data[3..] == SYSEX_XG_ON[3..]);
+
+    if !is_reset {
+        return false;
+    }
+
+    broadcast_audio(synth, channels.clone(), sustain_off());
+    broadcast_audio(synth, channels.clone(), ChannelAudioEvent::AllNotesOff);
+    broadcast_audio(synth, channels, ChannelAudioEvent::ResetControl);
+    true
+}
+
+// 松开延音踏板，否则被踏板保持的音符在复位后仍会继续发声
+fn sustain_off() -> ChannelAudioEvent {
+    ChannelAudioEvent::Control(ControlEvent::Raw(64, 0))
+}
+
+// 延音踏板 (CC 64)：>= 64 为踩下，< 64 为松开
+// 先按 MIDI 规范的阈值归一化为 0 / 127，避免半踏板值被误判
+fn sustain_pedal(value: u8) -> ChannelAudioEvent {
+    ChannelAudioEvent::Control(ControlEvent::Raw(64, if value >= 64 { 127 } else { 0 }))
+}
+
+// 按全局增益缩放通道音量后发送，结果限制在 MIDI 的 0 ~ 127 范围内
+fn send_volume(synth: &mut TappedSynth, channel: u32, volume: u8, gain: f32) {
+    let scaled = (volume as f32 * gain).round().clamp(0.0, 127.0) as u8;
+    synth.send_event(SynthEvent::Channel(
+        channel,
+        ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::Raw(7, scaled))),
+    ));
+}
+
+// 发送通道表情 (CC 11)
+fn send_expression(synth: &mut TappedSynth, channel: u32, value: u8) {
+    synth.send_event(expression_event(channel, value));
+}
+
+fn expression_event(channel: u32, value: u8) -> SynthEvent {
+    SynthEvent::Channel(channel, ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::Raw(11, value))))
+}
+
+// 设置通道的弯音范围 (半音)
+fn send_bend_range(synth: &mut TappedSynth, channel: u32, semitones: f32) {
+    synth.send_event(SynthEvent::Channel(
+        channel,
+        ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::PitchBendSensitivity(semitones))),
+    ));
+}
+
+// 切断指定通道的所有发声并复位控制器 (防止延音踏板等导致的挂音)
+fn panic_channels(synth: &mut TappedSynth, channels: Range<u32>) {
+    broadcast_audio(synth, channels.clone(), sustain_off());
+    broadcast_audio(synth, channels.clone(), ChannelAudioEvent::AllNotesKilled);
+    broadcast_audio(synth, channels, ChannelAudioEvent::ResetControl);
+}
+
+// 设置 UDP 套接字的系统接收缓冲区 (SO_RCVBUF)，标准库没有提供该接口
+// 返回设置后系统实际分配的大小
+#[cfg(windows)]
+fn set_recv_buffer_size(socket: &UdpSocket, size: i32) -> std::io::Result<i32> {
+    use std::os::windows::io::AsRawSocket;
+    use windows_sys::Win32::Networking::WinSock::{getsockopt, setsockopt, SOL_SOCKET, SO_RCVBUF};
+
+    let raw = socket.as_raw_socket() as usize;
+    let mut granted: i32 = 0;
+    let mut len = std::mem::size_of::<i32>() as i32;
+    let ok = unsafe {
+        setsockopt(raw, SOL_SOCKET, SO_RCVBUF, &size as *const i32 as *const u8, std::mem::size_of::<i32>() as i32) == 0
+            && getsockopt(raw, SOL_SOCKET, SO_RCVBUF, &mut granted as *mut i32 as *mut u8, &mut len) == 0
+    };
+    if ok { Ok(granted) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(unix)]
+fn set_recv_buffer_size(socket: &UdpSocket, size: i32) -> std::io::Result<i32> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = socket.as_raw_fd();
+    let mut granted: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ok = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const i32 as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        ) == 0
+            && libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, &mut granted as *mut libc::c_int as *mut libc::c_void, &mut len) == 0
+    };
+    if ok { Ok(granted) } else { Err(std::io::Error::last_os_error()) }
+}
+
+// 提高当前线程的调度优先级
+// Windows 使用 THREAD_PRIORITY_TIME_CRITICAL；其他平台先尝试实时调度 (SCHED_RR，通常需要 root 或 rtprio 权限)，
+// 不行再降低 nice 值 (Linux 上只作用于当前线程)
+#[cfg(windows)]
+fn raise_thread_priority() -> std::io::Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_TIME_CRITICAL};
+
+    let ok = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) };
+    if ok != 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+#[cfg(unix)]
+fn raise_thread_priority() -> std::io::Result<()> {
+    unsafe {
+        let param = libc::sched_param { sched_priority: libc::sched_get_priority_min(libc::SCHED_RR) };
+        if libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_RR, &param) == 0 {
+            return Ok(());
+        }
+        if libc::setpriority(libc::PRIO_PROCESS, 0, -10) == 0 {
+            return Ok(());
+        }
+    }
+    Err(std::io::Error::last_os_error())
+}
+
+#[cfg(not(any(windows, unix)))]
+fn raise_thread_priority() -> std::io::Result<()> {
+    Err(std::io::Error::from(ErrorKind::Unsupported))
+}
+
+// 把一个 UMP 通道消息转换为短消息 [端口ID, 状态字节, 数据1, 数据2]，不支持的消息返回 None
+// 支持 MIDI 1.0 通道消息 (类型 0x2，32 位) 与 MIDI 2.0 通道消息 (类型 0x4，64 位)
+// xsynth 只接受 7 位力度，MIDI 2.0 的 16 位力度、32 位控制器值按比例缩小；音色切换附带的 Bank 暂不处理
+fn ump_to_short(ump: &[u8]) -> Option<Vec<u8>> {
+    let word0 = u32::from_be_bytes(ump.get(..4)?.try_into().ok()?);
+    let [head, status, data1, data2] = word0.to_be_bytes();
+    let port = head & 0x0F;
+    match head >> 4 {
+        0x2 if (0x80..0xF0).contains(&status) => Some(vec![port, status, data1, data2]),
+        0x4 => {
+            let word1 = u32::from_be_bytes(ump.get(4..8)?.try_into().ok()?);
+            let to_7bit = |value: u32| (value >> 25) as u8;
+            match status & 0xF0 {
+                // MIDI 2.0 中力度为 0 的 NoteOn 仍是 NoteOn，不能当作 NoteOff，最小取 1
+                0x90 => Some(vec![port, status, data1, to_7bit(word1).max(1)]),
+                0x80 | 0xA0 | 0xB0 => Some(vec![port, status, data1, to_7bit(word1)]),
+                0xC0 => Some(vec![port, status, (word1 >> 24) as u8 & 0x7F]),
+                0xD0 => Some(vec![port, status, to_7bit(word1)]),
+                // 弯音：32 位缩小为 14 位，拆成 LSB / MSB 两个 7 位数据字节
+                0xE0 => {
+                    let value = word1 >> 18;
+                    Some(vec![port, status, (value & 0x7F) as u8, (value >> 7) as u8])
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// 根据来源的下一个期望序号统计丢失的封包数
+// 序号相差 128 以上视为乱序或重复的旧封包，不计入丢包，也不更新期望序号
+fn count_dropped(next_seq: &mut HashMap<SocketAddr, u8>, src: SocketAddr, seq: u8) -> u64 {
+    let gap = next_seq.get(&src).map(|&expected| seq.wrapping_sub(expected));
+    if gap.is_some_and(|gap| gap >= 128) {
+        return 0;
+    }
+    next_seq.insert(src, seq.wrapping_add(1));
+    gap.unwrap_or(0) as u64
+}
+
+// 从一个 TCP 连接中读取带长度前缀的封包：[长度(小端 u16), 封包原始字节...]
+// TCP 保证送达，驱动不会给封包编号
+fn read_tcp_packets(
+    mut stream: TcpStream,
+    socket_index: usize,
+    packet_tx: Sender<Packet>,
+    is_running: Arc<AtomicBool>,
+    received: Arc<AtomicU64>,
+) {
+    // 监听端口是非阻塞的，连接需要改回阻塞并设置超时，从而能响应停止信号
+    if stream.set_nonblocking(false).is_err()
+        || stream.set_read_timeout(Some(Duration::from_millis(10))).is_err()
+    {
+        return;
+    }
+    let _ = stream.set_nodelay(true);
+
+    let mut pending = Vec::new();
+    let mut buf = vec![0u8; 65536];
+    while is_running.load(Ordering::Relaxed) {
+        match stream.read(&mut buf) {
+            Ok(0) => break, // 对端关闭连接
+            Ok(size) => pending.extend_from_slice(&buf[..size]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+            Err(_) => break,
+        }
+
+        // 取出所有已经完整到达的封包，不完整的留到下次读取
+        let mut offset = 0;
+        while pending.len() - offset >= 2 {
+            let len = u16::from_le_bytes([pending[offset], pending[offset + 1]]) as usize;
+            if pending.len() - offset - 2 < len {
+                break;
+            }
+            let packet = pending[offset + 2..offset + 2 + len].to_vec();
+            received.fetch_add(1, Ordering::Relaxed);
+            if packet_tx.send((socket_index, packet)).is_err() {
+                return;
+            }
+            offset += 2 + len;
+        }
+        pending.drain(..offset);
+    }
+}
+
+/// 端口是否已被其他程序占用：试绑定一次，失败原因为地址已占用时返回 true
+pub fn port_in_use(transport: Transport, port: u16) -> bool {
+    let addr = ("127.0.0.1", port);
+    let result = match transport {
+        Transport::Udp => UdpSocket::bind(addr).map(drop),
+        Transport::Tcp => TcpListener::bind(addr).map(drop),
+    };
+    matches!(result, Err(e) if e.kind() == std::io::ErrorKind::AddrInUse)
+}
+
+/// 从 start 开始向后寻找能连续绑定 count 个端口的起始端口，最多尝试 MAX_PORT_PROBES 次
+pub fn find_free_ports(transport: Transport, start: u16, count: u16) -> Option<u16> {
+    let count = count.max(1);
+    (0..MAX_PORT_PROBES)
+        .filter_map(|i| start.checked_add(i))
+        .find(|&first| (0..count).all(|i| first.checked_add(i).is_some_and(|port| !port_in_use(transport, port))))
+}
+
+// 通过本地 UDP / TCP 向引擎的每个监听端口发送紧急静音封包，作用于全部通道
+pub fn send_panic(config: &RealtimeConfig) -> std::io::Result<()> {
+    // 每个监听端口只负责自己的通道区间，逐个发送；部分端口没有绑定成功时只要有一个送达即可
+    let mut delivered = false;
+    let mut first_error = None;
+    for port in config.udp_ports() {
+        match send_local_packets(config, port, &[panic_packet()]) {
+            Ok(()) => delivered = true,
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) if !delivered => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// 作用于收到它的监听端口全部通道的紧急静音封包
+fn panic_packet() -> Vec<u8> {
+    vec![PACKET_PANIC, PANIC_ALL_PORTS]
+}
+
+/// 作用于全部通道的紧急静音封包 (每个监听端口一个)，可直接送入 packet_sender
+pub fn panic_packets(socket_count: usize) -> impl Iterator<Item = Packet> {
+    (0..socket_count.max(1)).map(|socket_index| (socket_index, panic_packet()))
+}
+
+// 像驱动一样通过本机回环把封包发到引擎的指定监听端口
+fn send_local_packets(config: &RealtimeConfig, port: u16, packets: &[Vec<u8>]) -> std::io::Result<()> {
+    match config.transport {
+        Transport::Udp => {
+            let socket = UdpSocket::bind("127.0.0.1:0")?;
+            for packet in packets {
+                socket.send_to(packet, ("127.0.0.1", port))?;
+            }
+        }
+        Transport::Tcp => {
+            let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+            let mut stream = TcpStream::connect_timeout(&addr, Duration::from_millis(500))?;
+            let mut framed = Vec::new();
+            for packet in packets {
+                framed.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+                framed.extend_from_slice(packet);
+            }
+            stream.write_all(&framed)?;
+        }
+    }
+    Ok(())
+}
+
+/// 回环自检：从临时 Socket 向监听端口发送测试和弦，确认引擎收到了封包并产生了复音
+/// 会阻塞约 SELF_TEST_WAIT，需要在后台线程中调用
+pub fn loopback_self_test(config: &RealtimeConfig, packets_received: &AtomicU64, voice_count: &AtomicU64) -> Result<String, String> {
+    let note_on: Vec<Vec<u8>> = TEST_CHORD.iter().map(|&key| vec![0, 0x90, key, 100]).collect();
+    let note_off: Vec<Vec<u8>> = TEST_CHORD.iter().map(|&key| vec![0, 0x80, key, 0]).collect();
+
+    let before = packets_received.load(Ordering::Relaxed);
+    send_local_packets(config, config.udp_port, &note_on).map_err(|e| format!("无法向 {} 端口 {} 发送封包: {}", config.transport.label(), config.udp_port, e))?;
+
+    let started = Instant::now();
+    let mut peak_voices = 0;
+    while started.elapsed() < SELF_TEST_WAIT {
+        peak_voices = peak_voices.max(voice_count.load(Ordering::Relaxed));
+        thread::sleep(Duration::from_millis(20));
+    }
+    let received = packets_received.load(Ordering::Relaxed) - before;
+    let _ = send_local_packets(config, config.udp_port, &note_off);
+
+    if received < note_on.len() as u64 {
+        return Err(format!(
+            "引擎只收到 {} / {} 个封包，请检查端口 {} 是否被防火墙或其他程序占用",
+            received, note_on.len(), config.udp_port
+        ));
+    }
+    if peak_voices == 0 {
+        return Err("引擎收到了封包但没有发声，请检查音色库是否加载成功、通道 1 的音色是否存在".to_string());
+    }
+    Ok(format!("自检通过：引擎收到 {} 个封包，产生了 {} 个复音", received, peak_voices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 带时间戳的事件等待期间，同一端口不带时间戳的封包排在它们之后；全部出队后恢复直接处理
+    #[test]
+    fn timed_scheduler_keeps_port_order() {
+        let delay = Duration::from_millis(10);
+        let mut timed = TimedScheduler::new(delay);
+        let start = Instant::now();
+        assert!(timed.schedule(0, vec![0, 0x90, 60, 100], 1_000, start).is_none());
+        assert!(timed.schedule(0, vec![0, 0x80, 60, 0], 2_000, start).is_none());
+        assert!(timed.hold_back(0, vec![0, 0xB0, 7, 100]).is_none());
+        // 其他驱动端口与其他接收端口不受影响
+        assert!(timed.hold_back(0, vec![1, 0xB0, 7, 100]).is_some());
+        assert!(timed.hold_back(1, vec![0, 0xB0, 7, 100]).is_some());
+
+        let later = start + delay * 2;
+        let order: Vec<u8> = std::iter::from_fn(|| timed.pop_due(later)).map(|(_, packet)| packet[1]).collect();
+        assert_eq!(order, [0x90, 0x80, 0xB0]);
+        assert!(timed.latest.is_empty());
+        assert!(timed.hold_back(0, vec![0, 0xB0, 7, 100]).is_some());
+    }
+
+    // 紧急静音丢弃等待中的事件后，该端口的封包不再排队
+    #[test]
+    fn timed_scheduler_discard_clears_port() {
+        let mut timed = TimedScheduler::new(Duration::from_millis(10));
+        let now = Instant::now();
+        assert!(timed.schedule(0, vec![2, 0x90, 60, 100], 1_000, now).is_none());
+        timed.discard(0, Some(2));
+        assert_eq!(timed.wait_time(now, Duration::from_millis(10)), Duration::from_millis(10));
+        assert!(timed.hold_back(0, vec![2, 0xB0, 7, 100]).is_some());
+    }
+
+    // 接收缓冲区确实被修改：Linux 会把请求值翻倍，其他系统按请求值分配
+    #[test]
+    fn recv_buffer_size_reports_granted_size() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let small = set_recv_buffer_size(&socket, 16 * 1024).unwrap();
+        let large = set_recv_buffer_size(&socket, 64 * 1024).unwrap();
+        assert!(small >= 16 * 1024);
+        assert!(large >= 64 * 1024 && large > small);
+    }
+
+    #[test]
+    fn bank_msb_120_selects_percussion() {
+        let mut banks = BankTracker::new(16);
+        banks.control(0, 0, GM2_RHYTHM_BANK);
+        // 打击乐模式下 xsynth 固定使用音色库的 Bank 128，传给它的 Bank 为 0
+        assert_eq!(banks.resolve(0), (0, true));
+        assert_eq!(banks.resolve(1), (0, false));
+    }
+
+    #[test]
+    fn bank_msb_121_uses_lsb_variation() {
+        let mut banks = BankTracker::new(16);
+        banks.control(0, 0, GM2_MELODY_BANK);
+        banks.control(0, 32, 3);
+        assert_eq!(banks.resolve(0), (3, false));
+        // GS / XG 的 MSB 直接作为 Bank，LSB 忽略
+        banks.control(0, 0, 8);
+        assert_eq!(banks.resolve(0), (8, false));
+    }
+
+    #[test]
+    fn bank_reset_restores_default_percussion() {
+        let mut banks = BankTracker::new(16);
+        banks.set_default_percussion(&[9], true);
+        banks.control(9, 0, GM2_MELODY_BANK);
+        banks.control(0, 0, GM2_RHYTHM_BANK);
+        banks.percussion[9] = false;
+        banks.percussion[0] = true;
+
+        let mut changed = banks.reset_channels(0..16);
+        changed.sort_unstable();
+        assert_eq!(changed, vec![0, 9]);
+        assert_eq!(banks.resolve(0), (0, false));
+        assert_eq!(banks.resolve(9), (0, true));
+        assert_eq!(banks.percussion_channels(), vec![9]);
+    }
+
+    #[test]
+    fn rpn_0_sets_bend_range() {
+        let mut rpn = RpnTracker::new(16);
+        assert_eq!(rpn.control(0, 6, 12), None, "未选择 RPN 时数据输入无效");
+        assert_eq!(rpn.control(0, 101, 0), None);
+        assert_eq!(rpn.control(0, 100, 0), None);
+        assert_eq!(rpn.control(0, 6, 12), Some(12.0));
+        assert_eq!(rpn.control(0, 38, 50), Some(12.5));
+        // 选择 NRPN 后 RPN 0 的数据输入不再生效
+        assert_eq!(rpn.control(0, 99, 1), None);
+        assert_eq!(rpn.control(0, 6, 2), None);
+        assert_eq!(rpn.semitones(0), 12.5);
+        assert_eq!(rpn.semitones(1), 2.0);
+    }
+
+    #[test]
+    fn full_scale_bend_uses_rpn_range() {
+        use xsynth_core::channel::{ChannelInitOptions, VoiceChannel};
+        use xsynth_core::{AudioPipe, ChannelCount};
+
+        let mut rpn = RpnTracker::new(16);
+        rpn.control(0, 101, 0);
+        rpn.control(0, 100, 0);
+        let semitones = rpn.control(0, 6, 12).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("xxsynth-bend-{}", std::process::id()));
+        let params = AudioStreamParams::new(48000, ChannelCount::Mono);
+        let sf = SampleSoundfont::new(looping_sine_sfz(&dir), params, SoundfontInitOptions::default()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut channel = VoiceChannel::new(ChannelInitOptions::default(), params, None);
+        channel.process_event(ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(vec![Arc::new(sf)])));
+        channel.process_event(ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::PitchBendSensitivity(semitones))));
+        // 弯音轮推到最高 (16383)，换算方式与接收循环一致
+        let value = ((127i32 << 7 | 127) - 8192) as f32 / 8192.0;
+        channel.process_event(ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value))));
+        channel.process_event(ChannelEvent::Audio(ChannelAudioEvent::NoteOn { key: 69, vel: 100 }));
+
+        // 跳过起音，再按上升沿过零次数估算 1 秒内的频率
+        let mut buf = vec![0.0f32; 4800];
+        channel.read_samples(&mut buf);
+        let mut buf = vec![0.0f32; 48000];
+        channel.read_samples(&mut buf);
+        let crossings = buf.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0).count();
+        // 440 Hz 升高 12 个半音 (满幅略低于 1.0) 约为 880 Hz
+        assert!((875..=882).contains(&crossings), "{} Hz", crossings);
+    }
+
+    #[test]
+    fn aftertouch_scales_expression() {
+        let mut expression = ExpressionTracker::new(16);
+        // 从未收到触后时按原值发送
+        expression.expression[0] = 100;
+        assert_eq!(expression.scaled(0), 100);
+        expression.pressure[0] = Some(127);
+        assert_eq!(expression.scaled(0), 100);
+        expression.pressure[0] = Some(64);
+        assert_eq!(expression.scaled(0), 50);
+        expression.pressure[0] = Some(0);
+        assert_eq!(expression.scaled(0), 0);
+        assert_eq!(expression.scaled(1), DEFAULT_EXPRESSION);
+
+        expression.reset_channels(0..1);
+        assert_eq!(expression.scaled(0), DEFAULT_EXPRESSION);
+    }
+
+    #[test]
+    fn aftertouch_packets_route_to_expression() {
+        let mut expression = ExpressionTracker::new(16);
+        // 关闭触后时忽略，压力不被记录
+        assert_eq!(expression.aftertouch(false, 0, 0xD0, 64, 0), None);
+        assert_eq!(expression.aftertouch(false, 0, 0xA0, 60, 64), None);
+        assert_eq!(expression.pressure[0], None);
+        // 通道触后的压力在 data1，复音触后在 data2 (data1 为按键)，都作用于整个通道
+        assert_eq!(expression.aftertouch(true, 0, 0xD3, 64, 0), Some(64));
+        assert_eq!(expression.aftertouch(true, 1, 0xA3, 60, 32), Some(32));
+        assert_eq!(expression.pressure[1], Some(32));
+        // 其他消息不是触后
+        assert_eq!(expression.aftertouch(true, 2, 0xB0, 11, 64), None);
+    }
+
+    // 持续的音符在收到通道触后 / 复音触后后音量下降，关闭触后时不变
+    #[test]
+    fn aftertouch_lowers_sustained_note_level() {
+        use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, ThreadCount};
+        use xsynth_core::{AudioPipe, ChannelCount};
+
+        let dir = std::env::temp_dir().join(format!("xxsynth-aftertouch-{}", std::process::id()));
+        let params = AudioStreamParams::new(48000, ChannelCount::Stereo);
+        let sf = SampleSoundfont::new(looping_sine_sfz(&dir), params, SoundfontInitOptions::default()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut group = ChannelGroup::new(ChannelGroupConfig {
+            channel_init_options: Default::default(),
+            format: SynthFormat::Custom { channels: 2 },
+            audio_params: params,
+            parallelism: ParallelismOptions { channel: ThreadCount::None, key: ThreadCount::None },
+        });
+        let sf: Arc<dyn SoundfontBase> = Arc::new(sf);
+        group.send_event(SynthEvent::AllChannels(ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(vec![sf]))));
+        let mut buf = vec![0.0f32; 48000 / 5 * 2];
+        let mut rms = |group: &mut ChannelGroup| {
+            buf.fill(0.0);
+            group.read_samples(&mut buf);
+            (buf.iter().map(|s| s * s).sum::<f32>() / buf.len() as f32).sqrt()
+        };
+        let mut expression = ExpressionTracker::new(2);
+        let mut packet = |group: &mut ChannelGroup, enabled: bool, channel: u32, status: u8, data1: u8, data2: u8| {
+            if let Some(value) = expression.aftertouch(enabled, channel, status, data1, data2) {
+                group.send_event(expression_event(channel, value));
+            }
+        };
+
+        for channel in 0..2 {
+            group.send_event(SynthEvent::Channel(channel, ChannelEvent::Audio(ChannelAudioEvent::NoteOn { key: 69, vel: 100 })));
+        }
+        rms(&mut group);
+        let before = rms(&mut group);
+        assert!(before > 0.01);
+
+        packet(&mut group, false, 0, 0xD0, 16, 0);
+        rms(&mut group);
+        assert!((rms(&mut group) - before).abs() < before * 0.05);
+
+        packet(&mut group, true, 0, 0xD0, 16, 0);
+        rms(&mut group);
+        let channel_pressure = rms(&mut group);
+        assert!(channel_pressure < before * 0.8, "{} -> {}", before, channel_pressure);
+
+        // 复音触后的按键 (60) 没有在发声，仍然作用于整个通道
+        packet(&mut group, true, 1, 0xA0, 60, 16);
+        rms(&mut group);
+        let poly_pressure = rms(&mut group);
+        assert!(poly_pressure < channel_pressure * 0.8, "{} -> {}", channel_pressure, poly_pressure);
+    }
+
+    // 接收循环发送事件的吞吐量：直接持有合成器 (现在的做法) 与每个事件先锁住 Arc<Mutex<..>> (移除前的做法) 对比
+    // 另一个线程每毫秒锁一次，模拟原来界面读取统计信息时的争用
+    // RealtimeSynth 需要音频设备，这里用同样按事件缓存的 ChannelGroup 代替，只比较加锁本身的开销
+    // 运行：cargo test --release -p xxsynth-app event_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn event_throughput() {
+        use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, ThreadCount};
+        use xsynth_core::{AudioPipe, ChannelCount};
+
+        const EVENTS: u32 = 2_000_000;
+        let group = || {
+            ChannelGroup::new(ChannelGroupConfig {
+                channel_init_options: Default::default(),
+                format: SynthFormat::Custom { channels: 16 },
+                audio_params: AudioStreamParams::new(48000, ChannelCount::Stereo),
+                parallelism: ParallelismOptions { channel: ThreadCount::None, key: ThreadCount::None },
+            })
+        };
+        let event = |i: u32| {
+            let key = 36 + (i / 2 % 60) as u8;
+            let audio = if i.is_multiple_of(2) { ChannelAudioEvent::NoteOn { key, vel: 100 } } else { ChannelAudioEvent::NoteOff { key } };
+            SynthEvent::Channel(i / 2 % 16, ChannelEvent::Audio(audio))
+        };
+        let rate = |elapsed: Duration| EVENTS as f64 / elapsed.as_secs_f64() / 1e6;
+
+        let mut owned = group();
+        let start = Instant::now();
+        for i in 0..EVENTS {
+            owned.send_event(event(i));
+        }
+        let owned_rate = rate(start.elapsed());
+
+        let shared = Arc::new(Mutex::new(group()));
+        let running = Arc::new(AtomicBool::new(true));
+        let contender = {
+            let shared = shared.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    let _ = shared.lock().unwrap().stream_params();
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+        let start = Instant::now();
+        for i in 0..EVENTS {
+            shared.lock().unwrap().send_event(event(i));
+        }
+        let locked_rate = rate(start.elapsed());
+        running.store(false, Ordering::Relaxed);
+        contender.join().unwrap();
+
+        println!("直接发送: {:.1} M 事件/秒，每个事件加锁: {:.1} M 事件/秒", owned_rate, locked_rate);
+    }
+
+    // 生成一个循环播放的正弦波 SFZ，音符在松开前不会自然结束
+    fn looping_sine_sfz(dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let spec = hound::WavSpec { channels: 1, sample_rate: 44100, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(dir.join("sine.wav"), spec).unwrap();
+        for i in 0..4410 {
+            let sample = (i as f32 * 440.0 * std::f32::consts::TAU / 44100.0).sin();
+            writer.write_sample((sample * i16::MAX as f32 * 0.5) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let sfz = dir.join("sine.sfz");
+        std::fs::write(&sfz, "<region> sample=sine.wav lokey=0 hikey=127 pitch_keycenter=69 loop_mode=loop_continuous loop_start=0 loop_end=4409 ampeg_release=0.01\n").unwrap();
+        sfz
+    }
+
+    #[test]
+    fn sustain_pedal_holds_notes_until_released() {
+        use xsynth_core::channel::{ChannelInitOptions, VoiceChannel};
+        use xsynth_core::{AudioPipe, ChannelCount};
+
+        let dir = std::env::temp_dir().join(format!("xxsynth-sustain-{}", std::process::id()));
+        let params = AudioStreamParams::new(48000, ChannelCount::Stereo);
+        let sf = SampleSoundfont::new(looping_sine_sfz(&dir), params, SoundfontInitOptions::default()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut channel = VoiceChannel::new(ChannelInitOptions::default(), params, None);
+        let stats = channel.get_channel_stats();
+        channel.process_event(ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(vec![Arc::new(sf)])));
+        let mut buf = vec![0.0f32; 48000 / 5 * 2];
+        let mut render = |channel: &mut VoiceChannel| {
+            buf.fill(0.0);
+            channel.read_samples(&mut buf);
+        };
+
+        channel.process_event(ChannelEvent::Audio(ChannelAudioEvent::NoteOn { key: 60, vel: 100 }));
+        // 半踏板 (< 64) 不算踩下
+        channel.process_event(ChannelEvent::Audio(sustain_pedal(63)));
+        channel.process_event(ChannelEvent::Audio(sustain_pedal(100)));
+        channel.process_event(ChannelEvent::Audio(ChannelAudioEvent::NoteOff { key: 60 }));
+        render(&mut channel);
+        assert_eq!(stats.voice_count(), 1, "踩下踏板时 NoteOff 不应释放音符");
+
+        channel.process_event(ChannelEvent::Audio(sustain_pedal(0)));
+        render(&mut channel);
+        assert_eq!(stats.voice_count(), 0, "松开踏板后音符应被释放");
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::audio::{panic_packets, Packet};
+use crate::config::{RealtimeConfig, PERCUSSION_CHANNEL};
+
+// 起始速率与每一档的倍数，速率按档位逐步提高直到出现欠载
+const START_RATE: u64 = 1000;
+const RATE_STEP: f64 = 1.5;
+const MAX_RATE: u64 = 5_000_000;
+// 每一档持续的时长，开头一小段用于让复音数稳定下来，期间的欠载不计
+const STEP_DURATION: Duration = Duration::from_secs(3);
+const STEP_SETTLE: Duration = Duration::from_millis(500);
+// 每个音符按下后保持的时长，复音数大致为 速率 * 时长 * 层数 (另加释音尾巴)
+const NOTE_LENGTH: Duration = Duration::from_millis(200);
+// 每毫秒发送一批，与驱动转发大量音符时的节奏接近
+const BATCH_INTERVAL: Duration = Duration::from_millis(1);
+// 使用的音高范围，避开键位过滤常见的极高 / 极低音
+const KEY_MIN: u8 = 36;
+const KEY_MAX: u8 = 96;
+// 每一档结束后的间隔，让上一档的释音尾巴消失
+const STEP_PAUSE: Duration = Duration::from_millis(500);
+
+// 一档速率的测量结果
+pub struct BenchmarkStep {
+    pub rate: u64,         // 目标速率 (音符/秒)
+    pub sent_rate: u64,    // 实际送出的速率，发送端跟不上时低于目标
+    pub peak_voices: u64,  // 该档的最高复音数
+    pub peak_load: f32,    // 该档渲染线程的最高负载
+    pub underruns: u64,    // 该档稳定后出现的欠载次数
+}
+
+impl BenchmarkStep {
+    pub fn passed(&self) -> bool {
+        self.underruns == 0
+    }
+}
+
+// 压力测试的共享状态，界面轮询显示进度与结果
+#[derive(Default)]
+pub struct BenchmarkState {
+    pub running: AtomicBool, // 测试进行中，界面清除后测试在当前档结束前停止
+    pub current_rate: AtomicU64, // 正在测试的速率
+    pub result: Mutex<Option<Result<String, String>>>, // 最近一次的报告
+}
+
+// 压力测试需要的引擎状态，均来自 AudioEngineHandle
+pub struct BenchmarkTarget {
+    pub config: RealtimeConfig,
+    pub packet_tx: Sender<Packet>,
+    pub voice_count: Arc<AtomicU64>,
+    pub render_load: Arc<AtomicU32>,
+    pub underruns: Arc<AtomicU64>,
+}
+
+/// 在后台线程运行压力测试：按档位提高 NoteOn / NoteOff 的速率，直到出现欠载或发送端跟不上
+/// 测试使用真实的引擎与输出设备，期间把测试通道的音量 (CC 7) 设为 0 以免发出刺耳的声音
+/// 结束后发送紧急静音，引擎随之把全部通道的音量恢复为默认值
+pub fn spawn_benchmark(target: BenchmarkTarget, state: Arc<BenchmarkState>) {
+    *state.result.lock().unwrap() = None;
+    state.running.store(true, Ordering::SeqCst);
+    thread::spawn(move || {
+        let steps = run_steps(&target, &state);
+        for packet in panic_packets(target.config.udp_ports().len()) {
+            let _ = target.packet_tx.send(packet);
+        }
+        let report = build_report(&target.config, &steps, state.running.load(Ordering::SeqCst));
+        match &report {
+            Ok(text) => log::info!("压力测试完成:\n{}", text),
+            Err(msg) => log::warn!("压力测试失败: {}", msg),
+        }
+        *state.result.lock().unwrap() = Some(report);
+        state.current_rate.store(0, Ordering::Relaxed);
+        state.running.store(false, Ordering::SeqCst);
+    });
+}
+
+fn run_steps(target: &BenchmarkTarget, state: &BenchmarkState) -> Vec<BenchmarkStep> {
+    // 音符分散到驱动端口 1 的旋律通道上，避开打击乐通道
+    let channels: Vec<u8> = (0..16).filter(|&ch| ch != PERCUSSION_CHANNEL).collect();
+    // 音量为 0 时合成器仍然照常渲染复音，负载与欠载不受影响
+    for &channel in &channels {
+        if target.packet_tx.send((0, vec![0, 0xB0 | channel, 7, 0])).is_err() {
+            return Vec::new();
+        }
+    }
+    let mut steps = Vec::new();
+    let mut rate = START_RATE;
+    while rate <= MAX_RATE && state.running.load(Ordering::SeqCst) {
+        state.current_rate.store(rate, Ordering::Relaxed);
+        let Some(step) = run_step(target, state, rate, &channels) else {
+            break; // 引擎已停止
+        };
+        log::info!(
+            "压力测试: {} 音符/秒 (实际 {})，复音 {}，负载 {:.0}%，欠载 {}",
+            step.rate, step.sent_rate, step.peak_voices, step.peak_load * 100.0, step.underruns
+        );
+        // 发送端达不到目标速率时再提高也没有意义
+        let saturated = step.sent_rate < step.rate * 9 / 10;
+        let passed = step.passed();
+        steps.push(step);
+        if !passed || saturated {
+            break;
+        }
+        rate = (rate as f64 * RATE_STEP).round() as u64;
+        thread::sleep(STEP_PAUSE);
+    }
+    steps
+}
+
+fn run_step(target: &BenchmarkTarget, state: &BenchmarkState, rate: u64, channels: &[u8]) -> Option<BenchmarkStep> {
+    let started = Instant::now();
+    let mut pending_off: VecDeque<(Instant, u8, u8)> = VecDeque::new();
+    let mut sent = 0u64;
+    let mut next_key = KEY_MIN;
+    let mut next_channel = 0;
+    let mut underruns_before = None;
+    let mut peak_voices = 0;
+    let mut peak_load = 0.0f32;
+
+    while started.elapsed() < STEP_DURATION && state.running.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        // 按经过的时间补齐应发送的音符数，sleep 不准时也能保持平均速率
+        let due = (started.elapsed().as_secs_f64() * rate as f64) as u64;
+        while sent < due {
+            let channel = channels[next_channel];
+            target.packet_tx.send((0, vec![0, 0x90 | channel, next_key, 100])).ok()?;
+            pending_off.push_back((now + NOTE_LENGTH, channel, next_key));
+            next_key = if next_key >= KEY_MAX { KEY_MIN } else { next_key + 1 };
+            next_channel = (next_channel + 1) % channels.len();
+            sent += 1;
+        }
+        while let Some(&(release_at, channel, key)) = pending_off.front() {
+            if release_at > now {
+                break;
+            }
+            target.packet_tx.send((0, vec![0, 0x80 | channel, key, 0])).ok()?;
+            pending_off.pop_front();
+        }
+
+        if started.elapsed() >= STEP_SETTLE {
+            let underruns = target.underruns.load(Ordering::Relaxed);
+            underruns_before.get_or_insert(underruns);
+            peak_voices = peak_voices.max(target.voice_count.load(Ordering::Relaxed));
+            peak_load = peak_load.max(f32::from_bits(target.render_load.load(Ordering::Relaxed)));
+        }
+        thread::sleep(BATCH_INTERVAL);
+    }
+
+    for (_, channel, key) in pending_off {
+        target.packet_tx.send((0, vec![0, 0x80 | channel, key, 0])).ok()?;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let underruns = underruns_before.map_or(0, |before| target.underruns.load(Ordering::Relaxed) - before);
+    Some(BenchmarkStep {
+        rate,
+        sent_rate: (sent as f64 / elapsed).round() as u64,
+        peak_voices,
+        peak_load,
+        underruns,
+    })
+}
+
+// 生成纯文本报告，最后一个没有欠载的档位即为可持续的最高速率
+fn build_report(config: &RealtimeConfig, steps: &[BenchmarkStep], completed: bool) -> Result<String, String> {
+    if steps.is_empty() {
+        return Err(if completed { "引擎已停止，无法完成压力测试".to_string() } else { "压力测试已取消".to_string() });
+    }
+
+    let mut out = String::new();
+    let threads = if config.thread_count == 0 { "自动".to_string() } else { config.thread_count.to_string() };
+    let layers = if config.layer_limit == 0 { "不限".to_string() } else { config.layer_limit.to_string() };
+    let _ = writeln!(
+        out,
+        "配置: 渲染线程 {}，缓冲 {:.1} ms，采样率 {} Hz，层数上限 {}，音符时长 {} ms",
+        threads, config.render_window_ms, config.sample_rate, layers, NOTE_LENGTH.as_millis()
+    );
+    match steps.iter().rev().find(|s| s.passed()) {
+        Some(best) => {
+            let _ = writeln!(out, "最高可持续速率: {} 音符/秒 (复音 {}，负载 {:.0}%)", best.sent_rate, best.peak_voices, best.peak_load * 100.0);
+        }
+        None => {
+            let _ = writeln!(out, "最高可持续速率: 低于 {} 音符/秒 (第一档即出现欠载)", START_RATE);
+        }
+    }
+    let last = steps.last().unwrap();
+    if !last.passed() {
+        let _ = writeln!(out, "从 {} 音符/秒起出现欠载 (复音 {})", last.rate, last.peak_voices);
+    } else if last.sent_rate < last.rate * 9 / 10 {
+        let _ = writeln!(out, "发送端在 {} 音符/秒达到上限，引擎尚未出现欠载", last.sent_rate);
+    } else if !completed {
+        let _ = writeln!(out, "测试已提前停止");
+    }
+    for step in steps {
+        let _ = writeln!(
+            out,
+            "  {:>8} 音符/秒  复音 {:>7}  负载 {:>4.0}%  欠载 {}",
+            step.rate, step.peak_voices, step.peak_load * 100.0, step.underruns
+        );
+    }
+    Ok(out)
+}
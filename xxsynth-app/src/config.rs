@@ -1,56 +1,535 @@
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
 use xsynth_core::channel_group::ThreadCount;
-use xsynth_core::soundfont::Interpolator;
+use xsynth_core::soundfont::{EnvelopeCurveType, EnvelopeOptions, Interpolator, SoundfontInitOptions};
+use xsynth_core::ChannelCount;
+
+// 音色库列表中的一项
+#[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SoundfontEntry {
+    pub path: PathBuf,
+    pub enabled: bool,
+    pub bank: Option<u8>,   // 仅加载指定 Bank，None 为全部
+    pub preset: Option<u8>, // 仅加载指定 Preset，None 为全部
+    #[serde(default)]
+    pub envelope: EnvelopeCurves, // 音量包络各阶段的曲线
+    #[serde(default)]
+    pub interpolator: Option<InterpolatorWrapper>, // 该音色库使用的插值算法，None 为跟随全局设置
+}
+
+impl SoundfontEntry {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            enabled: true,
+            bank: None,
+            preset: None,
+            envelope: EnvelopeCurves::default(),
+            interpolator: None,
+        }
+    }
+}
+
+// 包络曲线类型，对应 xsynth 的 EnvelopeCurveType (后者不支持序列化)
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub enum CurveType {
+    Linear,
+    Exponential,
+}
+
+impl CurveType {
+    pub const ALL: [CurveType; 2] = [CurveType::Linear, CurveType::Exponential];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Linear => "线性",
+            Self::Exponential => "指数",
+        }
+    }
+
+    fn to_xsynth(self) -> EnvelopeCurveType {
+        match self {
+            Self::Linear => EnvelopeCurveType::Linear,
+            Self::Exponential => EnvelopeCurveType::Exponential,
+        }
+    }
+
+    fn from_xsynth(curve: EnvelopeCurveType) -> Self {
+        match curve {
+            EnvelopeCurveType::Linear => Self::Linear,
+            EnvelopeCurveType::Exponential => Self::Exponential,
+        }
+    }
+}
+
+// 音色库音量包络的起音 / 衰减 / 释音曲线
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct EnvelopeCurves {
+    pub attack: CurveType,
+    pub decay: CurveType,
+    pub release: CurveType,
+}
+
+impl Default for EnvelopeCurves {
+    // 与 xsynth 的默认包络保持一致
+    fn default() -> Self {
+        let options = EnvelopeOptions::default();
+        Self {
+            attack: CurveType::from_xsynth(options.attack_curve),
+            decay: CurveType::from_xsynth(options.decay_curve),
+            release: CurveType::from_xsynth(options.release_curve),
+        }
+    }
+}
+
+impl EnvelopeCurves {
+    pub fn to_options(self) -> EnvelopeOptions {
+        EnvelopeOptions {
+            attack_curve: self.attack.to_xsynth(),
+            decay_curve: self.decay.to_xsynth(),
+            release_curve: self.release.to_xsynth(),
+        }
+    }
+}
+
+// 通道重映射：把某个驱动端口上某个 MIDI 通道的消息发往指定的引擎通道 (均从 0 开始)
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChannelRoute {
+    pub port: u8,
+    pub channel: u8,
+    pub target: u32, // 该监听端口通道区间内的序号
+}
 
-// 实时配置结构体
-#[derive(Clone)]
+// MIDI 直通：该驱动端口上该 MIDI 通道的消息转发到外部 MIDI 输出端口 (均从 0 开始)
+#[derive(Clone, Copy, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ThruChannel {
+    pub port: u8,
+    pub channel: u8,
+}
+
+// 实时配置结构体，保存在设置文件的 realtime 对象中，缺少的字段使用默认值
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct RealtimeConfig {
     pub render_window_ms: f64,
-    pub thread_count: usize, // 0 为 Auto
+    pub thread_count: usize, // 0 为 Auto，1 为单线程
     pub interpolator: InterpolatorWrapper,
+    pub transport: Transport, // 接收 MIDI 封包使用的协议
     pub udp_port: u16,
+    pub udp_port_count: u16, // 从 udp_port 开始连续监听的端口数，多个端口平分全部通道
     pub total_channels: u32,
+    pub driver_port_count: u8, // 驱动向宿主提供的 MIDI 端口数 (1 ~ 16)，每个端口对应 16 个通道
     pub ignore_velocity_min: u8,
     pub ignore_velocity_max: u8,
+    pub sample_rate: u32,
+    pub gain: f32, // 全局音量增益，可在运行中实时调整
+    pub layer_limit: u32, // 每个通道的复音层数上限，0 为不限制，可在运行中实时调整
+    pub use_effects: bool, // 是否启用音色库自带的效果 (滤波器等)，切换时热重载音色库
+    pub timestamped_packets: bool, // 驱动是否在短消息后附带时间戳，引擎据此按原有间隔排布事件
+    pub aftertouch: bool, // 是否处理触后 (映射到表情)，可在运行中实时调整
+    pub pcm_sink: Option<String>, // 原始 PCM 输出的目标 (tcp:// 、udp:// 或命名管道)，None 为关闭
+    pub channel_routes: Vec<ChannelRoute>, // 通道重映射，为空时按 通道偏移 + 端口ID * 16 + 通道 路由
+    pub stop_fade_ms: u32, // 停止引擎时等待释放尾音的时间，0 为立即切断
+    pub velocity_curve: VelocityCurve, // NoteOn 力度的映射曲线，可在运行中实时调整
+    pub ignore_key_min: u8, // 低于该音高的 NoteOn 直接丢弃，可在运行中实时调整
+    pub ignore_key_max: u8, // 高于该音高的 NoteOn 直接丢弃，可在运行中实时调整
+    pub fade_out_killing: bool, // 超出层数上限被挤掉的声音是否淡出，xsynth 只在创建通道时读取，修改后需重启引擎
+    pub high_priority: bool, // 提高接收与处理线程的调度优先级，启动引擎时生效
+    pub gm_drums: bool, // 把每个驱动端口的通道 10 作为打击乐通道 (GM/GS)，可在运行中实时调整
+    pub channel_base_offset: u32, // 驱动端口 1 的通道 1 对应的引擎通道 (每个监听端口内)，多开实例时错开通道区间
+    pub audio_channels: AudioChannels, // 实时输出的声道数，单声道渲染量减半，启动引擎时生效
+    pub midi_thru_port: Option<String>, // MIDI 直通的目标 (系统 MIDI 输出端口名称)，None 为关闭
+    pub midi_thru_channels: Vec<ThruChannel>, // 转发到直通端口、不在本地合成的通道
 }
 
+// 实时模式可选的采样率
+pub const SAMPLE_RATES: [u32; 4] = [44100, 48000, 96000, 192000];
+
+// 驱动最多提供的 MIDI 端口数
+pub const MAX_DRIVER_PORTS: u8 = 16;
+
+// GM/GS 规定的打击乐通道 (通道 10，从 0 开始为 9)
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+// 停止引擎时默认的淡出时间 (ms) 与上限，淡出期间 stop() 会等待
+pub const DEFAULT_STOP_FADE_MS: u32 = 200;
+pub const MAX_STOP_FADE_MS: u32 = 2000;
+
+// 缓冲区 (xsynth 渲染窗口) 的可调范围 (ms)，低于 1ms 时给出警告
+pub const MIN_RENDER_WINDOW_MS: f64 = 0.25;
+pub const MAX_RENDER_WINDOW_MS: f64 = 100.0;
+
+// 估算延迟时假定的声卡缓冲 (ms)：xsynth 以默认缓冲打开输出设备，实际值无法读取，
+// 这里取 Windows 共享模式 (WASAPI) 的默认周期
+const ASSUMED_DEVICE_BUFFER_MS: f64 = 10.0;
+
+// MIDI 音高的最大值 (G9)
+pub const MAX_MIDI_KEY: u8 = 127;
+
+// 全局增益上限：xsynth 没有总音量接口，增益通过缩放每个通道的 CC 7 实现，
+// 默认音量 (100) 的通道最多只能放大到 127，再大的增益只会被截断
+pub const MAX_GAIN: f32 = 1.27;
+
 impl Default for RealtimeConfig {
     fn default() -> Self {
         Self {
             render_window_ms: 10.0,
             thread_count: 0, // 默认使用 Auto 模式
             interpolator: InterpolatorWrapper::Nearest,
+            transport: Transport::Udp,
             udp_port: 44444,
+            udp_port_count: 1,
             total_channels: 16,
+            driver_port_count: MAX_DRIVER_PORTS,
             ignore_velocity_min: 0,
             ignore_velocity_max: 1,
+            sample_rate: 48000,
+            gain: 1.0,
+            layer_limit: 4, // 与 xsynth 通道的默认层数一致
+            use_effects: false, // 默认关闭，避免超大音符量时 CPU 占用突增
+            timestamped_packets: false,
+            aftertouch: true,
+            pcm_sink: None,
+            channel_routes: Vec::new(),
+            stop_fade_ms: DEFAULT_STOP_FADE_MS,
+            velocity_curve: VelocityCurve::Linear,
+            ignore_key_min: 0,
+            ignore_key_max: MAX_MIDI_KEY,
+            // 与 xsynth 默认一致：直接切断。淡出更干净，但被挤掉的声音在淡出期间仍要渲染，
+            // 复音数长期顶满时 CPU 占用会明显升高
+            fade_out_killing: false,
+            // 核心数少的机器上可能把界面线程饿死，默认关闭
+            high_priority: false,
+            // 黑乐谱通常不遵守 GM，通道 10 也当普通乐器用
+            gm_drums: false,
+            channel_base_offset: 0,
+            audio_channels: AudioChannels::Stereo,
+            midi_thru_port: None,
+            midi_thru_channels: Vec::new(),
         }
     }
 }
 
 impl RealtimeConfig {
+    // xsynth-realtime 本身已经为每个通道开一个线程，这里的线程池用于通道内按键的并行渲染
+    // 0 = 自动 (xsynth 按 CPU 核心数建线程池)，1 = 单线程 (不建线程池)，N = 指定线程数
     pub fn get_thread_count(&self) -> ThreadCount {
-        if self.thread_count == 0 {
-            ThreadCount::Auto
-        } else {
-            ThreadCount::Manual(self.thread_count)
+        match self.thread_count {
+            0 => ThreadCount::Auto,
+            1 => ThreadCount::None,
+            n => ThreadCount::Manual(n),
         }
     }
 
     pub fn get_interpolator(&self) -> Interpolator {
-        match self.interpolator {
-            InterpolatorWrapper::Nearest => Interpolator::Nearest,
-            InterpolatorWrapper::Linear => Interpolator::Linear,
+        self.interpolator.to_xsynth()
+    }
+
+    // 需要监听的全部端口，超出 u16 范围的部分会被丢弃
+    pub fn udp_ports(&self) -> Vec<u16> {
+        (0..self.udp_port_count.max(1)).filter_map(|i| self.udp_port.checked_add(i)).collect()
+    }
+
+    // 端口参数错误，例如端口 0 或端口范围超出 65535
+    pub fn udp_port_error(&self) -> Option<String> {
+        if self.udp_port == 0 {
+            Some("端口不能为 0".to_string())
+        } else if self.udp_ports().len() < self.udp_port_count.max(1) as usize {
+            Some(format!("端口范围超出 65535，最多只能从 {} 开始监听 {} 个端口", self.udp_port, 65536 - self.udp_port as u32))
+        } else {
+            None
+        }
+    }
+
+    // 端口参数警告：1024 以下为系统保留端口，可能需要管理员权限或与系统服务冲突
+    pub fn udp_port_warning(&self) -> Option<String> {
+        if self.udp_port != 0 && self.udp_port < 1024 {
+            Some("1024 以下为系统保留端口，可能无法绑定或与其他服务冲突".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// 估算从收到 MIDI 到声卡出声的延迟 (ms)：渲染窗口 + 声卡缓冲
+    /// 开启事件时间戳时，事件还会按渲染窗口的长度延后排布
+    pub fn estimated_latency_ms(&self) -> f64 {
+        let scheduling = if self.timestamped_packets { self.render_window_ms } else { 0.0 };
+        self.render_window_ms + scheduling + ASSUMED_DEVICE_BUFFER_MS
+    }
+
+    // 缓冲区警告：太小时每个渲染窗口的固定开销 (尤其是多线程调度) 占比过大，容易爆音
+    pub fn render_window_warning(&self) -> Option<String> {
+        if self.render_window_ms < 1.0 {
+            Some("低于 1ms 的缓冲区极易爆音，只建议在性能很强的机器上尝试".to_string())
+        } else if self.render_window_ms < 3.0 && self.thread_count != 1 {
+            Some("缓冲区较小时多线程调度的开销占比较大，可能爆音；可调大缓冲区或改为单线程".to_string())
+        } else {
+            None
+        }
+    }
+
+    // 通道数错误：路由按 端口ID * 16 + 通道 计算，必须是 16 的倍数，且平均分给每个监听端口后仍是 16 的倍数
+    pub fn total_channels_error(&self) -> Option<String> {
+        let socket_count = self.udp_port_count.max(1) as u32;
+        if self.total_channels == 0 || !self.total_channels.is_multiple_of(16) {
+            Some("总通道数必须是 16 的倍数".to_string())
+        } else if !self.total_channels.is_multiple_of(16 * socket_count) {
+            Some(format!(
+                "总通道数 {} 无法平均分给 {} 个监听端口 (每个端口的通道数必须是 16 的倍数)",
+                self.total_channels, socket_count
+            ))
+        } else {
+            None
+        }
+    }
+
+    // 通道偏移错误：必须按 16 个通道对齐，且不能超出每个监听端口的通道数
+    pub fn channel_offset_error(&self) -> Option<String> {
+        let channels_per_socket = self.total_channels / self.udp_port_count.max(1) as u32;
+        if !self.channel_base_offset.is_multiple_of(16) {
+            Some("通道偏移必须是 16 的倍数".to_string())
+        } else if self.channel_base_offset >= channels_per_socket {
+            Some(format!("通道偏移 {} 超出范围 (每个监听端口只有 {} 个通道)", self.channel_base_offset, channels_per_socket))
+        } else {
+            None
+        }
+    }
+
+    // 每个驱动端口的打击乐通道经过重映射后对应的引擎通道
+    pub fn drum_channels(&self) -> Vec<u32> {
+        let socket_count = self.udp_ports().len() as u32;
+        let channels_per_socket = self.total_channels / socket_count;
+        let routes = self.channel_route_table();
+        let mut channels = Vec::new();
+        for socket in 0..socket_count {
+            for port in 0..MAX_DRIVER_PORTS as u32 {
+                let source = port * 16 + PERCUSSION_CHANNEL as u32;
+                let target = routes[source as usize].unwrap_or(source + self.channel_base_offset);
+                if target < channels_per_socket {
+                    channels.push(socket * channels_per_socket + target);
+                }
+            }
+        }
+        channels
+    }
+
+    // 引擎实际接收的驱动端口数：驱动只发往第一个监听端口，该端口分到的通道 (从偏移开始) 每 16 个对应一个驱动端口
+    pub fn usable_driver_ports(&self) -> u8 {
+        let channels_per_socket = self.total_channels / self.udp_port_count.max(1) as u32;
+        (channels_per_socket.saturating_sub(self.channel_base_offset) / 16).min(MAX_DRIVER_PORTS as u32) as u8
+    }
+
+    // 驱动端口警告：超出第一个监听端口通道范围的驱动端口不会发声
+    pub fn driver_port_warning(&self) -> Option<String> {
+        let usable = self.usable_driver_ports();
+        if self.driver_port_count > usable {
+            Some(format!("当前通道数只够 {} 个驱动端口使用，Port {} 及之后的端口不会发声", usable, usable as u32 + 1))
+        } else {
+            None
+        }
+    }
+
+    // 通道重映射的查找表，下标为 端口ID * 16 + 通道；同一输入出现多次时以最后一条为准
+    pub fn channel_route_table(&self) -> Vec<Option<u32>> {
+        let mut table = vec![None; MAX_DRIVER_PORTS as usize * 16];
+        for route in &self.channel_routes {
+            if let Some(slot) = table.get_mut(route.port as usize * 16 + route.channel as usize) {
+                *slot = Some(route.target);
+            }
+        }
+        table
+    }
+
+    // 按 端口ID * 16 + 通道 索引的直通表，没有设置直通端口时全部为 false
+    pub fn thru_table(&self) -> Vec<bool> {
+        let mut table = vec![false; MAX_DRIVER_PORTS as usize * 16];
+        if self.midi_thru_port.is_some() {
+            for thru in &self.midi_thru_channels {
+                if let Some(slot) = table.get_mut(thru.port as usize * 16 + thru.channel as usize) {
+                    *slot = true;
+                }
+            }
+        }
+        table
+    }
+
+    // 直通端口错误：选了本程序驱动自己的端口时，转发出去的消息会回到引擎形成循环
+    pub fn midi_thru_error(&self) -> Option<String> {
+        let port = self.midi_thru_port.as_ref()?;
+        crate::midithru::is_driver_port(port)
+            .then(|| format!("MIDI 直通端口不能是 XXSynth 自己的端口 ({})，否则消息会回到引擎无限循环", port))
+    }
+
+    // 重映射警告：目标超出每个监听端口的通道数时，对应的消息会被丢弃
+    pub fn channel_routes_warning(&self) -> Option<String> {
+        let channels_per_socket = self.total_channels / self.udp_port_count.max(1) as u32;
+        self.channel_routes.iter().find(|r| r.target >= channels_per_socket).map(|r| {
+            format!("引擎通道 {} 超出范围 (每个监听端口只有 {} 个通道)，对应的消息会被丢弃", r.target + 1, channels_per_socket)
+        })
+    }
+
+    /// 启动引擎前的参数检查，返回第一个错误
+    pub fn validate(&self) -> Result<(), String> {
+        match self
+            .udp_port_error()
+            .or_else(|| self.total_channels_error())
+            .or_else(|| self.channel_offset_error())
+            .or_else(|| self.midi_thru_error())
+        {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // 忽略力度范围是否启用：最小、最大值都为 0 时视为关闭
+    pub fn is_ignore_range_enabled(&self) -> bool {
+        !(self.ignore_velocity_min == 0 && self.ignore_velocity_max == 0)
+    }
+
+    // 忽略力度范围，关闭时给出空范围，不忽略任何音符
+    // 接收循环按 MIDI 端发来的原始力度 (力度曲线映射之前) 判断，不交给 xsynth
+    // 力度为 0 的 NoteOn 在接收循环中已经转换成 NoteOff，不受此范围影响
+    pub fn get_ignore_range(&self) -> RangeInclusive<u8> {
+        if self.is_ignore_range_enabled() {
+            self.ignore_velocity_min..=self.ignore_velocity_max
+        } else {
+            RangeInclusive::new(1, 0)
+        }
+    }
+
+    // 保留的音高范围，范围外的 NoteOn 在接收循环中丢弃；NoteOff 照常转发，避免调整范围时留下挂音
+    pub fn key_range(&self) -> RangeInclusive<u8> {
+        self.ignore_key_min..=self.ignore_key_max
+    }
+
+    // 音高过滤是否启用：保留全部 0 ~ 127 时视为关闭
+    pub fn is_key_filter_enabled(&self) -> bool {
+        self.ignore_key_min > 0 || self.ignore_key_max < MAX_MIDI_KEY
+    }
+
+    pub fn get_layer_count(&self) -> Option<usize> {
+        if self.layer_limit == 0 { None } else { Some(self.layer_limit as usize) }
+    }
+
+    pub fn get_soundfont_options(&self) -> SoundfontInitOptions {
+        SoundfontInitOptions {
+            interpolator: self.get_interpolator(),
+            use_effects: self.use_effects,
+            ..Default::default()
+        }
+    }
+}
+
+// 力度曲线：把 MIDI 端发来的 NoteOn 力度映射为实际发声的力度
+// 线性为原样输出；指数让轻触更轻、重击时变化更陡；自定义 Gamma 为 127 * (v / 127) ^ gamma
+#[derive(PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Exponential,
+    Gamma(f32),
+}
+
+// 自定义 Gamma 的取值范围，小于 1 时整体变响，大于 1 时整体变轻
+pub const MIN_VELOCITY_GAMMA: f32 = 0.2;
+pub const MAX_VELOCITY_GAMMA: f32 = 5.0;
+pub const DEFAULT_VELOCITY_GAMMA: f32 = 0.6;
+
+// 指数曲线的陡峭程度
+const VELOCITY_EXP_STEEPNESS: f32 = 3.0;
+
+impl VelocityCurve {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Linear => "线性",
+            Self::Exponential => "指数",
+            Self::Gamma(_) => "自定义 Gamma",
+        }
+    }
+
+    /// 映射一个 NoteOn 力度，结果保持在 1 ~ 127，不会把音符变成 NoteOff
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let x = velocity.min(127) as f32 / 127.0;
+        let y = match self {
+            Self::Linear => return velocity,
+            Self::Exponential => (VELOCITY_EXP_STEEPNESS * x).exp_m1() / VELOCITY_EXP_STEEPNESS.exp_m1(),
+            Self::Gamma(gamma) => x.powf(gamma.clamp(MIN_VELOCITY_GAMMA, MAX_VELOCITY_GAMMA)),
+        };
+        (y * 127.0).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+// 接收 MIDI 封包的传输协议
+// UDP 延迟最低，但极端音符量下可能丢包；TCP 保证顺序与送达，每个封包前加 2 字节 (小端) 长度
+#[derive(PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum Transport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Udp => "UDP",
+            Self::Tcp => "TCP",
+        }
+    }
+
+    // 写入驱动共享配置的取值
+    pub fn config_value(&self) -> &'static str {
+        match self {
+            Self::Udp => "udp",
+            Self::Tcp => "tcp",
+        }
+    }
+}
+
+// 实时输出的声道数
+#[derive(PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum AudioChannels {
+    Mono,
+    #[default]
+    Stereo,
+}
+
+impl AudioChannels {
+    pub const ALL: [AudioChannels; 2] = [AudioChannels::Stereo, AudioChannels::Mono];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Mono => "单声道 (mono)",
+            Self::Stereo => "立体声 (stereo)",
+        }
+    }
+
+    pub fn to_channel_count(self) -> ChannelCount {
+        match self {
+            Self::Mono => ChannelCount::Mono,
+            Self::Stereo => ChannelCount::Stereo,
         }
     }
 }
 
 // 包装一下 Interpolator 以便在 UI 中使用
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub enum InterpolatorWrapper {
     Nearest,
     Linear,
 }
 
+impl InterpolatorWrapper {
+    pub const ALL: [InterpolatorWrapper; 2] = [InterpolatorWrapper::Nearest, InterpolatorWrapper::Linear];
+
+    pub fn to_xsynth(self) -> Interpolator {
+        match self {
+            Self::Nearest => Interpolator::Nearest,
+            Self::Linear => Interpolator::Linear,
+        }
+    }
+}
+
 impl ToString for InterpolatorWrapper {
     fn to_string(&self) -> String {
         match self {
@@ -60,20 +539,64 @@ impl ToString for InterpolatorWrapper {
     }
 }
 
-// 渲染配置结构体
-#[derive(Clone)]
+// 渲染配置结构体，随设置一起保存，缺失的字段使用默认值
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct RenderConfig {
     pub midi_path: String,
     pub output_path: String,
     pub sample_rate: u32,
     pub audio_channels: String,
     pub layers: u32,
-    pub channel_threading: String,
-    pub key_threading: String,
+    #[serde(with = "thread_count_token")]
+    pub channel_threading: ThreadCount,
+    #[serde(with = "thread_count_token")]
+    pub key_threading: ThreadCount,
     pub apply_limiter: bool,
     pub disable_fade_out: bool,
     pub linear_envelope: bool,
     pub interpolation: String,
+    pub use_external_renderer: bool, // false 时使用内置渲染器 (xsynth-core)
+    pub xsynth_render_path: Option<PathBuf>, // 外部 xsynth-render 程序的路径，None 时从 PATH / 同级目录查找
+    pub output_format: OutputFormat,
+    pub loop_mode: LoopMode, // 渲染完成后把 WAV 处理成可无缝循环的音频
+}
+
+// 循环渲染的处理方式，循环点为 MIDI 最后一个事件的时刻
+#[derive(PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum LoopMode {
+    #[default]
+    Off,
+    WrapTail, // 循环点之后的尾音叠加回开头
+    Trim,     // 在循环点截断，丢弃尾音
+}
+
+// 渲染输出格式，FLAC / OGG 由 ffmpeg 从渲染出的 WAV 转码得到
+#[derive(PartialEq, Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub enum OutputFormat {
+    Wav,
+    Flac,
+    Ogg,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 3] = [OutputFormat::Wav, OutputFormat::Flac, OutputFormat::Ogg];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Wav => "WAV (无损, 未压缩)",
+            Self::Flac => "FLAC (无损压缩)",
+            Self::Ogg => "OGG Vorbis (有损压缩)",
+        }
+    }
 }
 
 impl Default for RenderConfig {
@@ -84,12 +607,152 @@ impl Default for RenderConfig {
             sample_rate: 48000,
             audio_channels: "stereo".to_string(),
             layers: 32,
-            channel_threading: "auto".to_string(),
-            key_threading: "auto".to_string(),
+            channel_threading: ThreadCount::Auto,
+            key_threading: ThreadCount::Auto,
             apply_limiter: false,
             disable_fade_out: false,
             linear_envelope: false,
             interpolation: "linear".to_string(),
+            use_external_renderer: false,
+            xsynth_render_path: None,
+            output_format: OutputFormat::Wav,
+            loop_mode: LoopMode::Off,
         }
     }
-}
\ No newline at end of file
+}
+
+impl RenderConfig {
+    // 输出路径的扩展名是否与所选格式一致
+    pub fn output_extension_matches(&self) -> bool {
+        std::path::Path::new(&self.output_path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(self.output_format.extension()))
+    }
+
+    pub fn get_interpolator(&self) -> Interpolator {
+        if self.interpolation == "none" { Interpolator::Nearest } else { Interpolator::Linear }
+    }
+
+    // 开始渲染前的参数检查，返回第一个错误
+    pub fn launch_error(&self) -> Option<String> {
+        self.threading_error().or_else(|| self.external_renderer_error())
+    }
+
+    // 自定义线程数必须为正整数，0 会被 xsynth-render 拒绝
+    fn threading_error(&self) -> Option<String> {
+        if self.channel_threading == ThreadCount::Manual(0) {
+            return Some("通道多线程的线程数必须大于 0".to_string());
+        }
+        if self.key_threading == ThreadCount::Manual(0) {
+            return Some("按键多线程的线程数必须大于 0".to_string());
+        }
+        None
+    }
+
+    // 指定的外部 xsynth-render 不可用时返回原因；未指定路径时由系统查找，这里不检查
+    pub fn external_renderer_error(&self) -> Option<String> {
+        if !self.use_external_renderer {
+            return None;
+        }
+        let path = self.xsynth_render_path.as_ref()?;
+        if !path.is_file() {
+            return Some(format!("找不到指定的 xsynth-render: {}", path.display()));
+        }
+        if !is_executable(path) {
+            return Some(format!("指定的 xsynth-render 不是可执行文件: {}", path.display()));
+        }
+        None
+    }
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("exe"))
+}
+
+#[cfg(not(windows))]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+// 解析 xsynth-render 风格的线程参数：none, auto 或正整数
+fn parse_thread_count(s: &str) -> Option<ThreadCount> {
+    match s.trim() {
+        "none" => Some(ThreadCount::None),
+        "auto" => Some(ThreadCount::Auto),
+        n => n.parse::<usize>().ok().filter(|&n| n > 0).map(ThreadCount::Manual),
+    }
+}
+
+// 线程设置对应的 xsynth-render 命令行参数
+pub fn thread_count_arg(count: ThreadCount) -> String {
+    match count {
+        ThreadCount::None => "none".to_string(),
+        ThreadCount::Auto => "auto".to_string(),
+        ThreadCount::Manual(n) => n.to_string(),
+    }
+}
+
+// ThreadCount 不支持序列化，按命令行参数的写法保存，与旧版本的字符串设置兼容
+// 无法识别的旧值 (手误输入) 回退为 auto，不让整个设置文件读取失败
+mod thread_count_token {
+    use super::{parse_thread_count, thread_count_arg};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use xsynth_core::channel_group::ThreadCount;
+
+    pub fn serialize<S: Serializer>(count: &ThreadCount, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&thread_count_arg(*count))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ThreadCount, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        Ok(parse_thread_count(&token).unwrap_or_else(|| {
+            log::warn!("无效的渲染线程设置 \"{}\"，已改为 auto", token);
+            ThreadCount::Auto
+        }))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drum_channels_without_routes() {
+        let cfg = RealtimeConfig { total_channels: 48, ..Default::default() };
+        assert_eq!(cfg.drum_channels(), vec![9, 25, 41]);
+    }
+
+    #[test]
+    fn drum_channels_follow_route_on_channel_10() {
+        let cfg = RealtimeConfig {
+            total_channels: 32,
+            channel_routes: vec![ChannelRoute { port: 0, channel: PERCUSSION_CHANNEL, target: 3 }],
+            ..Default::default()
+        };
+        assert_eq!(cfg.drum_channels(), vec![3, 25]);
+    }
+
+    #[test]
+    fn drum_channels_apply_base_offset() {
+        let cfg = RealtimeConfig { total_channels: 48, channel_base_offset: 16, ..Default::default() };
+        // 偏移后端口 0 的通道 10 为引擎通道 25，端口 2 超出范围
+        assert_eq!(cfg.drum_channels(), vec![25, 41]);
+    }
+
+    #[test]
+    fn total_channels_split_per_socket() {
+        let cfg = |total_channels, udp_port_count| RealtimeConfig { total_channels, udp_port_count, ..Default::default() };
+        assert!(cfg(64, 2).total_channels_error().is_none());
+        assert!(cfg(48, 2).total_channels_error().is_some());
+        assert!(cfg(40, 1).total_channels_error().is_some());
+    }
+
+    #[test]
+    fn thread_count_mapping() {
+        let count = |thread_count| RealtimeConfig { thread_count, ..Default::default() }.get_thread_count();
+        assert_eq!(count(0), ThreadCount::Auto);
+        assert_eq!(count(1), ThreadCount::None);
+        assert_eq!(count(8), ThreadCount::Manual(8));
+    }
+}
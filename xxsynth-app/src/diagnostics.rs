@@ -0,0 +1,129 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use crate::audio::{default_output_name, loopback_self_test, soundfont_memory};
+use crate::ui::format_bytes;
+use crate::benchmark::spawn_benchmark;
+use crate::{PortRegistration, XXSynthApp};
+
+// 实际链接的 xsynth 各组件版本，由 build.rs 从 Cargo.lock 读取
+pub(crate) const XSYNTH_VERSIONS: [(&str, &str); 3] = [
+    ("xsynth-core", env!("XSYNTH_CORE_VERSION")),
+    ("xsynth-realtime", env!("XSYNTH_REALTIME_VERSION")),
+    ("xsynth-soundfonts", env!("XSYNTH_SOUNDFONTS_VERSION")),
+];
+
+/// 程序与 xsynth 各组件的版本，一行一个，用于 "关于" 窗口的复制与诊断信息
+pub(crate) fn version_summary() -> String {
+    let mut out = format!("XXSynth {}\n", env!("CARGO_PKG_VERSION"));
+    for (name, version) in XSYNTH_VERSIONS {
+        let _ = writeln!(out, "{} {}", name, version);
+    }
+    out
+}
+
+impl XXSynthApp {
+    /// 在后台线程运行回环自检，结果写入 self_test_result，由界面显示
+    pub(crate) fn start_self_test(&mut self) {
+        let Some(handle) = &self.audio_handle else {
+            self.set_status(log::Level::Warn, "引擎未运行，请先启动引擎再自检。");
+            return;
+        };
+        if self.self_test_running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let config = handle.config.clone();
+        let packets_received = handle.packets_received.clone();
+        let voice_count = handle.voice_count.clone();
+        let result = self.self_test_result.clone();
+        let running = self.self_test_running.clone();
+        *result.lock().unwrap() = None;
+        thread::spawn(move || {
+            let outcome = loopback_self_test(&config, &packets_received, &voice_count);
+            match &outcome {
+                Ok(msg) => log::info!("{}", msg),
+                Err(msg) => log::warn!("自检失败: {}", msg),
+            }
+            *result.lock().unwrap() = Some(outcome);
+            running.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// 在后台线程运行压力测试，进度与报告写入 benchmark，由界面显示
+    pub(crate) fn start_benchmark(&mut self) {
+        let Some(handle) = &self.audio_handle else {
+            self.set_status(log::Level::Warn, "引擎未运行，请先启动引擎再测试。");
+            return;
+        };
+        if self.benchmark.running.load(Ordering::SeqCst) {
+            return;
+        }
+        log::info!("开始压力测试...");
+        spawn_benchmark(handle.benchmark_target(), self.benchmark.clone());
+    }
+
+    /// 汇总当前的引擎状态与配置，生成便于附在问题反馈里的纯文本
+    pub(crate) fn diagnostics_report(&self) -> String {
+        let mut out = String::new();
+        let cfg = &self.realtime_config;
+
+        out.push_str(&version_summary());
+        let _ = writeln!(out, "系统: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+        let _ = writeln!(out, "CPU 线程数: {}", std::thread::available_parallelism().map(|n| n.get()).unwrap_or(0));
+        let _ = writeln!(out, "输出设备: {}", default_output_name().unwrap_or_else(|| "(无)".to_string()));
+        let _ = writeln!(out, "MIDI 端口注册: {}", match &self.port_registration {
+            PortRegistration::Registered(slot) => format!("成功 ({})", slot),
+            PortRegistration::Failed => "失败 (未以管理员身份运行或提权被拒绝)".to_string(),
+            PortRegistration::NoFreeSlot => "失败 (midi1 ~ midi9 均已被其他驱动占用)".to_string(),
+            PortRegistration::Unregistered => "已手动卸载".to_string(),
+        });
+
+        let _ = writeln!(out);
+        match &self.audio_handle {
+            Some(handle) => {
+                // 引擎实际使用的是启动时的配置，界面上未应用的修改不算在内
+                let running = &handle.config;
+                let ports: Vec<String> = running.udp_ports().iter().map(|p| p.to_string()).collect();
+                let _ = writeln!(out, "引擎: 运行中，复音数 {}", handle.voice_count.load(Ordering::Relaxed));
+                let _ = writeln!(out, "监听: {} 端口 {}", running.transport.label(), ports.join(", "));
+                let _ = writeln!(out, "总通道数: {}，驱动端口数: {}", running.total_channels, running.driver_port_count);
+                let _ = writeln!(out, "采样率: {} Hz，{}，缓冲区: {} ms", running.sample_rate, running.audio_channels.label(), running.render_window_ms);
+                let _ = writeln!(out, "按键多线程: {:?}", running.get_thread_count());
+                let _ = writeln!(out, "插值: {}，层数上限: {}，效果: {}", running.interpolator.to_string(), running.layer_limit, running.use_effects);
+                let _ = writeln!(out, "音量增益: {:.2}", running.gain);
+                let memory = soundfont_memory();
+                let _ = writeln!(
+                    out,
+                    "音色库内存 (估算): {}，已加载 {} 个，{} 个无法估算",
+                    format_bytes(memory.bytes), memory.loaded, memory.unestimated
+                );
+                let _ = writeln!(out, "忽略力度: {:?}", running.get_ignore_range());
+                if running != cfg {
+                    let _ = writeln!(out, "(界面上有尚未应用的参数修改)");
+                }
+            }
+            None => {
+                let _ = writeln!(out, "引擎: 已停止");
+            }
+        }
+
+        let _ = writeln!(out);
+        let _ = writeln!(out, "音色库 ({} 个):", self.soundfonts.len());
+        let results = self.sf_load_results.lock().map(|r| r.clone()).unwrap_or_default();
+        for (i, entry) in self.soundfonts.iter().enumerate() {
+            let state = if !entry.enabled {
+                "已禁用".to_string()
+            } else {
+                match results.iter().find(|(p, _)| *p == entry.path) {
+                    Some((_, Ok(info))) => format!("已加载，{}", info.summary()),
+                    Some((_, Err(e))) => format!("加载失败: {}", e),
+                    None => "未加载".to_string(),
+                }
+            };
+            let _ = writeln!(out, "  {}. {} [{}]", i + 1, entry.path.display(), state);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::audio::spawn_audio_thread;
+use crate::config::{OutputFormat, SoundfontEntry, Transport, MAX_GAIN};
+use crate::render::render_blocking;
+use crate::settings::{self, AppSettings};
+
+// 命令行参数，未指定的项沿用图形界面保存的设置
+#[derive(clap::Parser)]
+#[command(name = "xxsynth-app", about = "XXSynth MIDI 合成器")]
+pub struct Cli {
+    /// 不打开窗口，直接在后台运行音频引擎，按 Ctrl-C 退出
+    #[arg(long)]
+    pub headless: bool,
+
+    /// 音色库文件，可重复指定，顺序即加载顺序
+    #[arg(short = 's', long = "soundfont")]
+    pub soundfonts: Vec<PathBuf>,
+
+    /// UDP 监听端口
+    #[arg(short = 'p', long)]
+    pub udp_port: Option<u16>,
+
+    /// 改用 TCP 接收 MIDI 封包 (保证顺序与送达)
+    #[arg(long)]
+    pub tcp: bool,
+
+    /// 总通道数
+    #[arg(short = 'c', long)]
+    pub channels: Option<u32>,
+
+    /// 按键多线程数量，0 为自动，1 为单线程
+    #[arg(short = 't', long)]
+    pub threads: Option<usize>,
+
+    /// 采样率
+    #[arg(long)]
+    pub sample_rate: Option<u32>,
+
+    /// 每个通道的复音层数上限，0 为不限制
+    #[arg(short = 'l', long)]
+    pub layers: Option<u32>,
+
+    /// 全局音量增益 (0.0 ~ 1.27)
+    #[arg(short = 'g', long)]
+    pub gain: Option<f32>,
+
+    /// 离线渲染指定的 MIDI 文件后退出，不打开窗口；"-" 表示从标准输入读取。
+    /// 标准输入需为完整的标准 MIDI 文件 (SMF，以 MThd 开头)，读到 EOF 为止
+    #[arg(long, value_name = "MIDI")]
+    pub render: Option<String>,
+
+    /// 渲染输出路径，按扩展名选择 WAV / FLAC / OGG，未指定时沿用图形界面的设置
+    #[arg(short = 'o', long, requires = "render")]
+    pub output: Option<PathBuf>,
+}
+
+/// 无界面模式：启动引擎后阻塞，直到收到 Ctrl-C
+pub fn run(cli: Cli) -> Result<(), String> {
+    let settings = AppSettings::load();
+
+    let mut config = settings.realtime_config();
+    if let Some(port) = cli.udp_port { config.udp_port = port; }
+    if cli.tcp { config.transport = Transport::Tcp; }
+    if let Some(channels) = cli.channels { config.total_channels = channels.clamp(16, 256); }
+    if let Some(threads) = cli.threads { config.thread_count = threads; }
+    if let Some(rate) = cli.sample_rate { config.sample_rate = rate; }
+    if let Some(layers) = cli.layers { config.layer_limit = layers; }
+    if let Some(gain) = cli.gain { config.gain = gain.clamp(0.0, MAX_GAIN); }
+
+    config.validate()?;
+    // 驱动按共享配置选择端口与协议，需与本次的参数保持一致
+    settings::write_driver_config(&config);
+
+    let soundfonts = if cli.soundfonts.is_empty() {
+        settings.soundfonts
+    } else {
+        cli.soundfonts.into_iter().map(SoundfontEntry::new).collect()
+    };
+
+    let stop_requested = Arc::new(AtomicBool::new(false));
+    let stop_clone = stop_requested.clone();
+    ctrlc::set_handler(move || stop_clone.store(true, Ordering::SeqCst))
+        .map_err(|e| format!("无法注册 Ctrl-C 处理: {}", e))?;
+
+    let load_progress = Arc::new(Mutex::new(0.0));
+    let engine_message = Arc::new(Mutex::new(None));
+    let load_results = Arc::new(Mutex::new(Vec::new()));
+
+    let mut handle = spawn_audio_thread(config, soundfonts, load_progress, engine_message.clone(), load_results)?;
+    println!("无界面模式运行中，按 Ctrl-C 退出。");
+
+    while !stop_requested.load(Ordering::SeqCst) && handle.is_running.load(Ordering::Relaxed) {
+        if let Some(msg) = engine_message.lock().unwrap().take() {
+            println!("{}", msg);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    handle.stop();
+    Ok(())
+}
+
+/// 命令行渲染：使用图形界面保存的渲染参数，音色库可用 -s 覆盖，完成后返回
+pub fn render(cli: Cli) -> Result<(), String> {
+    let Some(input) = cli.render else {
+        return Ok(());
+    };
+    let settings = AppSettings::load();
+
+    let mut cfg = settings.render.clone();
+    // xsynth-render 只接受文件路径，标准输入的内容先完整读入临时文件
+    let stdin_file = if input == "-" { Some(read_stdin_midi()?) } else { None };
+    cfg.midi_path = match &stdin_file {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => input,
+    };
+    if let Some(output) = cli.output {
+        let ext = output.extension().map(|e| e.to_string_lossy().to_lowercase());
+        if let Some(format) = OutputFormat::ALL.into_iter().find(|f| ext.as_deref() == Some(f.extension())) {
+            cfg.output_format = format;
+        }
+        cfg.output_path = output.to_string_lossy().to_string();
+    }
+
+    let soundfonts: Vec<PathBuf> = if cli.soundfonts.is_empty() {
+        settings.soundfonts.iter().filter(|sf| sf.enabled).map(|sf| sf.path.clone()).collect()
+    } else {
+        cli.soundfonts
+    };
+
+    let result = if soundfonts.is_empty() {
+        Err("渲染需要至少一个音色库，请用 -s 指定或先在图形界面中添加".to_string())
+    } else if let Some(e) = cfg.launch_error() {
+        Err(e)
+    } else {
+        eprintln!("正在渲染 {} -> {}", if stdin_file.is_some() { "(标准输入)" } else { cfg.midi_path.as_str() }, cfg.output_path);
+        render_blocking(&cfg, &soundfonts)
+    };
+    if let Some(path) = stdin_file {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+// 从标准输入读取整个 MIDI 文件并写入临时目录，返回临时文件路径
+fn read_stdin_midi() -> Result<PathBuf, String> {
+    let mut data = Vec::new();
+    std::io::stdin().read_to_end(&mut data).map_err(|e| format!("无法读取标准输入: {}", e))?;
+    if !data.starts_with(b"MThd") {
+        return Err("标准输入不是标准 MIDI 文件 (应以 MThd 开头)".to_string());
+    }
+    let path = std::env::temp_dir().join(format!("xxsynth-stdin-{}.mid", std::process::id()));
+    std::fs::write(&path, &data).map_err(|e| format!("无法写入临时文件: {}", e))?;
+    Ok(path)
+}
+
+// Release 版本隐藏了控制台窗口，从命令行启动时附加到父进程的控制台以便输出日志
+#[cfg(windows)]
+pub fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn attach_console() {}
@@ -0,0 +1,132 @@
+// 全局快捷键：程序不在前台 (甚至隐藏到托盘) 时也能触发，用于演出中的紧急静音
+// 目前只在 Windows 上提供，通过 RegisterHotKey 在单独线程的消息循环中接收 WM_HOTKEY
+
+// 快捷键组合，按键保存为 egui 的按键名 (A ~ Z、0 ~ 9、F1 ~ F24)
+#[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct HotkeyBinding {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: String,
+}
+
+impl Default for HotkeyBinding {
+    // 默认 Ctrl+Alt+P，很少被其他程序占用
+    fn default() -> Self {
+        Self { ctrl: true, alt: true, shift: false, key: "P".to_string() }
+    }
+}
+
+impl HotkeyBinding {
+    /// 从按下的按键生成组合，不支持的按键或没有 Ctrl / Alt 的字母数字键 (会妨碍正常打字) 返回 None
+    pub fn from_key(key: egui::Key, modifiers: egui::Modifiers) -> Option<Self> {
+        let binding = Self { ctrl: modifiers.ctrl, alt: modifiers.alt, shift: modifiers.shift, key: key.name().to_string() };
+        let vk = binding.virtual_key()?;
+        let is_function_key = (0x70..=0x87).contains(&vk);
+        (is_function_key || binding.ctrl || binding.alt).then_some(binding)
+    }
+
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if self.ctrl { parts.push("Ctrl"); }
+        if self.alt { parts.push("Alt"); }
+        if self.shift { parts.push("Shift"); }
+        parts.push(&self.key);
+        parts.join("+")
+    }
+
+    // Windows 虚拟键码：字母与数字即 ASCII 码，F1 ~ F24 为 0x70 ~ 0x87
+    fn virtual_key(&self) -> Option<u32> {
+        let mut chars = self.key.chars();
+        if let (Some(c), None) = (chars.next(), chars.next())
+            && c.is_ascii_alphanumeric()
+        {
+            return Some(c.to_ascii_uppercase() as u32);
+        }
+        let n: u32 = self.key.strip_prefix('F')?.parse().ok()?;
+        (1..=24).contains(&n).then(|| 0x6F + n)
+    }
+}
+
+// 已注册的全局快捷键，释放时注销并结束消息线程
+pub struct GlobalHotkey {
+    #[cfg(windows)]
+    thread_id: u32,
+    #[cfg(windows)]
+    thread_handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl GlobalHotkey {
+    /// 注册全局快捷键，按下时在消息线程中调用 on_press；组合已被其他程序占用时返回错误
+    pub fn register(binding: &HotkeyBinding, on_press: impl Fn() + Send + 'static) -> Result<Self, String> {
+        #[cfg(windows)]
+        {
+            register_windows(binding, on_press)
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = (binding, on_press);
+            Err("当前平台不支持全局快捷键".to_string())
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for GlobalHotkey {
+    fn drop(&mut self) {
+        use windows_sys::Win32::UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT};
+        unsafe {
+            PostThreadMessageW(self.thread_id, WM_QUIT, 0, 0);
+        }
+        if let Some(handle) = self.thread_handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(windows)]
+fn register_windows(binding: &HotkeyBinding, on_press: impl Fn() + Send + 'static) -> Result<GlobalHotkey, String> {
+    use std::sync::mpsc;
+    use windows_sys::Win32::System::Threading::GetCurrentThreadId;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, UnregisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetMessageW, PeekMessageW, MSG, PM_NOREMOVE, WM_HOTKEY};
+
+    const HOTKEY_ID: i32 = 1;
+    let vk = binding.virtual_key().ok_or_else(|| format!("不支持的按键: {}", binding.key))?;
+    let mut modifiers = MOD_NOREPEAT;
+    if binding.ctrl { modifiers |= MOD_CONTROL; }
+    if binding.alt { modifiers |= MOD_ALT; }
+    if binding.shift { modifiers |= MOD_SHIFT; }
+
+    // 热键消息投递到注册它的线程，注册与消息循环必须在同一个线程里
+    let (result_tx, result_rx) = mpsc::channel::<Result<u32, String>>();
+    let label = binding.label();
+    let thread_handle = std::thread::spawn(move || unsafe {
+        let mut msg: MSG = std::mem::zeroed();
+        // 先建立消息队列，之后才能接收 PostThreadMessageW 发来的退出消息
+        PeekMessageW(&mut msg, std::ptr::null_mut(), 0, 0, PM_NOREMOVE);
+        if RegisterHotKey(std::ptr::null_mut(), HOTKEY_ID, modifiers, vk) == 0 {
+            let _ = result_tx.send(Err(format!("{} 已被其他程序占用", label)));
+            return;
+        }
+        let _ = result_tx.send(Ok(GetCurrentThreadId()));
+        // GetMessageW 收到 WM_QUIT 时返回 0，出错时返回 -1
+        while GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) > 0 {
+            if msg.message == WM_HOTKEY && msg.wParam == HOTKEY_ID as usize {
+                on_press();
+            }
+        }
+        UnregisterHotKey(std::ptr::null_mut(), HOTKEY_ID);
+    });
+
+    match result_rx.recv() {
+        Ok(Ok(thread_id)) => Ok(GlobalHotkey { thread_id, thread_handle: Some(thread_handle) }),
+        Ok(Err(e)) => {
+            let _ = thread_handle.join();
+            Err(e)
+        }
+        Err(_) => Err("快捷键线程异常退出".to_string()),
+    }
+}
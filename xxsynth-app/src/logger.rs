@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// 日志面板最多保留的行数，超出后丢弃最旧的
+pub const MAX_LOG_LINES: usize = 500;
+
+// 日志面板中的一行
+#[derive(Clone)]
+pub struct LogEntry {
+    pub elapsed: f64, // 距程序启动的秒数
+    pub level: Level,
+    pub message: String,
+}
+
+// 环形缓冲区日志：音频线程与 UI 线程都通过 log 宏写入，同时照常输出到控制台
+pub struct RingLogger {
+    started_at: Instant,
+    entries: Mutex<VecDeque<LogEntry>>,
+}
+
+static LOGGER: LazyLock<RingLogger> = LazyLock::new(|| RingLogger {
+    started_at: Instant::now(),
+    entries: Mutex::new(VecDeque::with_capacity(MAX_LOG_LINES)),
+});
+
+impl Log for RingLogger {
+    // 本程序的日志记录到 Info，依赖库 (egui / wgpu 等) 只记录警告与错误
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            metadata.level() <= Level::Info
+        } else {
+            metadata.level() <= Level::Warn
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("[{}] {}", record.level(), message),
+            _ => println!("[{}] {}", record.level(), message),
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= MAX_LOG_LINES {
+                entries.pop_front();
+            }
+            entries.push_back(LogEntry {
+                elapsed: self.started_at.elapsed().as_secs_f64(),
+                level: record.level(),
+                message,
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// 安装全局日志，程序启动时调用一次
+pub fn init() {
+    if log::set_logger(&*LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+/// 距程序启动的秒数，与日志面板的时间一致
+pub fn elapsed() -> f64 {
+    LOGGER.started_at.elapsed().as_secs_f64()
+}
+
+/// 复制出不低于指定级别的日志，供界面绘制
+pub fn entries(min_level: Level) -> Vec<LogEntry> {
+    LOGGER.entries.lock()
+        .map(|entries| entries.iter().filter(|e| e.level <= min_level).cloned().collect())
+        .unwrap_or_default()
+}
+
+/// 清空日志面板
+pub fn clear() {
+    if let Ok(mut entries) = LOGGER.entries.lock() {
+        entries.clear();
+    }
+}
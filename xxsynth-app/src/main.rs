@@ -1,84 +1,294 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // 隐藏控制台窗口
 
 mod audio;
+mod benchmark; // 压力测试：测量可持续的最高音符速率
 mod config;
+mod diagnostics; // 诊断信息导出
+mod headless; // 无界面 (命令行) 模式
+mod hotkey;   // 全局快捷键 (紧急静音)
+mod logger;   // 日志环形缓冲区，供日志面板显示
+mod midicapture; // 把收到的 MIDI 事件录制为 MIDI 文件
+mod midithru;  // MIDI 直通：部分通道转发到外部 MIDI 端口
+mod pcm_sink; // 实时输出的原始 PCM 流 (Socket / 命名管道)
+mod player;   // 内置 MIDI 文件播放器
+mod recorder; // 实时输出录音
+mod render;   // 离线渲染 (内置库 / 外部程序)
 mod settings; // 新增模块：本地持久化设置
+mod sfinfo;   // 音色库元数据 (格式、预设 / 采样数量)
+mod tray;     // 系统托盘图标与菜单
 mod ui;       // 新增模块：UI 细节渲染
+mod wavloop;  // 渲染结果的无缝循环后处理
 
 use eframe::egui;
-use std::path::PathBuf;
-use std::process::Command;
+use std::collections::VecDeque;
+use std::process::{Child, Command};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use config::{InterpolatorWrapper, RealtimeConfig, RenderConfig};
-use audio::{spawn_audio_thread, AudioEngineHandle};
-use settings::AppSettings;
+use benchmark::BenchmarkState;
+use hotkey::{GlobalHotkey, HotkeyBinding};
+use logger::LogEntry;
+use config::{RealtimeConfig, RenderConfig, SoundfontEntry};
+use audio::{find_free_ports, port_in_use, spawn_audio_thread, AudioEngineHandle, SoundfontLoadResults};
+use player::MidiPlayer;
+use midicapture::MidiCapture;
+use recorder::Recorder;
+use render::{QueueItem, RenderShared};
+use settings::{AppSettings, ThemeMode};
 
-const MIDI_PORT_NAME: &str = "midi7";
+// 注册表中可用于 MIDI 驱动的槽位，midi (不带编号) 通常由系统驱动占用
+const MIDI_PORT_SLOTS: [&str; 9] = ["midi1", "midi2", "midi3", "midi4", "midi5", "midi6", "midi7", "midi8", "midi9"];
+const DRIVER_DLL: &str = "xxsynth_winmm.dll";
+const DRIVERS32_KEY: &str = "HKLM\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Drivers32";
+
+// 随程序一起分发的备用中文字体 (放在程序目录的 fonts 子目录下)
+const FALLBACK_FONT_FILE: &str = "fallback-cjk.ttf";
+// 内置的点阵中文字体 (GNU Unifont 子集，只含界面用到的汉字)，找不到系统字体时使用
+// 界面新增汉字后需要用 assets/make_fallback_font.py 重新生成
+const EMBEDDED_CJK_FONT: &[u8] = include_bytes!("../assets/fallback-cjk-subset.ttf");
+// 封包速率走势图保留的秒数
+const PACKET_RATE_HISTORY: usize = 60;
+// 出现欠载后警告闪烁的时长
+const UNDERRUN_WARNING_DURATION: std::time::Duration = std::time::Duration::from_secs(3);
+// 音色库文件的扩展名 (小写)
+const SOUNDFONT_EXTENSIONS: [&str; 3] = ["sf2", "sf3", "sfz"];
+// 添加文件夹时最多加入的音色库数量，超出时整个文件夹都不添加，避免误选根目录加入成千上万个文件
+const MAX_FOLDER_SOUNDFONTS: usize = 200;
+// 状态栏消息历史保留的条数
+const STATUS_HISTORY_LEN: usize = 20;
+// 渲染试听只播放乐曲开头这么多秒
+const RENDER_PREVIEW_SECS: f64 = 10.0;
+
+// 递归收集文件夹下的音色库文件，数量超过上限后不再继续查找
+fn find_soundfonts(dir: &std::path::Path, found: &mut Vec<std::path::PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        if found.len() > MAX_FOLDER_SOUNDFONTS {
+            break;
+        }
+        let entry = entry?;
+        let path = entry.path();
+        // 不跟随符号链接进入文件夹，避免链接成环时无限递归
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            // 子文件夹读不了 (权限等) 时跳过，不影响其他文件夹
+            let _ = find_soundfonts(&path, found);
+        } else if path.extension().is_some_and(|e| SOUNDFONT_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str())) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+// 后台重启的结果
+struct RestartOutcome {
+    result: Result<AudioEngineHandle, String>, // 按新设置启动的结果
+    fallback: Option<AudioEngineHandle>,       // 新设置启动失败后，按原设置重新启动的引擎
+}
+
+// 上次启动引擎失败的原因，界面上据此提供重试与换端口
+pub(crate) struct StartFailure {
+    pub(crate) message: String,
+    pub(crate) ports_in_use: Vec<u16>,     // 被其他程序占用的监听端口
+    pub(crate) suggested_port: Option<u16>, // 能连续绑定全部端口的新起始端口
+}
+
+// 写入注册表 MIDI 端口的结果
+#[derive(PartialEq, Clone)]
+pub(crate) enum PortRegistration {
+    Registered(String), // 注册成功，值为使用的槽位 (例如 midi3)
+    Failed,             // 没有管理员权限，或用户拒绝了 UAC 提权
+    NoFreeSlot,         // 所有槽位都已被其他驱动占用
+    Unregistered,       // 用户手动卸载了虚拟端口
+}
+
+// 注册表中的驱动是否为本程序的驱动 (可能带完整路径)
+fn is_our_driver(dll: &str) -> bool {
+    dll.to_ascii_lowercase().ends_with(DRIVER_DLL)
+}
 
 #[derive(PartialEq)]
 pub(crate) enum Tab {
     Soundfonts,
     RealtimeSettings,
     RenderSettings,
+    Keyboard,
 }
 
 pub(crate) struct XXSynthApp {
     pub(crate) active_tab: Tab,
-    pub(crate) soundfonts: Vec<PathBuf>,
+    pub(crate) soundfonts: Vec<SoundfontEntry>,
     pub(crate) realtime_config: RealtimeConfig,
     pub(crate) render_config: RenderConfig,
     
     // 运行状态与脏标记
     pub(crate) audio_handle: Option<AudioEngineHandle>,
+    pub(crate) midi_player: Option<MidiPlayer>, // 内置播放器，依附于当前运行的引擎
+    pub(crate) player_speed: f64,                // 内置播放器的倍速，打开新文件时沿用
+    pub(crate) render_preview: bool,             // 内置播放器正在试听渲染页选择的 MIDI，到时自动停止
+    pub(crate) recorder: Option<Recorder>,      // 正在进行的实时录音
+    pub(crate) midi_capture: Option<MidiCapture>, // 正在进行的 MIDI 事件录制
     pub(crate) status_message: String,
+    pub(crate) status_history: VecDeque<LogEntry>, // 最近的状态栏消息，避免一闪而过的错误被后面的消息覆盖
+    status_level: log::Level,                      // 当前状态栏消息的级别
+    status_changed_at: std::time::Instant,
+    pub(crate) status_auto_hide_secs: u32, // 状态栏消息显示多少秒后自动清除，0 为一直显示
     pub(crate) is_dirty: bool, // 是否有未保存/未重启的修改
+    pub(crate) port_registration: PortRegistration,
+    pub(crate) midi_port_slot: Option<String>, // 注册使用的槽位，保存在设置中，下次优先使用
     
     // 加载/渲染进度状态
     pub(crate) load_progress: Arc<Mutex<f32>>,
+    pub(crate) engine_message: Arc<Mutex<Option<String>>>,
+    pub(crate) sf_load_results: SoundfontLoadResults, // 每个音色库的加载结果，失败的会在列表中标红
     pub(crate) is_rendering: Arc<AtomicBool>,
     pub(crate) render_progress: Arc<Mutex<f32>>,
-    pub(crate) render_error: Arc<Mutex<Option<String>>>,
+    pub(crate) render_secs: Arc<Mutex<f64>>,
+    pub(crate) render_error: Arc<Mutex<Option<Result<String, String>>>>,
+    pub(crate) render_started: Option<std::time::Instant>, // 本次渲染的开始时间，用于估算剩余时间
+    pub(crate) render_result: Option<Result<String, String>>, // 最近一次渲染的结果，弹窗显示直到用户关闭
+    pub(crate) render_queue: Arc<Mutex<Vec<QueueItem>>>, // 批量渲染队列，渲染线程会更新每项的状态
+    pub(crate) render_child: Arc<Mutex<Option<Child>>>, // 正在运行的 xsynth-render 子进程
+    pub(crate) render_cancel: Arc<AtomicBool>, // 取消内置渲染器的标记，每次开始渲染时清除
+
+    // 恢复默认设置的确认对话框
+    pub(crate) confirm_reset: bool,
+    pub(crate) reset_clear_soundfonts: bool,
+    pub(crate) sf_preset_name: String, // 音色库布局预设的名称输入框
+
+    // 系统托盘，不支持的平台上为 None
+    pub(crate) tray: Option<tray::Tray>,
+    pub(crate) start_minimized: bool, // 启动时直接隐藏到托盘
+    pub(crate) keep_engine_on_failure: bool, // 新设置启动失败时按原设置恢复运行
+    pub(crate) unregister_port_on_exit: bool, // 退出时从注册表删除虚拟 MIDI 端口
+    // 全局紧急静音快捷键：按下时由快捷键线程直接把静音封包送进当前引擎，窗口隐藏时也有效
+    pub(crate) panic_hotkey: Option<HotkeyBinding>,
+    global_hotkey: Option<GlobalHotkey>,
+    pub(crate) hotkey_error: Option<String>, // 注册失败的原因 (例如已被其他程序占用)
+    pub(crate) capturing_hotkey: bool,       // 正在等待用户按下新的组合
+    panic_target: audio::PanicTarget, // 当前引擎的封包发送端与监听端口数，每帧同步
+    pub(crate) theme: ThemeMode,
+    pub(crate) accent_color: Option<[u8; 3]>, // 用于运行状态与“需要应用”按钮的强调色
+    pub(crate) self_test_running: Arc<AtomicBool>,
+    pub(crate) self_test_result: Arc<Mutex<Option<Result<String, String>>>>, // 最近一次回环自检的结果
+    pub(crate) benchmark: Arc<BenchmarkState>, // 压力测试的进度与报告
+    pub(crate) preset_filter: String, // 预设浏览器的搜索关键字
+    pub(crate) thru_ports: Option<Vec<String>>, // 系统 MIDI 输出端口列表，首次展开直通设置时读取，点击刷新时更新
+    pub(crate) thru_edit_port: u8,      // 直通设置中正在编辑的驱动端口
+    pub(crate) packet_rate_history: VecDeque<u64>, // 最近一段时间每秒的封包速率，用于绘制走势图
+    last_rate_sample: std::time::Instant,
+    underruns_seen: u64, // 上次检查时的欠载次数，增加时闪烁警告
+    pub(crate) underrun_warning_until: Option<std::time::Instant>,
+
+    pub(crate) show_about: bool,
+    pub(crate) keyboard_popout: bool, // 键盘可视化是否在单独的窗口中显示
+    pub(crate) start_failure: Option<StartFailure>,
+    // 正在后台进行的重启 (停止旧引擎并启动新引擎)，完成前忽略新的重启请求
+    pending_restart: Option<std::thread::JoinHandle<RestartOutcome>>,
+
+    // 日志面板
+    pub(crate) show_log_panel: bool,
+    pub(crate) log_level: log::Level,  // 只显示不低于该级别的日志
+    pub(crate) log_autoscroll: bool,
+
+    // 窗口几何信息，每帧更新，退出时保存
+    window_pos: Option<egui::Pos2>,
+    window_size: Option<egui::Vec2>,
+    window_checked: bool,
 }
 
 impl XXSynthApp {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>, settings: AppSettings) -> Self {
         // 配置中文字体
         Self::setup_custom_fonts(&cc.egui_ctx);
 
         // 自动写入注册表 (带智能提权)
-        Self::register_midi_port();
+        let port_registration = Self::register_midi_port(settings.midi_port_slot.as_deref());
+        let midi_port_slot = match &port_registration {
+            PortRegistration::Registered(slot) => Some(slot.clone()),
+            _ => settings.midi_port_slot.clone(),
+        };
 
-        // 1. 加载本地设置
-        let settings = AppSettings::load();
+        // 1. 应用本地设置
         
-        let mut realtime_config = RealtimeConfig::default();
-        realtime_config.udp_port = settings.udp_port;
-        realtime_config.total_channels = settings.total_channels;
-        realtime_config.render_window_ms = settings.render_window_ms;
-        realtime_config.thread_count = settings.thread_count;
-        realtime_config.interpolator = if settings.interpolator == 1 { InterpolatorWrapper::Linear } else { InterpolatorWrapper::Nearest };
-        realtime_config.ignore_velocity_min = settings.ignore_velocity_min;
-        realtime_config.ignore_velocity_max = settings.ignore_velocity_max;
+        let realtime_config = settings.realtime_config();
 
         let mut app = Self {
             active_tab: Tab::Soundfonts,
             soundfonts: settings.soundfonts.clone(),
             realtime_config,
-            render_config: RenderConfig::default(),
+            render_config: settings.render.clone(),
             audio_handle: None,
+            midi_player: None,
+            player_speed: 1.0,
+            render_preview: false,
+            recorder: None,
+            midi_capture: None,
             status_message: "正在准备引擎...".to_string(),
+            status_history: VecDeque::with_capacity(STATUS_HISTORY_LEN),
+            status_level: log::Level::Info,
+            status_changed_at: std::time::Instant::now(),
+            status_auto_hide_secs: settings.status_auto_hide_secs,
             is_dirty: false,
+            port_registration,
+            midi_port_slot,
             load_progress: Arc::new(Mutex::new(0.0)),
+            engine_message: Arc::new(Mutex::new(None)),
+            sf_load_results: Arc::new(Mutex::new(Vec::new())),
             is_rendering: Arc::new(AtomicBool::new(false)),
             render_progress: Arc::new(Mutex::new(0.0)),
+            render_secs: Arc::new(Mutex::new(0.0)),
             render_error: Arc::new(Mutex::new(None)),
+            render_started: None,
+            render_result: None,
+            render_queue: Arc::new(Mutex::new(Vec::new())),
+            render_child: Arc::new(Mutex::new(None)),
+            render_cancel: Arc::new(AtomicBool::new(false)),
+            confirm_reset: false,
+            reset_clear_soundfonts: false,
+            sf_preset_name: String::new(),
+            tray: tray::Tray::new(&cc.egui_ctx),
+            start_minimized: settings.start_minimized,
+            keep_engine_on_failure: settings.keep_engine_on_failure,
+            unregister_port_on_exit: settings.unregister_port_on_exit,
+            panic_hotkey: settings.panic_hotkey.clone(),
+            global_hotkey: None,
+            hotkey_error: None,
+            capturing_hotkey: false,
+            panic_target: Arc::new(Mutex::new(None)),
+            theme: settings.theme,
+            accent_color: settings.accent_color,
+            self_test_running: Arc::new(AtomicBool::new(false)),
+            self_test_result: Arc::new(Mutex::new(None)),
+            benchmark: Arc::new(BenchmarkState::default()),
+            preset_filter: String::new(),
+            thru_ports: None,
+            thru_edit_port: 0,
+            packet_rate_history: VecDeque::new(),
+            last_rate_sample: std::time::Instant::now(),
+            underruns_seen: 0,
+            underrun_warning_until: None,
+            show_about: false,
+            keyboard_popout: false,
+            start_failure: None,
+            pending_restart: None,
+            show_log_panel: false,
+            log_level: log::Level::Info,
+            log_autoscroll: true,
+            window_pos: settings.window_x.zip(settings.window_y).map(|(x, y)| egui::pos2(x, y)),
+            window_size: settings.window_width.zip(settings.window_height).map(|(w, h)| egui::vec2(w, h)),
+            window_checked: false,
         };
 
+        app.apply_theme(&cc.egui_ctx);
+        app.apply_panic_hotkey();
+
+        // 托盘创建失败时不能让窗口一直隐藏
+        if app.tray.is_none() && settings.start_minimized {
+            cc.egui_ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+        }
+
         // 2. 默认自动启动引擎
-        if app.soundfonts.is_empty() {
-            app.status_message = "警告：没有加载任何音色库，将不会有声音。".to_string();
+        if !app.soundfonts.iter().any(|sf| sf.enabled) {
+            app.set_status(log::Level::Warn, "警告：没有加载任何音色库，将不会有声音。");
         }
         
         // 统一调用重启流程
@@ -89,24 +299,26 @@ impl XXSynthApp {
 
     /// 统一的引擎重启流程
     pub(crate) fn restart_engine(&mut self) {
-        // 1. 停止旧引擎
-        if let Some(mut handle) = self.audio_handle.take() {
-            handle.stop();
+        // 上一次重启尚未完成时忽略 (例如连点两次)，否则旧引擎还没释放端口，新引擎会绑定失败
+        if self.is_restarting() {
+            log::debug!("引擎正在重启，忽略重复的重启请求");
+            return;
+        }
+
+        // 0. 参数有误时不动旧引擎，避免引擎停掉后又启动失败
+        if let Err(e) = self.realtime_config.validate() {
+            self.set_status(log::Level::Error, format!("参数错误: {}", e));
+            return;
         }
 
+        // 1. 关闭播放器与录音 (连接的是旧引擎)，旧引擎在后台线程中停止
+        self.midi_player = None;
+        self.stop_recording();
+        self.stop_midi_capture();
+        let old_handle = self.audio_handle.take();
+
         // 2. 保存设置到本地 JSON
-        let cfg = &self.realtime_config;
-        let settings = AppSettings {
-            soundfonts: self.soundfonts.clone(),
-            udp_port: cfg.udp_port,
-            total_channels: cfg.total_channels,
-            render_window_ms: cfg.render_window_ms,
-            thread_count: cfg.thread_count,
-            interpolator: if cfg.interpolator == InterpolatorWrapper::Linear { 1 } else { 0 },
-            ignore_velocity_min: cfg.ignore_velocity_min,
-            ignore_velocity_max: cfg.ignore_velocity_max,
-        };
-        settings.save();
+        self.save_settings();
         
         // 清除脏标记
         self.is_dirty = false;
@@ -116,91 +328,760 @@ impl XXSynthApp {
             *p = 0.0; 
         }
 
-        // 4. 启动新引擎
-        match spawn_audio_thread(self.realtime_config.clone(), self.soundfonts.clone(), self.load_progress.clone()) {
+        // 4. 在后台线程中等待旧引擎退出 (尾音淡出、释放端口) 后启动新引擎，界面不会卡住
+        // 新旧引擎可能监听同一端口，无法先启动新引擎再切换，只能在新引擎启动失败后按原设置再启动一次
+        let config = self.realtime_config.clone();
+        let soundfonts = self.soundfonts.clone();
+        let load_progress = self.load_progress.clone();
+        let engine_message = self.engine_message.clone();
+        let sf_load_results = self.sf_load_results.clone();
+        let keep_on_failure = self.keep_engine_on_failure;
+        self.pending_restart = Some(std::thread::spawn(move || {
+            let previous = old_handle.map(|mut handle| {
+                handle.stop();
+                handle.config.clone()
+            });
+            let spawn = |config| spawn_audio_thread(config, soundfonts.clone(), load_progress.clone(), engine_message.clone(), sf_load_results.clone());
+            let result = spawn(config);
+            let fallback = match (&result, previous) {
+                (Err(e), Some(previous)) if keep_on_failure => {
+                    log::warn!("新设置启动失败 ({})，按原设置重新启动引擎", e);
+                    spawn(previous).inspect_err(|e| log::error!("按原设置重新启动也失败了: {}", e)).ok()
+                }
+                _ => None,
+            };
+            RestartOutcome { result, fallback }
+        }));
+        self.set_status(log::Level::Info, "正在重启引擎...");
+    }
+
+    pub(crate) fn is_restarting(&self) -> bool {
+        self.pending_restart.is_some()
+    }
+
+    // 引擎正在运行且参数没有改动时，音色库列表的修改可以直接热重载
+    pub(crate) fn can_hot_reload_soundfonts(&self) -> bool {
+        !self.is_restarting()
+            && self.audio_handle.as_ref().is_some_and(|h| h.is_running.load(Ordering::Relaxed) && h.config == self.realtime_config)
+    }
+
+    // 后台重启完成后接管新引擎
+    fn poll_restart(&mut self, ctx: &egui::Context) {
+        let Some(pending) = self.pending_restart.take_if(|pending| pending.is_finished()) else {
+            if self.is_restarting() {
+                ctx.request_repaint_after(std::time::Duration::from_millis(50));
+            }
+            return;
+        };
+        let outcome = pending.join().unwrap_or_else(|_| RestartOutcome { result: Err("重启线程异常退出".to_string()), fallback: None });
+        match outcome.result {
             Ok(handle) => {
+                // 按新引擎实际使用的配置显示，重启期间界面上的参数可能已被修改
+                let ports: Vec<String> = handle.config.udp_ports().iter().map(|p| p.to_string()).collect();
+                self.set_status(log::Level::Info, format!("已启动引擎。监听 {} 端口 {}", handle.config.transport.label(), ports.join(", ")));
                 self.audio_handle = Some(handle);
-                self.status_message = format!("已启动引擎。监听 UDP 端口 {}", self.realtime_config.udp_port);
+                self.start_failure = None;
+            }
+            Err(e) if outcome.fallback.is_some() => {
+                // 新设置被拒绝：引擎按原设置继续运行，界面上保留修改并标记为未应用，方便修正后重试
+                log::error!("引擎启动失败: {}", e);
+                self.set_status(log::Level::Error, format!("新设置启动失败，已恢复为原设置继续运行: {}", e));
+                self.start_failure = Some(self.diagnose_start_failure(e));
+                self.audio_handle = outcome.fallback;
+                self.is_dirty = true;
             }
             Err(e) => {
-                self.status_message = format!("启动失败: {}", e);
+                log::error!("引擎启动失败: {}", e);
+                self.set_status(log::Level::Error, format!("启动失败: {}", e));
+                self.start_failure = Some(self.diagnose_start_failure(e));
                 // 失败时直接将进度条拉满，避免界面卡死在加载状态
                 if let Ok(mut p) = self.load_progress.lock() { *p = 1.0; }
             }
         }
     }
 
-    fn setup_custom_fonts(ctx: &egui::Context) {
-        let mut fonts = egui::FontDefinitions::default();
+    // 启动失败后检查监听端口是否被占用，被占用时顺便找一段空闲端口供用户选择
+    fn diagnose_start_failure(&self, message: String) -> StartFailure {
+        let cfg = &self.realtime_config;
+        let ports_in_use: Vec<u16> = cfg.udp_ports().into_iter().filter(|&port| port_in_use(cfg.transport, port)).collect();
+        let suggested_port = if ports_in_use.is_empty() {
+            None
+        } else {
+            let after = cfg.udp_port.saturating_add(cfg.udp_port_count.max(1));
+            find_free_ports(cfg.transport, after, cfg.udp_port_count)
+        };
+        if !ports_in_use.is_empty() {
+            log::warn!("端口 {:?} 已被其他程序占用，建议改用 {:?}", ports_in_use, suggested_port);
+        }
+        StartFailure { message, ports_in_use, suggested_port }
+    }
 
-        let font_path = "C:\\Windows\\Fonts\\msyh.ttc";
-        if let Ok(font_data) = std::fs::read(font_path) {
-            fonts.font_data.insert(
-                "msyh".to_owned(),
-                std::sync::Arc::new(egui::FontData::from_owned(font_data)),
-            );
+    /// 恢复默认的实时参数 (可选清空音色库列表) 并重启引擎，原设置先备份以便撤销
+    pub(crate) fn reset_to_defaults(&mut self, clear_soundfonts: bool) {
+        self.save_settings();
+        settings::backup_for_undo();
 
-            if let Some(vec) = fonts.families.get_mut(&egui::FontFamily::Proportional) {
-                vec.insert(0, "msyh".to_owned());
+        self.realtime_config = AppSettings::default().realtime_config();
+        if clear_soundfonts {
+            self.soundfonts.clear();
+        }
+        self.restart_engine();
+        log::info!("已恢复默认设置{}", if clear_soundfonts { "并清空音色库列表" } else { "" });
+        self.set_status(self.status_level, format!("已恢复默认设置。{}", self.status_message));
+    }
+
+    /// 撤销上一次恢复默认设置
+    pub(crate) fn undo_reset(&mut self) {
+        let Some(previous) = settings::take_undo() else {
+            self.set_status(log::Level::Warn, "没有可以撤销的设置。");
+            return;
+        };
+        self.soundfonts = previous.soundfonts.clone();
+        self.realtime_config = previous.realtime_config();
+        self.render_config = previous.render.clone();
+        self.restart_engine();
+        log::info!("已撤销恢复默认设置");
+        self.set_status(self.status_level, format!("已还原之前的设置。{}", self.status_message));
+    }
+
+    /// 停止录音并等待 WAV 文件写完
+    pub(crate) fn stop_recording(&mut self) {
+        let Some(recorder) = self.recorder.take() else { return };
+        if let Some(handle) = &self.audio_handle {
+            handle.stop_recording();
+        }
+        let path = recorder.path.clone();
+        match recorder.finish() {
+            Ok(()) => self.set_status(log::Level::Info, format!("录音已保存至 {}", path.display())),
+            Err(e) => self.set_status(log::Level::Error, e),
+        }
+    }
+
+    /// 停止 MIDI 事件录制并写出文件
+    pub(crate) fn stop_midi_capture(&mut self) {
+        let Some(capture) = self.midi_capture.take() else { return };
+        if let Some(handle) = &self.audio_handle {
+            handle.stop_midi_capture();
+        }
+        let path = capture.path.clone();
+        match capture.finish() {
+            Ok(()) => self.set_status(log::Level::Info, format!("MIDI 录制已保存至 {}", path.display())),
+            Err(e) => self.set_status(log::Level::Error, e),
+        }
+    }
+
+    /// 应用音色库列表：引擎参数未变时热重载音色库，否则完整重启引擎
+    pub(crate) fn apply_soundfonts(&mut self) {
+        match &mut self.audio_handle {
+            Some(handle) if handle.config == self.realtime_config => {
+                handle.reload_soundfonts(self.soundfonts.clone());
+                self.save_settings();
+                self.is_dirty = false;
+                self.set_status(log::Level::Info, "正在重新加载音色库...");
             }
-            if let Some(vec) = fonts.families.get_mut(&egui::FontFamily::Monospace) {
-                vec.insert(0, "msyh".to_owned());
+            _ => self.restart_engine(),
+        }
+    }
+
+    pub(crate) fn save_settings(&self) {
+        let settings = AppSettings {
+            version: settings::SETTINGS_VERSION,
+            soundfonts: self.soundfonts.clone(),
+            realtime: self.realtime_config.clone(),
+            render: self.render_config.clone(),
+            midi_port_slot: self.midi_port_slot.clone(),
+            start_minimized: self.start_minimized,
+            keep_engine_on_failure: self.keep_engine_on_failure,
+            unregister_port_on_exit: self.unregister_port_on_exit,
+            panic_hotkey: self.panic_hotkey.clone(),
+            theme: self.theme,
+            accent_color: self.accent_color,
+            status_auto_hide_secs: self.status_auto_hide_secs,
+            window_width: self.window_size.map(|s| s.x),
+            window_height: self.window_size.map(|s| s.y),
+            window_x: self.window_pos.map(|p| p.x),
+            window_y: self.window_pos.map(|p| p.y),
+        };
+        settings.save();
+        settings::write_driver_config(&self.realtime_config);
+    }
+
+    // 处理拖放到窗口上的文件：音色库加入列表，MIDI 设为渲染输入，并切换到对应页面
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped: Vec<_> = ctx.input(|i| i.raw.dropped_files.iter().filter_map(|f| f.path.clone()).collect());
+        if dropped.is_empty() {
+            return;
+        }
+
+        let mut ignored = Vec::new();
+        for path in dropped {
+            if path.is_dir() {
+                self.add_soundfont_folder(&path);
+                self.active_tab = Tab::Soundfonts;
+                continue;
+            }
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            match ext.as_str() {
+                "sf2" | "sf3" | "sfz" => {
+                    self.set_status(log::Level::Info, format!("已添加音色库 {}", path.display()));
+                    self.soundfonts.push(SoundfontEntry::new(path));
+                    self.is_dirty = true;
+                    self.active_tab = Tab::Soundfonts;
+                }
+                "mid" | "midi" => {
+                    self.render_config.midi_path = path.to_string_lossy().to_string();
+                    self.set_status(log::Level::Info, format!("已设为渲染输入 {}", path.display()));
+                    self.active_tab = Tab::RenderSettings;
+                }
+                _ => ignored.push(path.file_name().unwrap_or_default().to_string_lossy().to_string()),
             }
+        }
+
+        if !ignored.is_empty() {
+            self.set_status(log::Level::Warn, format!("已忽略不支持的文件: {}", ignored.join(", ")));
+        }
+    }
+
+    /// 递归查找文件夹中的音色库，按路径排序后加入列表末尾，已在列表中的跳过
+    pub(crate) fn add_soundfont_folder(&mut self, dir: &std::path::Path) {
+        let mut found = Vec::new();
+        if let Err(e) = find_soundfonts(dir, &mut found) {
+            self.set_status(log::Level::Error, format!("错误：无法读取文件夹 {}: {}", dir.display(), e));
+            return;
+        }
+        if found.len() > MAX_FOLDER_SOUNDFONTS {
+            self.set_status(log::Level::Warn, format!(
+                "文件夹 {} 中的音色库超过 {} 个，未添加。请选择更具体的子文件夹。",
+                dir.display(), MAX_FOLDER_SOUNDFONTS
+            ));
+            return;
+        }
+        found.sort();
+
+        let total = found.len();
+        let mut added = 0;
+        for path in found {
+            if !self.soundfonts.iter().any(|sf| sf.path == path) {
+                self.soundfonts.push(SoundfontEntry::new(path));
+                added += 1;
+            }
+        }
+        if added > 0 {
+            self.is_dirty = true;
+        }
+        log::info!("从文件夹 {} 添加了 {} 个音色库 (共找到 {} 个)", dir.display(), added, total);
+        if total == 0 {
+            self.set_status(log::Level::Warn, format!("文件夹 {} 中没有找到音色库 (SF2 / SF3 / SFZ)。", dir.display()));
+        } else if added < total {
+            self.set_status(log::Level::Info, format!("已从 {} 添加 {} 个音色库，跳过 {} 个已在列表中的。", dir.display(), added, total - added));
         } else {
-            eprintln!("警告: 找不到微软雅黑字体 ({})，中文可能无法正常显示。", font_path);
+            self.set_status(log::Level::Info, format!("已从 {} 添加 {} 个音色库。", dir.display(), added));
         }
+    }
+
+    // 记录窗口位置与大小，关闭窗口时写入设置
+    fn track_window_geometry(&mut self, ctx: &egui::Context) {
+        let (outer, inner, monitor, close_requested) = ctx.input(|i| {
+            let vp = i.viewport();
+            (vp.outer_rect, vp.inner_rect, vp.monitor_size, vp.close_requested())
+        });
+
+        // 首次拿到窗口信息时检查：如果保存的位置在已拔掉的显示器上 (找不到所在显示器)，挪回主屏幕
+        if !self.window_checked && let Some(outer) = outer {
+            self.window_checked = true;
+            match monitor {
+                None => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(100.0, 100.0)));
+                }
+                Some(monitor) if outer.width() > monitor.x || outer.height() > monitor.y => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(0.0, 0.0)));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(monitor * 0.9));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(outer) = outer {
+            self.window_pos = Some(outer.min);
+        }
+        if let Some(inner) = inner {
+            self.window_size = Some(inner.size());
+        }
+
+        if close_requested {
+            self.save_settings();
+            if self.unregister_port_on_exit && matches!(self.port_registration, PortRegistration::Registered(_)) {
+                self.unregister_port();
+            }
+        }
+    }
+
+    fn setup_custom_fonts(ctx: &egui::Context) {
+        let mut fonts = egui::FontDefinitions::default();
+
+        // 依次尝试候选字体，使用第一个能读取的
+        let loaded = Self::cjk_font_candidates()
+            .into_iter()
+            .find_map(|path| std::fs::read(&path).ok().map(|data| (path, data)));
+
+        let font_data = if let Some((font_path, font_data)) = loaded {
+            log::info!("使用中文字体: {}", font_path.display());
+            egui::FontData::from_owned(font_data)
+        } else {
+            log::warn!(
+                "找不到系统中文字体，改用内置的点阵字体。可将任意中文字体放到程序目录下的 fonts/{} 获得更好的显示效果。",
+                FALLBACK_FONT_FILE
+            );
+            egui::FontData::from_static(EMBEDDED_CJK_FONT)
+        };
+        Self::insert_cjk_font(&mut fonts, font_data);
 
         ctx.set_fonts(fonts);
     }
 
-    fn register_midi_port() {
-        println!("尝试将虚拟 MIDI 端口 [{}] 写入注册表...", MIDI_PORT_NAME);
-        let reg_key = "HKLM\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\Drivers32";
-        
+    // 把中文字体放在比例字体与等宽字体的最前面
+    fn insert_cjk_font(fonts: &mut egui::FontDefinitions, font_data: egui::FontData) {
+        fonts.font_data.insert("cjk".to_owned(), std::sync::Arc::new(font_data));
+
+        for family in [egui::FontFamily::Proportional, egui::FontFamily::Monospace] {
+            fonts.families.entry(family).or_default().insert(0, "cjk".to_owned());
+        }
+    }
+
+    // 中文字体的候选路径：程序目录下随附的字体优先，其次是各系统常见的中文字体
+    fn cjk_font_candidates() -> Vec<std::path::PathBuf> {
+        let mut candidates = Vec::new();
+
+        if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(|p| p.to_path_buf())) {
+            candidates.push(dir.join("fonts").join(FALLBACK_FONT_FILE));
+        }
+
+        // Windows：微软雅黑 (含 Light 版)、黑体、宋体。精简版系统 (N / LTSC) 或 Wine 下可能只有其中一部分
+        let windir = std::env::var_os("WINDIR").map(std::path::PathBuf::from).unwrap_or_else(|| "C:\\Windows".into());
+        for name in ["msyh.ttc", "msyhl.ttc", "simhei.ttf", "simsun.ttc"] {
+            candidates.push(windir.join("Fonts").join(name));
+        }
+
+        // Linux / macOS 常见的中文字体
+        for path in [
+            "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+            "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+            "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+            "/usr/share/fonts/truetype/wqy/wqy-zenhei.ttc",
+            "/System/Library/Fonts/PingFang.ttc",
+            "/System/Library/Fonts/STHeiti Light.ttc",
+        ] {
+            candidates.push(path.into());
+        }
+
+        candidates
+    }
+
+    // 注册虚拟 MIDI 端口：已注册过则沿用原槽位，否则优先使用上次的槽位，再依次寻找空闲的 midi1 ~ midi9
+    // 不会覆盖其他驱动 (例如 OmniMIDI) 占用的槽位
+    fn register_midi_port(preferred: Option<&str>) -> PortRegistration {
+        let slots = Self::query_midi_slots();
+        if let Some((slot, _)) = slots.iter().find(|(_, dll)| is_our_driver(dll)) {
+            log::info!("虚拟 MIDI 端口已注册 (端口: {})", slot);
+            return PortRegistration::Registered(slot.clone());
+        }
+
+        let is_free = |slot: &str| !slots.iter().any(|(name, dll)| name.eq_ignore_ascii_case(slot) && !dll.is_empty());
+        let Some(slot) = preferred
+            .filter(|p| MIDI_PORT_SLOTS.contains(p) && is_free(p))
+            .or_else(|| MIDI_PORT_SLOTS.into_iter().find(|s| is_free(s)))
+        else {
+            log::warn!("midi1 ~ midi9 均已被其他驱动占用，无法注册虚拟 MIDI 端口。");
+            return PortRegistration::NoFreeSlot;
+        };
+
+        log::info!("尝试将虚拟 MIDI 端口 [{}] 写入注册表...", slot);
         let status = Command::new("reg")
-            .args(&["add", reg_key, "/v", MIDI_PORT_NAME, "/t", "REG_SZ", "/d", "xxsynth_winmm.dll", "/f"])
+            .args(["add", DRIVERS32_KEY, "/v", slot, "/t", "REG_SZ", "/d", DRIVER_DLL, "/f"])
             .status();
 
         match status {
-            Ok(s) if s.success() => println!("注册表写入成功！(端口: {})", MIDI_PORT_NAME),
+            Ok(s) if s.success() => {
+                log::info!("注册表写入成功！(端口: {})", slot);
+                PortRegistration::Registered(slot.to_string())
+            }
             _ => {
-                println!("普通权限写入失败，准备通过 PowerShell 申请 UAC 提权...");
-                let ps_script = format!(
-                    "Start-Process reg -ArgumentList 'add \"{}\" /v {} /t REG_SZ /d xxsynth_winmm.dll /f' -Verb RunAs -WindowStyle Hidden",
-                    reg_key, MIDI_PORT_NAME
-                );
-                
-                let admin_status = Command::new("powershell")
-                    .args(&["-Command", &ps_script])
-                    .status();
-
-                match admin_status {
-                    Ok(s) if s.success() => println!("提权请求已发送，请在 UAC 弹窗中点击“是”。"),
-                    _ => eprintln!("提权请求失败！如果需要使用 MIDI 端口，请手动以管理员运行程序。"),
-                }
+                log::info!("普通权限写入失败，准备通过 PowerShell 申请 UAC 提权...");
+                Self::elevate_register(slot)
             }
         }
     }
 
+    // 通过 PowerShell 申请 UAC 提权后写入注册表，等待完成后重新读取确认结果
+    fn elevate_register(slot: &str) -> PortRegistration {
+        let ps_script = format!(
+            "Start-Process reg -ArgumentList 'add \"{}\" /v {} /t REG_SZ /d {} /f' -Verb RunAs -WindowStyle Hidden -Wait",
+            DRIVERS32_KEY, slot, DRIVER_DLL
+        );
+        let admin_status = Command::new("powershell")
+            .args(["-Command", &ps_script])
+            .status();
+        if !matches!(admin_status, Ok(s) if s.success()) {
+            log::error!("提权请求失败！如果需要使用 MIDI 端口，请手动以管理员运行程序。");
+            return PortRegistration::Failed;
+        }
+
+        let registered = Self::query_midi_slots()
+            .iter()
+            .any(|(name, dll)| name.eq_ignore_ascii_case(slot) && is_our_driver(dll));
+        if registered {
+            log::info!("注册表写入成功！(端口: {})", slot);
+            PortRegistration::Registered(slot.to_string())
+        } else {
+            log::error!("虚拟 MIDI 端口未注册，可能是 UAC 提权被拒绝。");
+            PortRegistration::Failed
+        }
+    }
+
+    // 读取 Drivers32 下所有 midi 槽位及其驱动 DLL
+    fn query_midi_slots() -> Vec<(String, String)> {
+        let Ok(output) = Command::new("reg").args(["query", DRIVERS32_KEY]).output() else {
+            return Vec::new();
+        };
+        // 输出形如 "    midi7    REG_SZ    xxsynth_winmm.dll"
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (name, value) = line.split_once("REG_SZ")?;
+                let name = name.trim();
+                name.to_ascii_lowercase().starts_with("midi").then(|| (name.to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+
+    /// 重新尝试注册虚拟 MIDI 端口 (需要时会弹出 UAC 提权)
+    pub(crate) fn retry_port_registration(&mut self) {
+        self.port_registration = Self::register_midi_port(self.midi_port_slot.as_deref());
+        let (level, message) = match &self.port_registration {
+            PortRegistration::Registered(slot) => {
+                self.midi_port_slot = Some(slot.clone());
+                self.save_settings();
+                (log::Level::Info, format!("虚拟 MIDI 端口已注册 ({})，重启宿主程序后即可看到 XXSynth 端口。", slot))
+            }
+            PortRegistration::Failed => (log::Level::Error, "虚拟 MIDI 端口注册失败，请在 UAC 弹窗中点击“是”，或以管理员身份运行程序。".to_string()),
+            PortRegistration::NoFreeSlot => (log::Level::Error, "midi1 ~ midi9 均已被其他驱动占用，请先卸载不用的 MIDI 驱动。".to_string()),
+            PortRegistration::Unregistered => (log::Level::Warn, "虚拟 MIDI 端口未注册。".to_string()),
+        };
+        self.set_status(level, message);
+    }
+
+    // 从注册表删除指定槽位，普通权限失败时申请 UAC 提权，完成后重新读取确认结果
+    fn unregister_midi_port(slot: &str) -> bool {
+        log::info!("尝试从注册表删除虚拟 MIDI 端口 [{}]...", slot);
+        let status = Command::new("reg")
+            .args(["delete", DRIVERS32_KEY, "/v", slot, "/f"])
+            .status();
+        if !matches!(status, Ok(s) if s.success()) {
+            log::info!("普通权限删除失败，准备通过 PowerShell 申请 UAC 提权...");
+            let ps_script = format!(
+                "Start-Process reg -ArgumentList 'delete \"{}\" /v {} /f' -Verb RunAs -WindowStyle Hidden -Wait",
+                DRIVERS32_KEY, slot
+            );
+            let _ = Command::new("powershell").args(["-Command", &ps_script]).status();
+        }
+
+        let removed = !Self::query_midi_slots()
+            .iter()
+            .any(|(name, dll)| name.eq_ignore_ascii_case(slot) && is_our_driver(dll));
+        if removed {
+            log::info!("已从注册表删除虚拟 MIDI 端口 (端口: {})", slot);
+        } else {
+            log::error!("虚拟 MIDI 端口 [{}] 删除失败，可能是 UAC 提权被拒绝。", slot);
+        }
+        removed
+    }
+
+    /// 卸载虚拟 MIDI 端口：删除注册表中所有指向本程序驱动的槽位，不会动其他驱动的槽位
+    /// 卸载程序前执行，避免 DLL 删除后留下失效的驱动项
+    pub(crate) fn unregister_port(&mut self) {
+        let slots: Vec<String> = Self::query_midi_slots()
+            .into_iter()
+            .filter(|(_, dll)| is_our_driver(dll))
+            .map(|(slot, _)| slot)
+            .collect();
+        if slots.is_empty() {
+            self.port_registration = PortRegistration::Unregistered;
+            self.set_status(log::Level::Warn, "注册表中没有 XXSynth 的虚拟 MIDI 端口。");
+            return;
+        }
+
+        let failed: Vec<&str> = slots.iter().filter(|slot| !Self::unregister_midi_port(slot)).map(String::as_str).collect();
+        if failed.is_empty() {
+            self.port_registration = PortRegistration::Unregistered;
+            self.set_status(log::Level::Info, format!("虚拟 MIDI 端口已卸载 ({})，下次启动程序时会重新注册。", slots.join(", ")));
+        } else {
+            let message = format!("虚拟 MIDI 端口卸载失败 ({})，请在 UAC 弹窗中点击“是”，或以管理员身份运行程序。", failed.join(", "));
+            self.set_status(log::Level::Error, message);
+        }
+    }
+
+    pub(crate) fn render_shared(&self) -> RenderShared {
+        RenderShared {
+            is_rendering: self.is_rendering.clone(),
+            progress: self.render_progress.clone(),
+            rendered_secs: self.render_secs.clone(),
+            error: self.render_error.clone(),
+            child: self.render_child.clone(),
+            cancel: self.render_cancel.clone(),
+        }
+    }
+
+    /// 取消渲染：内置渲染器在下一块停止，外部子进程直接结束；渲染线程随后上报“已取消”并解除界面锁定
+    pub(crate) fn cancel_render(&mut self) {
+        self.render_cancel.store(true, Ordering::SeqCst);
+        if let Some(mut child) = self.render_child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
     pub(crate) fn is_running(&self) -> bool {
         self.audio_handle.is_some()
     }
+
+    // 每秒记录一次封包速率，引擎停止后清空历史
+    fn sample_packet_rate(&mut self) {
+        let Some(handle) = &self.audio_handle else {
+            self.packet_rate_history.clear();
+            return;
+        };
+        if self.last_rate_sample.elapsed() < std::time::Duration::from_secs(1) {
+            return;
+        }
+        self.last_rate_sample = std::time::Instant::now();
+        self.packet_rate_history.push_back(handle.packet_rate.load(Ordering::Relaxed));
+        if self.packet_rate_history.len() > PACKET_RATE_HISTORY {
+            self.packet_rate_history.pop_front();
+        }
+    }
+
+    /// 设置状态栏消息并记入历史，级别由调用处给出 (同一帧内的多条消息都会保留在历史中)
+    pub(crate) fn set_status(&mut self, level: log::Level, message: impl Into<String>) {
+        let message = message.into();
+        if self.status_history.len() >= STATUS_HISTORY_LEN {
+            self.status_history.pop_front();
+        }
+        self.status_history.push_back(LogEntry {
+            elapsed: logger::elapsed(),
+            level,
+            message: message.clone(),
+        });
+        self.status_message = message;
+        self.status_level = level;
+        self.status_changed_at = std::time::Instant::now();
+    }
+
+    // 状态栏消息按设置在一段时间后自动清除
+    fn track_status_message(&mut self, ctx: &egui::Context) {
+        if self.status_auto_hide_secs > 0 && !self.status_message.is_empty() {
+            let hide_after = std::time::Duration::from_secs(self.status_auto_hide_secs as u64);
+            let shown = self.status_changed_at.elapsed();
+            if shown >= hide_after {
+                self.status_message.clear();
+            } else {
+                ctx.request_repaint_after(hide_after - shown);
+            }
+        }
+    }
+
+    // 欠载次数增加时闪烁警告几秒，引擎重启后计数从 0 开始
+    fn track_underruns(&mut self, ctx: &egui::Context) {
+        let underruns = self.audio_handle.as_ref().map_or(0, |h| h.underruns.load(Ordering::Relaxed));
+        if underruns > self.underruns_seen {
+            self.underrun_warning_until = Some(std::time::Instant::now() + UNDERRUN_WARNING_DURATION);
+        }
+        self.underruns_seen = underruns;
+        if self.underrun_warning_until.is_some_and(|until| std::time::Instant::now() < until) {
+            ctx.request_repaint_after(std::time::Duration::from_millis(250));
+        } else {
+            self.underrun_warning_until = None;
+        }
+    }
+
+    /// 开始 / 停止渲染试听：用内置播放器把要渲染的 MIDI 的开头送进实时引擎，不必为了听音色渲染整首
+    pub(crate) fn toggle_render_preview(&mut self) {
+        if self.render_preview {
+            self.midi_player = None;
+            self.render_preview = false;
+            self.set_status(log::Level::Info, "已停止试听。");
+            return;
+        }
+        let Some(handle) = self.audio_handle.as_ref().filter(|h| h.is_running.load(Ordering::Relaxed)) else {
+            self.set_status(log::Level::Warn, "试听使用实时引擎，请先在【实时设置】中启动引擎。");
+            return;
+        };
+        if self.render_config.midi_path.is_empty() {
+            self.set_status(log::Level::Error, "错误：请先选择输入的 MIDI 文件！");
+            return;
+        }
+
+        // 先关闭旧的播放器，避免两个文件同时发声
+        self.midi_player = None;
+        let path = std::path::PathBuf::from(&self.render_config.midi_path);
+        match MidiPlayer::load(&path, handle.packet_sender()) {
+            Ok(player) => {
+                player.play();
+                self.midi_player = Some(player);
+                self.render_preview = true;
+                self.set_status(log::Level::Info, format!("正在试听 {} 的前 {} 秒", path.display(), RENDER_PREVIEW_SECS));
+            }
+            Err(e) => self.set_status(log::Level::Error, e),
+        }
+    }
+
+    // 试听到达时长或乐曲播完时停止；播放器被关闭 (换文件、停止引擎) 时结束试听状态
+    fn track_render_preview(&mut self, ctx: &egui::Context) {
+        if !self.render_preview {
+            return;
+        }
+        let Some(player) = &self.midi_player else {
+            self.render_preview = false;
+            return;
+        };
+        let position = player.position();
+        if position >= RENDER_PREVIEW_SECS.min(player.duration) {
+            self.midi_player = None;
+            self.render_preview = false;
+            self.set_status(log::Level::Info, "试听结束。");
+        } else {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+    }
+
+    /// 按设置切换明暗主题
+    pub(crate) fn apply_theme(&self, ctx: &egui::Context) {
+        ctx.set_theme(match self.theme {
+            ThemeMode::System => egui::ThemePreference::System,
+            ThemeMode::Light => egui::ThemePreference::Light,
+            ThemeMode::Dark => egui::ThemePreference::Dark,
+        });
+    }
+
+    // 设置了强调色时返回强调色，否则返回该处原本的颜色
+    pub(crate) fn accent_or(&self, default: egui::Color32) -> egui::Color32 {
+        self.accent_color.map(|[r, g, b]| egui::Color32::from_rgb(r, g, b)).unwrap_or(default)
+    }
+
+    /// 停止引擎，同时停止依附于引擎的播放与录音
+    pub(crate) fn stop_engine(&mut self) {
+        // 正在重启时等重启完成，再把刚启动的引擎一并停止
+        if let Some(pending) = self.pending_restart.take()
+            && let Ok(outcome) = pending.join()
+        {
+            for mut handle in outcome.result.into_iter().chain(outcome.fallback) {
+                handle.stop();
+            }
+        }
+        self.midi_player = None;
+        self.stop_recording();
+        self.stop_midi_capture();
+        if let Some(mut handle) = self.audio_handle.take() {
+            handle.stop();
+        }
+        audio::clear_soundfont_cache();
+    }
+
+    /// 按当前设置重新注册全局紧急静音快捷键 (先注销旧的)
+    pub(crate) fn apply_panic_hotkey(&mut self) {
+        self.global_hotkey = None;
+        self.hotkey_error = None;
+        let Some(binding) = &self.panic_hotkey else { return };
+        if !cfg!(windows) {
+            return;
+        }
+        let target = self.panic_target.clone();
+        let result = GlobalHotkey::register(binding, move || {
+            log::warn!("全局快捷键：紧急静音");
+            if let Some((tx, socket_count)) = target.lock().unwrap().as_ref() {
+                for packet in audio::panic_packets(*socket_count) {
+                    let _ = tx.send(packet);
+                }
+            }
+        });
+        match result {
+            Ok(hotkey) => {
+                log::info!("已注册全局紧急静音快捷键 {}", binding.label());
+                self.global_hotkey = Some(hotkey);
+            }
+            Err(e) => {
+                log::warn!("无法注册全局快捷键: {}", e);
+                self.hotkey_error = Some(e);
+            }
+        }
+    }
+
+    // 处理托盘菜单发来的操作
+    fn handle_tray_actions(&mut self) {
+        let Some(tray) = &self.tray else {
+            return;
+        };
+        let actions: Vec<_> = std::iter::from_fn(|| tray.try_action()).collect();
+        for action in actions {
+            match action {
+                // 恢复窗口与退出已在托盘事件回调中完成
+                tray::TrayAction::Show | tray::TrayAction::Quit => {}
+                tray::TrayAction::StartEngine => {
+                    if !self.is_running() {
+                        self.restart_engine();
+                    }
+                }
+                tray::TrayAction::StopEngine => {
+                    self.stop_engine();
+                    self.set_status(log::Level::Info, "音频引擎已从托盘菜单停止。");
+                }
+            }
+        }
+    }
 }
 
 // 主界面的全局 Layout 逻辑
 impl eframe::App for XXSynthApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.track_window_geometry(ctx);
+        self.handle_tray_actions();
+        self.poll_restart(ctx);
+        *self.panic_target.lock().unwrap() = self.audio_handle.as_ref().map(|h| (h.packet_sender(), h.config.udp_ports().len()));
+        if let Some(tray) = &self.tray {
+            tray.set_engine(self.audio_handle.as_ref().map(|h| h.is_running.clone()));
+        }
+        self.sample_packet_rate();
+        self.track_underruns(ctx);
+        self.track_render_preview(ctx);
+
         // 捕获渲染子线程汇报的错误/完成消息
-        if let Ok(mut err) = self.render_error.lock() {
-            if let Some(msg) = err.take() {
-                self.status_message = msg;
+        let render_result = self.render_error.lock().ok().and_then(|mut err| err.take());
+        if let Some(result) = render_result {
+            match &result {
+                Ok(msg) => self.set_status(log::Level::Info, msg.clone()),
+                Err(msg) => self.set_status(log::Level::Error, msg.clone()),
             }
+            self.render_result = Some(result);
+            self.render_started = None;
+        }
+
+        // 捕获音频线程汇报的警告
+        let engine_message = self.engine_message.lock().ok().and_then(|mut guard| guard.take());
+        if let Some(msg) = engine_message {
+            self.set_status(log::Level::Warn, msg);
         }
+        self.track_status_message(ctx);
 
         let is_loading = *self.load_progress.lock().unwrap() < 1.0;
         let is_rendering = self.is_rendering.load(Ordering::SeqCst);
         let is_locked = is_loading || is_rendering;
 
+        // 加载或渲染期间不接受拖放
+        if !is_locked {
+            self.handle_dropped_files(ctx);
+        }
+
         // 模态加载进度弹窗
         if is_loading {
             ctx.set_cursor_icon(egui::CursorIcon::Wait);
@@ -219,8 +1100,23 @@ impl eframe::App for XXSynthApp {
                             .show_percentage()
                             .animate(true)
                             .desired_width(300.0));
-                        ui.add_space(15.0);
-                        ui.label("加载大型音色库可能较久");
+                        ui.add_space(10.0);
+                        match self.audio_handle.as_ref().and_then(|h| h.loading_file()) {
+                            Some(file) => ui.label(format!("正在加载 {}", file)),
+                            None => ui.label("加载大型音色库可能较久"),
+                        };
+                        ui.add_space(10.0);
+                        // 取消时停止引擎，正在加载的那个文件会在后台读完后丢弃
+                        if ui.add_enabled(self.audio_handle.is_some(), egui::Button::new("取消")).clicked()
+                            && let Some(mut handle) = self.audio_handle.take()
+                        {
+                            self.midi_player = None;
+                            self.stop_recording();
+                            self.stop_midi_capture();
+                            handle.stop();
+                            if let Ok(mut p) = self.load_progress.lock() { *p = 1.0; }
+                            self.set_status(log::Level::Warn, "已取消加载，引擎已停止。");
+                        }
                     });
                     ui.add_space(15.0);
                 });
@@ -238,18 +1134,77 @@ impl eframe::App for XXSynthApp {
                     ui.vertical_centered(|ui| {
                         ui.heading("🚀 正在将 MIDI 渲染至音频文件...");
                         ui.add_space(15.0);
+
+                        // 队列渲染时显示整体进度
+                        if let Ok(queue) = self.render_queue.lock()
+                            && let Some(current) = queue.iter().position(|item| item.status == render::QueueStatus::Rendering)
+                        {
+                            ui.label(format!("队列 {} / {}：{}", current + 1, queue.len(), queue[current].midi_path));
+                            ui.add_space(5.0);
+                        }
                         let pct = *self.render_progress.lock().unwrap();
-                        ui.add(egui::ProgressBar::new(pct)
-                            .show_percentage()
-                            .animate(true)
-                            .desired_width(300.0));
+                        if self.render_config.use_external_renderer || pct > 0.0 {
+                            ui.add(egui::ProgressBar::new(pct)
+                                .show_percentage()
+                                .animate(true)
+                                .desired_width(300.0));
+                        } else {
+                            // 读不到乐曲总时长时只能显示已渲染的时长
+                            let secs = *self.render_secs.lock().unwrap();
+                            ui.add(egui::ProgressBar::new(0.0)
+                                .text(format!("已渲染 {:.1} 秒", secs))
+                                .animate(true)
+                                .desired_width(300.0));
+                        }
+
+                        // 按已用时间和完成比例估算剩余时间，进度太小时估算不准，先不显示
+                        if let Some(started) = self.render_started {
+                            let elapsed = started.elapsed().as_secs_f64();
+                            let eta = if pct > 0.01 && pct < 1.0 {
+                                let remaining = (elapsed * (1.0 - pct as f64) / pct as f64) as u64;
+                                format!("，预计剩余 {}:{:02}", remaining / 60, remaining % 60)
+                            } else {
+                                String::new()
+                            };
+                            ui.label(format!("已用时 {}:{:02}{}", elapsed as u64 / 60, elapsed as u64 % 60, eta));
+                        }
                         ui.add_space(15.0);
                         ui.label("请勿关闭程序，渲染时间取决于乐曲复杂度和多线程配置。");
+                        if self.render_config.use_external_renderer {
+                            ui.add_space(10.0);
+                            if ui.button("⏹ 取消渲染").clicked() {
+                                self.cancel_render();
+                            }
+                        }
                     });
                     ui.add_space(15.0);
                 });
         }
 
+        // 渲染结束后弹窗显示结果，直到用户确认
+        if !is_locked && let Some(result) = &self.render_result {
+            let (title, text, color) = match result {
+                Ok(msg) => ("✅ 渲染完成", msg.clone(), egui::Color32::from_rgb(0, 160, 0)),
+                Err(msg) => ("❌ 渲染失败", msg.clone(), egui::Color32::RED),
+            };
+            let mut close = false;
+            egui::Window::new(title)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.add_space(10.0);
+                        ui.label(egui::RichText::new(text).color(color).strong());
+                        ui.add_space(10.0);
+                        close = ui.button("确定").clicked();
+                    });
+                });
+            if close {
+                self.render_result = None;
+            }
+        }
+
         // 顶部导航栏
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.add_enabled_ui(!is_locked, |ui| {
@@ -257,6 +1212,11 @@ impl eframe::App for XXSynthApp {
                     ui.selectable_value(&mut self.active_tab, Tab::Soundfonts, "🎹 音色库");
                     ui.selectable_value(&mut self.active_tab, Tab::RealtimeSettings, "\u{2699} 实时设置");
                     ui.selectable_value(&mut self.active_tab, Tab::RenderSettings, "🎬 渲染导出");
+                    ui.selectable_value(&mut self.active_tab, Tab::Keyboard, "🎼 键盘");
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.toggle_value(&mut self.show_about, "ℹ 关于");
+                        ui.toggle_value(&mut self.show_log_panel, "📜 日志");
+                    });
                 });
             });
         });
@@ -266,17 +1226,39 @@ impl eframe::App for XXSynthApp {
             ui.add_enabled_ui(!is_locked, |ui| {
                 ui.horizontal(|ui| {
                     let status_color = if self.is_running() { 
-                        egui::Color32::from_rgba_unmultiplied(0, 200, 0, 255) 
+                        self.accent_or(egui::Color32::from_rgba_unmultiplied(0, 200, 0, 255))
                     } else { 
                         egui::Color32::from_rgba_unmultiplied(200, 0, 0, 255) 
                     };
                     ui.colored_label(status_color, if self.is_running() { "● 正在运行" } else { "● 已停止" });
+                    if let PortRegistration::Registered(slot) = &self.port_registration {
+                        ui.separator();
+                        ui.label(format!("MIDI 端口: {}", slot)).on_hover_text("虚拟 MIDI 驱动在注册表 Drivers32 中使用的槽位");
+                    }
                     ui.separator();
+                    self.ui_status_history(ui);
                     ui.label(&self.status_message);
                 });
             });
         });
 
+        self.ui_about_window(ctx);
+
+        // 日志面板，位于状态栏上方，可拖动调整高度
+        if self.show_log_panel {
+            egui::TopBottomPanel::bottom("log_panel")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, |ui| self.ui_log_panel(ui));
+        }
+
+        // 虚拟端口未注册时常驻提示，这是安装后最常见的问题
+        if cfg!(windows) && !matches!(self.port_registration, PortRegistration::Registered(_)) {
+            egui::TopBottomPanel::top("port_banner").show(ctx, |ui| {
+                ui.add_enabled_ui(!is_locked, |ui| self.ui_port_banner(ui));
+            });
+        }
+
         // 中央内容区路由
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.add_enabled_ui(!is_locked, |ui| {
@@ -284,29 +1266,90 @@ impl eframe::App for XXSynthApp {
                     Tab::Soundfonts => self.ui_soundfonts(ui),
                     Tab::RealtimeSettings => self.ui_realtime(ui),
                     Tab::RenderSettings => self.ui_render(ui),
+                    Tab::Keyboard => self.ui_keyboard(ui),
                 }
             });
         });
+        self.ui_keyboard_viewport(ctx);
 
         if is_locked {
             ctx.request_repaint();
+        } else if self.is_running() {
+            // 引擎运行时定期刷新，以便显示实时复音数
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
         }
     }
 }
 
 fn main() -> eframe::Result<()> {
-    env_logger::init();
+    logger::init();
+
+    let cli = <headless::Cli as clap::Parser>::parse();
+    if cli.render.is_some() {
+        headless::attach_console();
+        if let Err(e) = headless::render(cli) {
+            log::error!("渲染失败: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if cli.headless {
+        headless::attach_console();
+        if let Err(e) = headless::run(cli) {
+            log::error!("启动失败: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let settings = AppSettings::load();
+
+    // 恢复上次的窗口大小与位置，尺寸限制在合理范围内
+    let width = settings.window_width.unwrap_or(680.0).clamp(400.0, 8192.0);
+    let height = settings.window_height.unwrap_or(580.0).clamp(300.0, 8192.0);
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_inner_size([width, height])
+        .with_title("XXSynth");
+    if let (Some(x), Some(y)) = (settings.window_x, settings.window_y) {
+        viewport = viewport.with_position([x, y]);
+    }
+    // 只有支持托盘的平台才隐藏启动，否则窗口将无法找回
+    if settings.start_minimized && cfg!(windows) {
+        viewport = viewport.with_visible(false);
+    }
 
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([680.0, 580.0])
-            .with_title("XXSynth"),
+        viewport,
         ..Default::default()
     };
 
     eframe::run_native(
         "xxsynth-app",
         options,
-        Box::new(|cc| Ok(Box::new(XXSynthApp::new(cc)))),
+        Box::new(|cc| Ok(Box::new(XXSynthApp::new(cc, settings)))),
     )
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 内置字体能被 egui 读取，且覆盖界面上用到的汉字与标点
+    #[test]
+    fn embedded_cjk_font_covers_ui_text() {
+        let mut fonts = egui::FontDefinitions::empty();
+        XXSynthApp::insert_cjk_font(&mut fonts, egui::FontData::from_static(EMBEDDED_CJK_FONT));
+        let ctx = egui::Context::default();
+        ctx.set_fonts(fonts);
+
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            ctx.fonts_mut(|fonts| {
+                let font_id = egui::FontId::proportional(14.0);
+                assert!(fonts.has_glyphs(&font_id, "音色库加载失败【紧急静音】：参数检查，已停止。"));
+                // 全角字形占满一个字号的宽度
+                assert!(fonts.glyph_width(&font_id, '中') > 10.0);
+            });
+            let galley = ctx.fonts_mut(|fonts| fonts.layout_no_wrap("渲染完成".to_owned(), egui::FontId::proportional(14.0), egui::Color32::WHITE));
+            assert!(galley.size().x > 0.0);
+        });
+    }
+}
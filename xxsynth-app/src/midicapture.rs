@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use midly::num::{u15, u24, u28, u7};
+use midly::{Arena, Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+// 录制收到的 MIDI 事件：(到达时刻, 轨道序号 = 监听端口序号 * 16 + 驱动端口, 原始消息字节)
+pub type CaptureEvent = (Instant, u32, Vec<u8>);
+
+// 每个四分音符 1000 tick，速度固定为每拍 1 秒，1 tick 正好是 1 毫秒
+const TICKS_PER_QUARTER: u16 = 1000;
+const TEMPO_US: u32 = 1_000_000;
+
+// MIDI 事件录制：把引擎收到的事件按到达时刻记录下来，停止时写成 Type-1 标准 MIDI 文件
+// 每个驱动端口一个音轨 (带端口号元事件)，可以拿去重新渲染或在编辑器中修改
+pub struct MidiCapture {
+    pub path: PathBuf,
+    pub started_at: Instant,
+    pub event_count: Arc<AtomicU64>, // 已记录的事件数
+    thread_handle: Option<thread::JoinHandle<Result<(), String>>>,
+}
+
+impl MidiCapture {
+    /// 启动记录线程，返回录制器与事件旁路的发送端；文件在停止时才写入
+    pub fn start(path: &Path) -> Result<(Self, Sender<CaptureEvent>), String> {
+        // 先确认能创建文件，免得录了半天才发现路径不可写
+        std::fs::File::create(path).map_err(|e| format!("无法创建 MIDI 文件 {}: {}", path.display(), e))?;
+
+        let (capture_tx, capture_rx) = mpsc::channel::<CaptureEvent>();
+        let started_at = Instant::now();
+        let event_count = Arc::new(AtomicU64::new(0));
+        let count_clone = event_count.clone();
+        let path_clone = path.to_path_buf();
+
+        let thread_handle = thread::spawn(move || capture_loop(capture_rx, started_at, count_clone, &path_clone));
+
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                started_at,
+                event_count,
+                thread_handle: Some(thread_handle),
+            },
+            capture_tx,
+        ))
+    }
+
+    /// 等待记录线程收完剩余的事件并写出文件
+    /// 调用前需要先让引擎丢弃旁路发送端 (停止录制或停止引擎)
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.thread_handle.take() {
+            Some(handle) => handle.join().unwrap_or_else(|_| Err("MIDI 录制线程异常退出".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn capture_loop(rx: Receiver<CaptureEvent>, started_at: Instant, event_count: Arc<AtomicU64>, path: &Path) -> Result<(), String> {
+    // 按轨道分组：(距开始的毫秒数, 原始消息)
+    let mut tracks: BTreeMap<u32, Vec<(u64, Vec<u8>)>> = BTreeMap::new();
+    for (time, track, bytes) in rx {
+        let ms = time.saturating_duration_since(started_at).as_millis() as u64;
+        tracks.entry(track).or_default().push((ms, bytes));
+        event_count.fetch_add(1, Ordering::Relaxed);
+    }
+    write_smf(path, &tracks)
+}
+
+fn write_smf(path: &Path, tracks: &BTreeMap<u32, Vec<(u64, Vec<u8>)>>) -> Result<(), String> {
+    let arena = Arena::new();
+    let end_of_track = TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) };
+
+    // 第一轨只放速度
+    let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(u15::new(TICKS_PER_QUARTER))));
+    smf.tracks.push(vec![
+        TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(TEMPO_US))) },
+        end_of_track,
+    ]);
+
+    let names: Vec<String> = tracks.keys().map(|&id| format!("Port {}", id + 1)).collect();
+    for ((&id, events), name) in tracks.iter().zip(&names) {
+        let mut track = vec![
+            TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())) },
+            TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::MidiPort(u7::new((id % 128) as u8))) },
+        ];
+        let mut last_ms = 0;
+        for (ms, bytes) in events {
+            // 只保留通道消息与 SysEx，解析失败的 (残缺的封包) 直接跳过
+            let Ok(event) = midly::live::LiveEvent::parse(bytes) else { continue };
+            let kind = event.as_track_event(&arena);
+            if !matches!(kind, TrackEventKind::Midi { .. } | TrackEventKind::SysEx(_)) {
+                continue;
+            }
+            track.push(TrackEvent { delta: u28::new((ms - last_ms).min(u28::max_value().as_int() as u64) as u32), kind });
+            last_ms = *ms;
+        }
+        track.push(end_of_track);
+        smf.tracks.push(track);
+    }
+
+    smf.save(path).map_err(|e| format!("无法写入 MIDI 文件 {}: {}", path.display(), e))?;
+    log::info!("MIDI 录制已保存: {} ({} 个音轨)", path.display(), tracks.len());
+    Ok(())
+}
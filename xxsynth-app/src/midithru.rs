@@ -0,0 +1,111 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use midir::{MidiOutput, MidiOutputConnection};
+
+// MIDI 直通：把指定 (驱动端口, 通道) 的消息原样转发到系统的 MIDI 输出端口 (例如硬件音源)，这些通道不再由本程序合成
+// 转发在单独的线程中进行，系统 MIDI 接口偶尔的阻塞不会拖慢音频处理循环
+
+// midir 在系统中注册的客户端名称
+const CLIENT_NAME: &str = "XXSynth MIDI Thru";
+// 端口断开后重试连接、以及确认端口仍然存在的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// 本程序驱动提供的端口名称前缀 (与 xxsynth-winmm 中 MODM_GETDEVCAPS 返回的 "XXSynth Port N" 一致)
+const DRIVER_PORT_PREFIX: &str = "XXSynth Port";
+
+/// 是否为本程序驱动自己的端口：转发到这些端口的消息会回到引擎，形成无限循环
+pub fn is_driver_port(name: &str) -> bool {
+    name.starts_with(DRIVER_PORT_PREFIX)
+}
+
+/// 系统中可用的 MIDI 输出端口名称，不含本程序驱动自己的端口
+pub fn output_ports() -> Vec<String> {
+    let Ok(output) = MidiOutput::new(CLIENT_NAME) else {
+        return Vec::new();
+    };
+    output
+        .ports()
+        .iter()
+        .filter_map(|port| output.port_name(port).ok())
+        .filter(|name| !is_driver_port(name))
+        .collect()
+}
+
+/// 启动转发线程，返回消息的发送端 (原始 MIDI 字节)；发送端全部释放后线程自动结束
+/// 端口不存在或中途消失时丢弃消息，并每秒尝试重新连接，状态变化通过 engine_message 告知界面
+pub fn start(port_name: &str, engine_message: Arc<Mutex<Option<String>>>) -> Sender<Vec<u8>> {
+    let (thru_tx, thru_rx) = mpsc::channel::<Vec<u8>>();
+    let port_name = port_name.to_string();
+    thread::spawn(move || thru_loop(&port_name, thru_rx, &engine_message));
+    thru_tx
+}
+
+fn thru_loop(port_name: &str, rx: Receiver<Vec<u8>>, engine_message: &Mutex<Option<String>>) {
+    let report = |msg: String| {
+        log::warn!("{}", msg);
+        if let Ok(mut m) = engine_message.lock() { *m = Some(msg); }
+    };
+
+    let mut conn: Option<MidiOutputConnection> = None;
+    let mut ever_connected = false;
+    let mut last_attempt: Option<Instant> = None;
+    let mut last_check = Instant::now();
+    loop {
+        if conn.is_none() && last_attempt.is_none_or(|t| t.elapsed() >= CHECK_INTERVAL) {
+            let first_attempt = last_attempt.is_none();
+            last_attempt = Some(Instant::now());
+            match connect(port_name) {
+                Ok(c) => {
+                    if ever_connected {
+                        report(format!("已重新连接 MIDI 直通端口 {}。", port_name));
+                    } else {
+                        log::info!("MIDI 直通: 已连接到 {}", port_name);
+                    }
+                    ever_connected = true;
+                    conn = Some(c);
+                }
+                // 只在第一次失败时提示，之后静默重试
+                Err(e) if first_attempt => report(format!("警告：无法打开 MIDI 直通端口 {}: {}，插上设备后会自动连接。", port_name, e)),
+                Err(_) => {}
+            }
+        }
+
+        match rx.recv_timeout(CHECK_INTERVAL) {
+            Ok(bytes) => {
+                if let Some(c) = &mut conn
+                    && let Err(e) = c.send(&bytes)
+                {
+                    report(format!("警告：MIDI 直通端口 {} 发送失败 ({})，已断开，将自动重连。", port_name, e));
+                    conn = None;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break, // 引擎已停止
+        }
+
+        // 部分系统上设备拔掉后发送不会报错，定期确认端口仍在列表中
+        if conn.is_some() && last_check.elapsed() >= CHECK_INTERVAL {
+            last_check = Instant::now();
+            if !output_ports().iter().any(|name| name == port_name) {
+                report(format!("警告：MIDI 直通端口 {} 已消失，直通通道的消息将被丢弃，设备恢复后会自动重连。", port_name));
+                conn = None;
+            }
+        }
+    }
+    log::info!("MIDI 直通已关闭");
+}
+
+fn connect(port_name: &str) -> Result<MidiOutputConnection, String> {
+    if is_driver_port(port_name) {
+        return Err("不能转发到 XXSynth 自己的端口".to_string());
+    }
+    let output = MidiOutput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+    let port = output
+        .ports()
+        .into_iter()
+        .find(|port| output.port_name(port).is_ok_and(|name| name == port_name))
+        .ok_or_else(|| "找不到该端口".to_string())?;
+    output.connect(&port, "xxsynth-thru").map_err(|e| e.to_string())
+}
@@ -0,0 +1,79 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xsynth_core::channel_group::ThreadCount;
+use xsynth_core::AudioStreamParams;
+
+use crate::recorder::{self, TapEvent};
+
+// PCM 输出：把实时输出的原始采样发往本地 Socket 或命名管道，方便接入 ffmpeg、OBS 等而不需要虚拟声卡
+// 格式：32 位小端浮点 (f32le)，双声道左右交错 (实时输出设为单声道时为单声道)，采样率与引擎一致，没有文件头，例如
+//   ffmpeg -f f32le -ar 48000 -ac 2 -i "tcp://127.0.0.1:5555?listen" out.flac
+// 与录音相同，用镜像的 ChannelGroup 按事件到达的时刻重放，音频回调不受影响
+
+// 连接 TCP 目标时最多等待的时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// 单个 UDP 数据报最多携带的字节数，按帧 (8 字节) 对齐，避免一帧被拆到两个数据报里
+const MAX_DATAGRAM_BYTES: usize = 65504;
+
+enum Output {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    Pipe(File),
+}
+
+impl Output {
+    // 目标格式：tcp://主机:端口 (对方监听)、udp://主机:端口，其余视为命名管道或文件路径 (例如 \\.\pipe\xxsynth)
+    fn open(target: &str) -> Result<Self, String> {
+        if let Some(addr) = target.strip_prefix("tcp://") {
+            let addr = addr.to_socket_addrs().map_err(|e| e.to_string())?.next().ok_or("无法解析地址")?;
+            let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+            let _ = stream.set_nodelay(true);
+            Ok(Self::Tcp(stream))
+        } else if let Some(addr) = target.strip_prefix("udp://") {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+            socket.connect(addr).map_err(|e| e.to_string())?;
+            Ok(Self::Udp(socket))
+        } else {
+            OpenOptions::new().write(true).open(target).map(Self::Pipe).map_err(|e| e.to_string())
+        }
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let result = match self {
+            Self::Tcp(stream) => stream.write_all(&bytes),
+            Self::Pipe(file) => file.write_all(&bytes),
+            Self::Udp(socket) => bytes.chunks(MAX_DATAGRAM_BYTES).try_for_each(|chunk| socket.send(chunk).map(|_| ())),
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// 打开 PCM 输出目标并启动写入线程，返回事件旁路的发送端
+/// 对方断开后写入线程记录警告并退出，引擎照常运行
+pub fn start(
+    target: &str,
+    audio_params: AudioStreamParams,
+    total_channels: u32,
+    multithreading: ThreadCount,
+) -> Result<Sender<TapEvent>, String> {
+    let mut output = Output::open(target).map_err(|e| format!("无法打开 PCM 输出 {}: {}", target, e))?;
+    let group = recorder::mirror_group(audio_params, total_channels, multithreading);
+
+    let (tap_tx, tap_rx) = mpsc::channel::<TapEvent>();
+    let target = target.to_string();
+    thread::spawn(move || {
+        let result = recorder::mirror_loop(group, tap_rx, Instant::now(), audio_params.sample_rate, |block| output.write(block));
+        if let Err(e) = result {
+            log::warn!("PCM 输出已停止 ({}): {}", target, e);
+        }
+    });
+
+    Ok(tap_tx)
+}
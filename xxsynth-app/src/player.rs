@@ -0,0 +1,335 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::audio::Packet;
+
+// 未指定速度时 MIDI 的默认速度 (120 BPM)，单位为每四分音符的微秒数
+const DEFAULT_TEMPO: u32 = 500_000;
+
+// 播放线程每次最多等待的时间，保证暂停 / 跳转等指令能被及时响应
+const MAX_WAIT: Duration = Duration::from_millis(5);
+
+// 播放速度 (倍速) 的可调范围，用于放慢密集的段落
+pub const MIN_PLAYBACK_SPEED: f64 = 0.25;
+pub const MAX_PLAYBACK_SPEED: f64 = 4.0;
+
+// 一个已换算成绝对时间的短消息
+pub struct TimedEvent {
+    pub time: f64, // 秒
+    pub status: u8,
+    pub data1: u8,
+    pub data2: u8,
+}
+
+enum PlayerCommand {
+    Play,
+    Pause,
+    Seek(f64),
+    SetSpeed(f64),
+    Stop,
+}
+
+// 内置 MIDI 文件播放器：解析整个文件后在后台线程按时间把事件送进引擎的封包处理循环
+pub struct MidiPlayer {
+    pub path: PathBuf,
+    pub duration: f64,                 // 乐曲总时长 (秒)
+    pub position: Arc<Mutex<f64>>,     // 当前播放位置 (秒)
+    pub is_playing: Arc<AtomicBool>,
+    command_tx: Sender<PlayerCommand>,
+    thread_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl MidiPlayer {
+    /// 解析 MIDI 文件并启动播放线程 (初始为暂停状态)
+    pub fn load(path: &Path, packet_tx: Sender<Packet>) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("无法读取 MIDI 文件: {}", e))?;
+        let events = parse_midi(&data)?;
+        let duration = events.last().map(|e| e.time).unwrap_or(0.0);
+
+        let position = Arc::new(Mutex::new(0.0));
+        let is_playing = Arc::new(AtomicBool::new(false));
+        let (command_tx, command_rx) = mpsc::channel::<PlayerCommand>();
+
+        let position_clone = position.clone();
+        let is_playing_clone = is_playing.clone();
+        let thread_handle = thread::spawn(move || {
+            let mut next_index = 0;
+            let mut start_pos = 0.0;
+            let mut started_at: Option<Instant> = None; // 正在播放时记录开始计时的时刻
+            // 乐曲时间 = 实际经过的时间 * 倍速，改变倍速时以当前位置为起点重新计时
+            let mut speed = 1.0;
+
+            loop {
+                // 计算距离下一个事件的等待时间
+                let now_pos = match started_at {
+                    Some(t) => start_pos + t.elapsed().as_secs_f64() * speed,
+                    None => start_pos,
+                };
+                let wait = match (started_at, events.get(next_index)) {
+                    (Some(_), Some(e)) => Duration::from_secs_f64(((e.time - now_pos) / speed).max(0.0)).min(MAX_WAIT),
+                    _ => MAX_WAIT,
+                };
+
+                match command_rx.recv_timeout(wait) {
+                    Ok(PlayerCommand::Play) => {
+                        if started_at.is_none() {
+                            if next_index >= events.len() {
+                                // 已播放到结尾，从头开始
+                                start_pos = 0.0;
+                                next_index = 0;
+                            }
+                            started_at = Some(Instant::now());
+                            is_playing_clone.store(true, Ordering::Relaxed);
+                        }
+                        continue;
+                    }
+                    Ok(PlayerCommand::Pause) => {
+                        if let Some(t) = started_at.take() {
+                            start_pos += t.elapsed().as_secs_f64() * speed;
+                        }
+                        is_playing_clone.store(false, Ordering::Relaxed);
+                        release_all(&packet_tx);
+                        continue;
+                    }
+                    Ok(PlayerCommand::Seek(pos)) => {
+                        release_all(&packet_tx);
+                        start_pos = pos.clamp(0.0, duration);
+                        next_index = events.partition_point(|e| e.time < start_pos);
+                        // 补发跳转点之前的音色与控制器，避免跳转后音色不对
+                        chase_controllers(&events[..next_index], &packet_tx);
+                        if started_at.is_some() {
+                            started_at = Some(Instant::now());
+                        }
+                        if let Ok(mut p) = position_clone.lock() { *p = start_pos; }
+                        continue;
+                    }
+                    Ok(PlayerCommand::SetSpeed(new_speed)) => {
+                        if let Some(t) = started_at {
+                            start_pos += t.elapsed().as_secs_f64() * speed;
+                            started_at = Some(Instant::now());
+                        }
+                        speed = new_speed.clamp(MIN_PLAYBACK_SPEED, MAX_PLAYBACK_SPEED);
+                        continue;
+                    }
+                    Ok(PlayerCommand::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                        release_all(&packet_tx);
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+
+                let Some(t) = started_at else { continue };
+                let now_pos = start_pos + t.elapsed().as_secs_f64() * speed;
+
+                while let Some(e) = events.get(next_index) {
+                    if e.time > now_pos {
+                        break;
+                    }
+                    if packet_tx.send((0, vec![0, e.status, e.data1, e.data2])).is_err() {
+                        // 引擎已经停止
+                        return;
+                    }
+                    next_index += 1;
+                }
+
+                if let Ok(mut p) = position_clone.lock() { *p = now_pos.min(duration); }
+
+                // 播放结束
+                if next_index >= events.len() {
+                    start_pos = duration;
+                    started_at = None;
+                    is_playing_clone.store(false, Ordering::Relaxed);
+                }
+            }
+
+            is_playing_clone.store(false, Ordering::Relaxed);
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            duration,
+            position,
+            is_playing,
+            command_tx,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    pub fn play(&self) {
+        let _ = self.command_tx.send(PlayerCommand::Play);
+    }
+
+    pub fn pause(&self) {
+        let _ = self.command_tx.send(PlayerCommand::Pause);
+    }
+
+    pub fn seek(&self, secs: f64) {
+        let _ = self.command_tx.send(PlayerCommand::Seek(secs));
+    }
+
+    /// 设置播放倍速 (MIN_PLAYBACK_SPEED ~ MAX_PLAYBACK_SPEED)，不影响音高
+    pub fn set_speed(&self, speed: f64) {
+        let _ = self.command_tx.send(PlayerCommand::SetSpeed(speed));
+    }
+
+    pub fn position(&self) -> f64 {
+        *self.position.lock().unwrap()
+    }
+
+    /// 停止播放并等待播放线程退出
+    pub fn stop(&mut self) {
+        let _ = self.command_tx.send(PlayerCommand::Stop);
+        if let Some(handle) = self.thread_handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MidiPlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 读取 MIDI 文件的总时长 (秒)
+pub fn midi_duration(path: &Path) -> Result<f64, String> {
+    Ok(midi_events(path)?.last().map(|e| e.time).unwrap_or(0.0))
+}
+
+/// 读取 MIDI 文件中按时间排序的全部短消息，供内置渲染器使用
+pub fn midi_events(path: &Path) -> Result<Vec<TimedEvent>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("无法读取 MIDI 文件: {}", e))?;
+    parse_midi(&data)
+}
+
+// 解析 MIDI 文件，合并所有音轨并按速度表换算为绝对时间
+fn parse_midi(data: &[u8]) -> Result<Vec<TimedEvent>, String> {
+    let smf = Smf::parse(data).map_err(|e| format!("MIDI 文件解析失败: {}", e))?;
+
+    // 先收集所有音轨的 (绝对 tick, 音轨内序号, 事件)，再统一排序，保证同一 tick 下各音轨内的顺序不变
+    let mut ticked = Vec::new();
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let mut tick: u64 = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            ticked.push((tick, track_index, event.kind));
+        }
+    }
+    ticked.sort_by_key(|(tick, track_index, _)| (*tick, *track_index));
+
+    let mut events = Vec::new();
+    let mut tempo = DEFAULT_TEMPO;
+    let mut last_tick = 0;
+    let mut time = 0.0;
+
+    for (tick, _, kind) in ticked {
+        // 每 tick 的秒数：Metrical 模式随速度变化，Timecode 模式固定
+        let secs_per_tick = match smf.header.timing {
+            Timing::Metrical(tpq) => tempo as f64 / 1_000_000.0 / tpq.as_int().max(1) as f64,
+            Timing::Timecode(fps, subframe) => 1.0 / (fps.as_f32() as f64 * subframe.max(1) as f64),
+        };
+        time += (tick - last_tick) as f64 * secs_per_tick;
+        last_tick = tick;
+
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(t)) => tempo = t.as_int(),
+            TrackEventKind::Midi { channel, message } => {
+                let ch = channel.as_int();
+                let (status, data1, data2) = match message {
+                    MidiMessage::NoteOff { key, vel } => (0x80 | ch, key.as_int(), vel.as_int()),
+                    MidiMessage::NoteOn { key, vel } => (0x90 | ch, key.as_int(), vel.as_int()),
+                    MidiMessage::Aftertouch { key, vel } => (0xA0 | ch, key.as_int(), vel.as_int()),
+                    MidiMessage::Controller { controller, value } => (0xB0 | ch, controller.as_int(), value.as_int()),
+                    MidiMessage::ProgramChange { program } => (0xC0 | ch, program.as_int(), 0),
+                    MidiMessage::ChannelAftertouch { vel } => (0xD0 | ch, vel.as_int(), 0),
+                    MidiMessage::PitchBend { bend } => {
+                        let value = bend.0.as_int();
+                        (0xE0 | ch, (value & 0x7F) as u8, (value >> 7) as u8)
+                    }
+                };
+                events.push(TimedEvent { time, status, data1, data2 });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(events)
+}
+
+// 重新发送给定区间内每个通道最后的音色、控制器与弯音
+fn chase_controllers(events: &[TimedEvent], packet_tx: &Sender<Packet>) {
+    let mut program = [None; 16];
+    let mut pitch_bend = [None; 16];
+    let mut controllers = vec![[None; 128]; 16];
+
+    for e in events {
+        let ch = (e.status & 0x0F) as usize;
+        match e.status & 0xF0 {
+            0xB0 => controllers[ch][e.data1 as usize] = Some(e.data2),
+            0xC0 => program[ch] = Some(e.data1),
+            0xE0 => pitch_bend[ch] = Some((e.data1, e.data2)),
+            _ => {}
+        }
+    }
+
+    for ch in 0..16 {
+        let ch_u8 = ch as u8;
+        if let Some(p) = program[ch] {
+            let _ = packet_tx.send((0, vec![0, 0xC0 | ch_u8, p, 0]));
+        }
+        for (cc, value) in controllers[ch].iter().enumerate() {
+            // 跳过通道模式消息 (120 ~ 127)，它们不是需要恢复的状态
+            if cc < 120 && let Some(v) = value {
+                let _ = packet_tx.send((0, vec![0, 0xB0 | ch_u8, cc as u8, *v]));
+            }
+        }
+        if let Some((lsb, msb)) = pitch_bend[ch] {
+            let _ = packet_tx.send((0, vec![0, 0xE0 | ch_u8, lsb, msb]));
+        }
+    }
+}
+
+// 暂停 / 跳转 / 停止时松开延音踏板并释放所有音符，避免挂音
+fn release_all(packet_tx: &Sender<Packet>) {
+    for ch in 0..16u8 {
+        let _ = packet_tx.send((0, vec![0, 0xB0 | ch, 64, 0]));
+        let _ = packet_tx.send((0, vec![0, 0xB0 | ch, 123, 0]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 单音轨、480 tick/四分音符：120 BPM 下一拍后改为 240 BPM，再过一拍松开音符
+    fn two_tempo_midi() -> Vec<u8> {
+        let track = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 500000 us/拍
+            0x00, 0x90, 0x3C, 0x64,
+            0x83, 0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, // 480 tick 后改为 250000 us/拍
+            0x83, 0x60, 0x80, 0x3C, 0x40,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let mut data = b"MThd".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        data.extend_from_slice(b"MTrk");
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(&track);
+        data
+    }
+
+    #[test]
+    fn duration_follows_tempo_changes() {
+        let events = parse_midi(&two_tempo_midi()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].time, 0.0);
+        // 0.5 秒 (120 BPM 的一拍) + 0.25 秒 (240 BPM 的一拍)
+        assert!((events[1].time - 0.75).abs() < 1e-9, "{}", events[1].time);
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthEvent, SynthFormat, ThreadCount};
+use xsynth_core::{AudioPipe, AudioStreamParams};
+
+// 录音旁路收到的事件：(发往实时合成器的时刻, 事件)
+pub type TapEvent = (Instant, SynthEvent);
+
+// 每次最多渲染的帧数，限制缓冲区大小
+const MAX_BLOCK_FRAMES: usize = 4096;
+
+// WAV 文件头的大小
+const WAV_HEADER_BYTES: u64 = 44;
+
+// 实时录音：xsynth-realtime 不提供输出缓冲区的旁路，这里用一个镜像的 ChannelGroup
+// 按事件到达的时刻重放同样的事件并写入 WAV，音频回调不受影响，但录音期间会多占用一份 CPU
+pub struct Recorder {
+    pub path: PathBuf,
+    pub started_at: Instant,
+    pub bytes_written: Arc<AtomicU64>, // 已写入的文件大小 (字节)
+    thread_handle: Option<thread::JoinHandle<Result<(), String>>>,
+}
+
+impl Recorder {
+    /// 创建 WAV 文件并启动写入线程，返回录音器与事件旁路的发送端
+    pub fn start(
+        path: &Path,
+        audio_params: AudioStreamParams,
+        total_channels: u32,
+        multithreading: ThreadCount,
+    ) -> Result<(Self, Sender<TapEvent>), String> {
+        let spec = hound::WavSpec {
+            channels: audio_params.channels.count(),
+            sample_rate: audio_params.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let writer = hound::WavWriter::create(path, spec)
+            .map_err(|e| format!("无法创建录音文件 {}: {}", path.display(), e))?;
+
+        let group = mirror_group(audio_params, total_channels, multithreading);
+
+        let (tap_tx, tap_rx) = mpsc::channel::<TapEvent>();
+        let started_at = Instant::now();
+        let bytes_written = Arc::new(AtomicU64::new(WAV_HEADER_BYTES));
+        let bytes_clone = bytes_written.clone();
+
+        let thread_handle = thread::spawn(move || {
+            record_loop(group, writer, tap_rx, started_at, audio_params.sample_rate, bytes_clone)
+        });
+
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                started_at,
+                bytes_written,
+                thread_handle: Some(thread_handle),
+            },
+            tap_tx,
+        ))
+    }
+
+    /// 等待写入线程把剩余的音频写完并补全 WAV 文件头
+    /// 调用前需要先让引擎丢弃旁路发送端 (停止录音或停止引擎)
+    pub fn finish(mut self) -> Result<(), String> {
+        match self.thread_handle.take() {
+            Some(handle) => handle.join().unwrap_or_else(|_| Err("录音线程异常退出".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn record_loop(
+    group: ChannelGroup,
+    mut writer: hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    tap_rx: Receiver<TapEvent>,
+    started_at: Instant,
+    sample_rate: u32,
+    bytes_written: Arc<AtomicU64>,
+) -> Result<(), String> {
+    let mut written_samples: u64 = 0;
+    mirror_loop(group, tap_rx, started_at, sample_rate, |block| {
+        for &sample in block {
+            writer.write_sample(sample).map_err(|e| format!("写入录音文件失败: {}", e))?;
+        }
+        written_samples += block.len() as u64;
+        bytes_written.store(WAV_HEADER_BYTES + written_samples * 4, Ordering::Relaxed);
+        Ok(())
+    })?;
+
+    writer.finalize().map_err(|e| format!("无法完成录音文件: {}", e))
+}
+
+/// 创建与实时合成器配置一致的镜像 ChannelGroup，供录音 / PCM 输出重放旁路事件
+pub fn mirror_group(audio_params: AudioStreamParams, total_channels: u32, multithreading: ThreadCount) -> ChannelGroup {
+    ChannelGroup::new(ChannelGroupConfig {
+        channel_init_options: Default::default(),
+        format: SynthFormat::Custom { channels: total_channels },
+        audio_params,
+        // 与实时合成器一致：通道并行，线程池用于通道内的按键
+        parallelism: ParallelismOptions { channel: ThreadCount::Auto, key: multithreading },
+    })
+}
+
+/// 按事件到达的时刻重放旁路事件，渲染出的交错采样 (声道数与实时输出一致) 交给 write
+/// 旁路发送端全部断开后渲染到当前时刻再返回
+pub fn mirror_loop(
+    mut group: ChannelGroup,
+    tap_rx: Receiver<TapEvent>,
+    started_at: Instant,
+    sample_rate: u32,
+    mut write: impl FnMut(&[f32]) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut rendered_frames: u64 = 0;
+    let channels = group.stream_params().channels.count() as usize;
+    let mut buf = vec![0.0f32; MAX_BLOCK_FRAMES * channels];
+
+    // 渲染到指定时刻为止，事件会在它到达的那一帧之前生效
+    let mut render_until = |group: &mut ChannelGroup, until: Instant| -> Result<(), String> {
+        let target_frames = (until.saturating_duration_since(started_at).as_secs_f64() * sample_rate as f64) as u64;
+        while rendered_frames < target_frames {
+            let frames = ((target_frames - rendered_frames) as usize).min(MAX_BLOCK_FRAMES);
+            let block = &mut buf[..frames * channels];
+            block.fill(0.0);
+            group.read_samples(block);
+            write(block)?;
+            rendered_frames += frames as u64;
+        }
+        Ok(())
+    };
+
+    loop {
+        match tap_rx.recv_timeout(Duration::from_millis(20)) {
+            Ok((time, event)) => {
+                render_until(&mut group, time)?;
+                group.send_event(event);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                render_until(&mut group, Instant::now())?;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // 旁路断开 (停止录音 / 停止引擎)：渲染到停止的时刻再收尾
+                render_until(&mut group, Instant::now())?;
+                return Ok(());
+            }
+        }
+    }
+}
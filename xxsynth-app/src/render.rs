@@ -0,0 +1,514 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use xsynth_core::channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent, ChannelInitOptions, ControlEvent};
+use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthEvent, SynthFormat};
+use xsynth_core::effects::VolumeLimiter;
+use xsynth_core::soundfont::{EnvelopeCurveType, EnvelopeOptions, SampleSoundfont, SoundfontBase, SoundfontInitOptions};
+use xsynth_core::{AudioPipe, AudioStreamParams, ChannelCount};
+
+use crate::config::{thread_count_arg, LoopMode, OutputFormat, RenderConfig};
+use crate::player::{midi_events, TimedEvent};
+
+// 渲染线程与 UI 共享的状态
+#[derive(Clone)]
+pub struct RenderShared {
+    pub is_rendering: Arc<AtomicBool>,
+    pub progress: Arc<Mutex<f32>>,        // 完成比例 (0.0 ~ 1.0)
+    pub rendered_secs: Arc<Mutex<f64>>,   // 内置渲染器模式下已渲染的乐曲时长
+    pub error: Arc<Mutex<Option<Result<String, String>>>>, // 渲染结束后的完成 (Ok) / 错误 (Err) 消息
+    pub child: Arc<Mutex<Option<Child>>>, // 正在运行的 xsynth-render 子进程
+    pub cancel: Arc<AtomicBool>,          // 用户请求取消，内置渲染器每渲染一块检查一次
+}
+
+// 用户取消渲染时返回的消息，队列渲染据此停止后续任务
+const CANCELLED_MSG: &str = "渲染已取消。";
+
+// 内置渲染器每次渲染的帧数，也是检查取消与更新进度的间隔
+const RENDER_BLOCK_FRAMES: usize = 4096;
+// 乐曲结束后最多再渲染的释音尾巴 (秒)，输出降到静音阈值以下时提前结束
+const MAX_TAIL_SECS: u64 = 10;
+const SILENCE_THRESHOLD: f32 = 0.0001;
+
+// 渲染队列中一项的状态
+#[derive(Clone, PartialEq)]
+pub enum QueueStatus {
+    Pending,
+    Rendering,
+    Done,
+    Failed(String),
+}
+
+// 渲染队列中的一项，使用相同的音色库与渲染参数，只有输入输出不同
+#[derive(Clone)]
+pub struct QueueItem {
+    pub midi_path: String,
+    pub output_path: String,
+    pub status: QueueStatus,
+}
+
+/// 在后台线程中执行一次渲染，结束后写入完成/错误消息并解除模态锁
+pub fn spawn_render(cfg: RenderConfig, soundfonts: Vec<PathBuf>, shared: RenderShared) {
+    shared.cancel.store(false, Ordering::SeqCst);
+    thread::spawn(move || {
+        let msg = render_one(&cfg, &soundfonts, &shared)
+            .map(|()| format!("渲染完成！音频已保存至 {}", cfg.output_path));
+        if let Ok(mut err) = shared.error.lock() {
+            *err = Some(msg);
+        }
+
+        // 渲染流程结束，解除模态锁
+        shared.is_rendering.store(false, Ordering::SeqCst);
+    });
+}
+
+/// 在后台线程中依次渲染队列里所有等待中的项目，取消时停止后续项目
+pub fn spawn_queue_render(cfg: RenderConfig, soundfonts: Vec<PathBuf>, queue: Arc<Mutex<Vec<QueueItem>>>, shared: RenderShared) {
+    shared.cancel.store(false, Ordering::SeqCst);
+    thread::spawn(move || {
+        let mut done = 0;
+        let mut failed = 0;
+        let mut cancelled = false;
+
+        // 每次重新查找下一个等待中的项目
+        loop {
+            let next = {
+                let mut queue = queue.lock().unwrap();
+                queue.iter_mut().find(|item| item.status == QueueStatus::Pending).map(|item| {
+                    item.status = QueueStatus::Rendering;
+                    (item.midi_path.clone(), item.output_path.clone())
+                })
+            };
+            let Some((midi_path, output_path)) = next else { break };
+
+            if let Ok(mut p) = shared.progress.lock() { *p = 0.0; }
+            if let Ok(mut secs) = shared.rendered_secs.lock() { *secs = 0.0; }
+
+            let item_cfg = RenderConfig { midi_path: midi_path.clone(), output_path, ..cfg.clone() };
+            let result = render_one(&item_cfg, &soundfonts, &shared);
+
+            let mut queue = queue.lock().unwrap();
+            if let Some(item) = queue.iter_mut().find(|item| item.status == QueueStatus::Rendering && item.midi_path == midi_path) {
+                item.status = match &result {
+                    Ok(()) => QueueStatus::Done,
+                    Err(e) => QueueStatus::Failed(e.clone()),
+                };
+            }
+            match result {
+                Ok(()) => done += 1,
+                Err(e) if e == CANCELLED_MSG => {
+                    cancelled = true;
+                    break;
+                }
+                Err(_) => failed += 1,
+            }
+        }
+
+        let msg = if cancelled {
+            Err(format!("队列渲染已取消：已完成 {} 个，失败 {} 个。", done, failed))
+        } else if failed > 0 {
+            Err(format!("队列渲染结束：成功 {} 个，失败 {} 个。", done, failed))
+        } else {
+            Ok(format!("队列渲染完成：共 {} 个文件。", done))
+        };
+        if let Ok(mut err) = shared.error.lock() {
+            *err = Some(msg);
+        }
+
+        shared.is_rendering.store(false, Ordering::SeqCst);
+    });
+}
+
+/// 在当前线程中完成一次渲染，供命令行模式使用
+pub fn render_blocking(cfg: &RenderConfig, soundfonts: &[PathBuf]) -> Result<(), String> {
+    let shared = RenderShared {
+        is_rendering: Arc::new(AtomicBool::new(true)),
+        progress: Arc::new(Mutex::new(0.0)),
+        rendered_secs: Arc::new(Mutex::new(0.0)),
+        error: Arc::new(Mutex::new(None)),
+        child: Arc::new(Mutex::new(None)),
+        cancel: Arc::new(AtomicBool::new(false)),
+    };
+    render_one(cfg, soundfonts, &shared)
+}
+
+// 渲染单个文件；非 WAV 格式先渲染到临时 WAV，再交给 ffmpeg 转码
+fn render_one(cfg: &RenderConfig, soundfonts: &[PathBuf], shared: &RenderShared) -> Result<(), String> {
+    let mut wav_cfg = cfg.clone();
+    if cfg.output_format != OutputFormat::Wav {
+        wav_cfg.output_path = format!("{}.tmp.wav", cfg.output_path);
+    }
+
+    if cfg.use_external_renderer {
+        run_external(&wav_cfg, soundfonts, shared)?;
+    } else {
+        run_library(&wav_cfg, soundfonts, shared)?;
+    }
+
+    // 循环处理在转码之前进行，只需要处理 WAV
+    if cfg.loop_mode != LoopMode::Off {
+        crate::wavloop::apply_loop(Path::new(&wav_cfg.output_path), Path::new(&cfg.midi_path), cfg.loop_mode)?;
+    }
+
+    if cfg.output_format != OutputFormat::Wav {
+        encode_with_ffmpeg(&wav_cfg.output_path, &cfg.output_path, cfg.output_format, shared)?;
+        let _ = std::fs::remove_file(&wav_cfg.output_path);
+    }
+    Ok(())
+}
+
+// 内置渲染器：用 xsynth-core 在本进程中渲染，不依赖外部程序
+// 取消时删除写了一半的 WAV 文件
+fn run_library(cfg: &RenderConfig, soundfonts: &[PathBuf], shared: &RenderShared) -> Result<(), String> {
+    let result = render_library(cfg, soundfonts, shared);
+    if result.as_ref().is_err_and(|e| e == CANCELLED_MSG) {
+        let _ = std::fs::remove_file(&cfg.output_path);
+    }
+    result
+}
+
+fn render_library(cfg: &RenderConfig, soundfonts: &[PathBuf], shared: &RenderShared) -> Result<(), String> {
+    let events = midi_events(Path::new(&cfg.midi_path))?;
+    let total_secs = events.last().map_or(0.0, |e| e.time);
+    let channels = if cfg.audio_channels == "mono" { ChannelCount::Mono } else { ChannelCount::Stereo };
+    let audio_params = AudioStreamParams::new(cfg.sample_rate, channels);
+
+    let mut sf_options = SoundfontInitOptions { interpolator: cfg.get_interpolator(), ..Default::default() };
+    if cfg.linear_envelope {
+        sf_options.vol_envelope_options = EnvelopeOptions {
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            release_curve: EnvelopeCurveType::Linear,
+        };
+    }
+    let mut loaded: Vec<Arc<dyn SoundfontBase>> = Vec::new();
+    for path in soundfonts {
+        // 大型音色库加载期间无法中断，只在每个文件之间检查
+        if shared.cancel.load(Ordering::SeqCst) {
+            return Err(CANCELLED_MSG.to_string());
+        }
+        let sf = SampleSoundfont::new(path, audio_params, sf_options)
+            .map_err(|e| format!("错误：无法加载音色库 {}: {}", path.display(), e))?;
+        loaded.push(Arc::new(sf));
+    }
+
+    let mut group = ChannelGroup::new(ChannelGroupConfig {
+        channel_init_options: ChannelInitOptions { fade_out_killing: !cfg.disable_fade_out },
+        format: SynthFormat::Midi,
+        audio_params,
+        parallelism: ParallelismOptions { channel: cfg.channel_threading, key: cfg.key_threading },
+    });
+    group.send_event(SynthEvent::AllChannels(ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(loaded))));
+    group.send_event(SynthEvent::AllChannels(ChannelEvent::Config(ChannelConfigEvent::SetLayerCount(
+        if cfg.layers == 0 { None } else { Some(cfg.layers as usize) },
+    ))));
+
+    let spec = hound::WavSpec {
+        channels: channels.count(),
+        sample_rate: cfg.sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let writer = hound::WavWriter::create(&cfg.output_path, spec)
+        .map_err(|e| format!("错误：无法创建输出文件 {}: {}", cfg.output_path, e))?;
+    let mut output = LibraryOutput {
+        group,
+        writer,
+        limiter: cfg.apply_limiter.then(|| VolumeLimiter::new(channels.count())),
+        buf: vec![0.0; RENDER_BLOCK_FRAMES * channels.count() as usize],
+        channels: channels.count() as usize,
+        rendered_frames: 0,
+    };
+    let sample_rate = cfg.sample_rate as f64;
+    let frame_of = |e: &TimedEvent| (e.time * sample_rate) as u64;
+
+    // 每个事件在它所在的那一帧之前生效，两个事件之间按块渲染
+    let mut next = 0;
+    while next < events.len() {
+        if shared.cancel.load(Ordering::SeqCst) {
+            return Err(CANCELLED_MSG.to_string());
+        }
+        let target = frame_of(&events[next]);
+        if output.rendered_frames < target {
+            output.render_block(((target - output.rendered_frames) as usize).min(RENDER_BLOCK_FRAMES))?;
+            let secs = output.rendered_frames as f64 / sample_rate;
+            if let Ok(mut s) = shared.rendered_secs.lock() { *s = secs; }
+            if total_secs > 0.0 && let Ok(mut p) = shared.progress.lock() {
+                *p = (secs / total_secs).clamp(0.0, 1.0) as f32;
+            }
+            continue;
+        }
+        while let Some(e) = events.get(next)
+            && frame_of(e) <= output.rendered_frames
+        {
+            if let Some(event) = midi_to_event(e) {
+                output.group.send_event(event);
+            }
+            next += 1;
+        }
+    }
+
+    // 乐曲结束：松开所有音符，渲染释音尾巴直到静音
+    output.group.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::AllNotesOff)));
+    output.group.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
+    let tail_end = output.rendered_frames + MAX_TAIL_SECS * cfg.sample_rate as u64;
+    while output.rendered_frames < tail_end {
+        if shared.cancel.load(Ordering::SeqCst) {
+            return Err(CANCELLED_MSG.to_string());
+        }
+        if output.render_block(RENDER_BLOCK_FRAMES)? < SILENCE_THRESHOLD {
+            break;
+        }
+    }
+
+    output.writer.finalize().map_err(|e| format!("错误：无法完成输出文件: {}", e))
+}
+
+// 内置渲染器的输出：合成器、WAV 写入与可选的限幅器
+struct LibraryOutput {
+    group: ChannelGroup,
+    writer: hound::WavWriter<BufWriter<File>>,
+    limiter: Option<VolumeLimiter>,
+    buf: Vec<f32>,
+    channels: usize,
+    rendered_frames: u64,
+}
+
+impl LibraryOutput {
+    // 渲染一块并写入文件，返回这一块的峰值
+    fn render_block(&mut self, frames: usize) -> Result<f32, String> {
+        let block = &mut self.buf[..frames * self.channels];
+        block.fill(0.0);
+        self.group.read_samples(block);
+        if let Some(limiter) = &mut self.limiter {
+            limiter.limit(block);
+        }
+        let mut peak = 0.0f32;
+        for &sample in block.iter() {
+            peak = peak.max(sample.abs());
+            self.writer.write_sample(sample).map_err(|e| format!("错误：写入输出文件失败: {}", e))?;
+        }
+        self.rendered_frames += frames as u64;
+        Ok(peak)
+    }
+}
+
+// 把 MIDI 文件中的短消息转换为合成器事件，处理的消息种类与 xsynth-render 一致
+fn midi_to_event(e: &TimedEvent) -> Option<SynthEvent> {
+    let event = match e.status & 0xF0 {
+        0x90 if e.data2 > 0 => ChannelAudioEvent::NoteOn { key: e.data1, vel: e.data2 },
+        0x80 | 0x90 => ChannelAudioEvent::NoteOff { key: e.data1 },
+        0xB0 => ChannelAudioEvent::Control(ControlEvent::Raw(e.data1, e.data2)),
+        0xC0 => ChannelAudioEvent::ProgramChange(e.data1),
+        0xE0 => {
+            let value = ((e.data2 as i32) << 7 | e.data1 as i32) - 8192;
+            ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value as f32 / 8192.0))
+        }
+        _ => return None,
+    };
+    Some(SynthEvent::Channel((e.status & 0x0F) as u32, ChannelEvent::Audio(event)))
+}
+
+// 调用外部的 xsynth-render 程序渲染，从 stderr 中解析进度
+fn run_external(cfg: &RenderConfig, soundfonts: &[PathBuf], shared: &RenderShared) -> Result<(), String> {
+    // 未指定路径时会自动查找 PATH 或同级目录下的 xsynth-render(.exe)
+    let mut cmd = match &cfg.xsynth_render_path {
+        Some(path) => Command::new(path),
+        None => Command::new("xsynth-render"),
+    };
+
+    cmd.arg(&cfg.midi_path);
+    for sf in soundfonts { cmd.arg(sf); }
+    cmd.arg("-o").arg(&cfg.output_path);
+    cmd.arg("-s").arg(cfg.sample_rate.to_string());
+    cmd.arg("-c").arg(&cfg.audio_channels);
+    cmd.arg("-l").arg(cfg.layers.to_string());
+    cmd.arg("--channel-threading").arg(thread_count_arg(cfg.channel_threading));
+    cmd.arg("--key-threading").arg(thread_count_arg(cfg.key_threading));
+    if cfg.apply_limiter { cmd.arg("-L"); }
+    if cfg.disable_fade_out { cmd.arg("--disable-fade-out"); }
+    if cfg.linear_envelope { cmd.arg("--linear-envelope"); }
+    cmd.arg("-I").arg(&cfg.interpolation);
+
+    // 在 Windows 环境下隐藏 xsynth-render 拉起时可能带来的黑框
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(match &cfg.xsynth_render_path {
+            Some(path) => format!("错误：无法启动 {}: {}", path.display(), e),
+            None => "错误：找不到 xsynth-render！请在渲染页指定程序路径，或将它放置在同级目录 / 添加到系统 PATH 中。".to_string(),
+        }),
+    };
+
+    let stderr = child.stderr.take();
+    // 子进程交给 UI 共享，以便“取消渲染”时可以直接结束它
+    *shared.child.lock().unwrap() = Some(child);
+
+    // xsynth-render 通常将进度日志用 indicatif 库输出在 stderr 中
+    // 子进程被结束后管道关闭，读取循环会自然退出
+    if let Some(stderr) = stderr {
+        let mut buffer: Vec<u8> = Vec::new();
+
+        // 逐字节读取 stderr，indicatif 用 \r 原地重绘进度条，所以 \r 和 \n 都视为一行结束
+        for b in BufReader::new(stderr).bytes() {
+            let Ok(b) = b else { break };
+            if b == b'\r' || b == b'\n' {
+                if let Some(pct) = parse_progress(&String::from_utf8_lossy(&buffer))
+                    && let Ok(mut p) = shared.progress.lock()
+                {
+                    *p = pct;
+                }
+                buffer.clear();
+            } else {
+                buffer.push(b);
+            }
+        }
+    }
+
+    // 子进程已被取走说明用户取消了渲染
+    let Some(mut child) = shared.child.lock().unwrap().take() else {
+        return Err(CANCELLED_MSG.to_string());
+    };
+
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err("错误：渲染进程异常退出！请检查 xsynth-render 工具。".to_string()),
+    }
+}
+
+// 调用 ffmpeg 将渲染出的 WAV 转码为 FLAC / OGG，失败时保留 WAV 文件
+fn encode_with_ffmpeg(wav_path: &str, out_path: &str, format: OutputFormat, shared: &RenderShared) -> Result<(), String> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y").arg("-i").arg(wav_path);
+    match format {
+        OutputFormat::Flac => { cmd.arg("-c:a").arg("flac"); }
+        OutputFormat::Ogg => { cmd.arg("-c:a").arg("libvorbis").arg("-q:a").arg("6"); }
+        OutputFormat::Wav => {}
+    }
+    cmd.arg(out_path);
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    cmd.stdout(Stdio::null());
+    cmd.stderr(Stdio::null());
+
+    let Ok(child) = cmd.spawn() else {
+        return Err(format!("错误：找不到 ffmpeg，无法转码为 {}！WAV 已保存至 {}", format.extension().to_uppercase(), wav_path));
+    };
+
+    // 同样交给 UI 共享，转码阶段也可以取消
+    *shared.child.lock().unwrap() = Some(child);
+    let status = loop {
+        let mut guard = shared.child.lock().unwrap();
+        let Some(child) = guard.as_mut() else {
+            return Err(CANCELLED_MSG.to_string());
+        };
+        if let Ok(Some(status)) = child.try_wait() {
+            guard.take();
+            break status;
+        }
+        drop(guard);
+        thread::sleep(std::time::Duration::from_millis(50));
+    };
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("错误：ffmpeg 转码失败！WAV 已保存至 {}", wav_path))
+    }
+}
+
+// 去掉 ANSI 转义序列 (颜色、光标移动等)，例如 "\x1b[32m50%\x1b[0m"
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI 序列：ESC [ 参数... 结束字节 (0x40 ~ 0x7E)
+        if chars.peek() == Some(&'[') {
+            chars.next();
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        } else {
+            // 其他两字节序列，例如 ESC 7 / ESC 8
+            chars.next();
+        }
+    }
+    out
+}
+
+// 从一行 stderr 中解析百分比进度 (0.0 ~ 1.0)，匹配形如 "42%" 或 "42.5%" 的最后一处
+fn parse_progress(line: &str) -> Option<f32> {
+    let line = strip_ansi(line);
+    let bytes = line.as_bytes();
+    let mut result = None;
+
+    for (idx, _) in line.match_indices('%') {
+        // 往前寻找数字和小数点来匹配百分比值
+        let mut start = idx;
+        while start > 0 && (bytes[start - 1].is_ascii_digit() || bytes[start - 1] == b'.') {
+            start -= 1;
+        }
+        if let Ok(pct) = line[start..idx].trim_start_matches('.').parse::<f32>()
+            && (0.0..=100.0).contains(&pct)
+        {
+            result = Some(pct / 100.0);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_ansi_removes_escape_sequences() {
+        assert_eq!(strip_ansi("\x1b[32m50%\x1b[0m done"), "50% done");
+        assert_eq!(strip_ansi("\x1b7saved\x1b8"), "saved");
+        // 被截断的序列不会留下参数字节
+        assert_eq!(strip_ansi("12%\x1b[3"), "12%");
+    }
+
+    #[test]
+    fn parse_progress_reads_ansi_wrapped_percentages() {
+        assert_eq!(parse_progress("\x1b[1m\x1b[32m42%\x1b[0m"), Some(0.42));
+        assert_eq!(parse_progress("[=====>    ] \x1b[33m12.5%\x1b[0m ETA 3s"), Some(0.125));
+        // 一行中有多处时取最后一处
+        assert_eq!(parse_progress("10% 20% 30%"), Some(0.3));
+    }
+
+    #[test]
+    fn parse_progress_ignores_partial_lines() {
+        // 进度条按 \r 刷新时读到的不完整片段
+        assert_eq!(parse_progress("Rendering 4"), None);
+        assert_eq!(parse_progress("%"), None);
+        assert_eq!(parse_progress(".%"), None);
+        assert_eq!(parse_progress("150%"), None);
+        assert_eq!(parse_progress("\x1b[32m"), None);
+    }
+}
@@ -1,42 +1,311 @@
 use std::fs;
 use std::path::PathBuf;
 
+use crate::hotkey::HotkeyBinding;
+use crate::config::{RealtimeConfig, RenderConfig, SoundfontEntry, MAX_DRIVER_PORTS, MAX_GAIN, MAX_MIDI_KEY, MAX_STOP_FADE_MS};
+
+const SETTINGS_FILE: &str = "xxsynth_settings.json";
+
+// 界面主题，跟随系统时由 egui 读取系统的明暗设置
+#[derive(PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum ThemeMode {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl ThemeMode {
+    pub const ALL: [ThemeMode; 3] = [ThemeMode::System, ThemeMode::Light, ThemeMode::Dark];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::System => "跟随系统",
+            Self::Light => "浅色",
+            Self::Dark => "深色",
+        }
+    }
+}
+
+// 恢复默认设置前的备份，用于撤销一次
+const UNDO_FILE: &str = "xxsynth_settings.undo.json";
+
+// 音色库布局预设的存放目录，与设置文件放在一起，每个预设一个 JSON 文件
+const PRESETS_DIR: &str = "presets";
+
+// 音色库布局预设：一组音色库及其各自的 Bank / Preset / 启用状态
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SoundfontPreset {
+    soundfonts: Vec<SoundfontEntry>,
+}
+
+// 设置文件的结构版本，每次做不兼容的字段改动时 +1 并在 migrate 中补上迁移步骤
+pub const SETTINGS_VERSION: u32 = 2;
+
+// v1 设置文件中平铺在顶层的实时参数，v2 起移入 realtime 对象
+const REALTIME_KEYS_V1: [&str; 23] = [
+    "transport", "udp_port", "udp_port_count", "total_channels", "driver_port_count", "render_window_ms",
+    "thread_count", "interpolator", "ignore_velocity_min", "ignore_velocity_max", "sample_rate", "gain",
+    "layer_limit", "use_effects", "timestamped_packets", "aftertouch", "pcm_sink", "channel_routes",
+    "stop_fade_ms", "velocity_curve", "ignore_key_min", "ignore_key_max", "fade_out_killing",
+];
+
 // 本地持久化保存结构
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AppSettings {
-    pub soundfonts: Vec<PathBuf>,
-    pub udp_port: u16,
-    pub total_channels: u32,
-    pub render_window_ms: f64,
-    pub thread_count: usize,
-    pub interpolator: u8,
-    pub ignore_velocity_min: u8,
-    pub ignore_velocity_max: u8,
+    #[serde(default)]
+    pub version: u32,
+    pub soundfonts: Vec<SoundfontEntry>,
+    // 实时引擎参数，与 RealtimeConfig 一一对应
+    #[serde(default = "default_realtime")]
+    pub realtime: RealtimeConfig,
+    // 渲染参数以及上次使用的输入 / 输出路径
+    #[serde(default)]
+    pub render: RenderConfig,
+    // 虚拟 MIDI 端口在注册表中使用的槽位 (例如 midi3)，便于下次沿用或清理
+    #[serde(default)]
+    pub midi_port_slot: Option<String>,
+    // 启动时直接隐藏到系统托盘
+    #[serde(default)]
+    pub start_minimized: bool,
+    // 重启时新设置启动失败，按原设置重新启动引擎，保证演出中不会断声
+    #[serde(default = "default_keep_engine_on_failure")]
+    pub keep_engine_on_failure: bool,
+    // 退出时从注册表删除虚拟 MIDI 端口，不留下驱动项
+    #[serde(default)]
+    pub unregister_port_on_exit: bool,
+    // 全局紧急静音快捷键，None 为不注册
+    #[serde(default = "default_panic_hotkey")]
+    pub panic_hotkey: Option<HotkeyBinding>,
+    // 外观：主题与强调色 (sRGB)，强调色为 None 时使用默认配色
+    #[serde(default)]
+    pub theme: ThemeMode,
+    #[serde(default)]
+    pub accent_color: Option<[u8; 3]>,
+    // 状态栏消息显示多少秒后自动清除 (仍保留在历史中)，0 为一直显示
+    #[serde(default)]
+    pub status_auto_hide_secs: u32,
+    // 窗口位置与大小 (逻辑像素)，None 表示使用默认值
+    pub window_width: Option<f32>,
+    pub window_height: Option<f32>,
+    pub window_x: Option<f32>,
+    pub window_y: Option<f32>,
+}
+
+fn default_keep_engine_on_failure() -> bool {
+    true
+}
+
+fn default_panic_hotkey() -> Option<HotkeyBinding> {
+    Some(HotkeyBinding::default())
+}
+
+// 首次运行时的实时参数，比 RealtimeConfig::default() 更适合黑乐谱：64 个通道、较大的缓冲、按 CPU 核心数开线程
+fn default_realtime() -> RealtimeConfig {
+    RealtimeConfig {
+        total_channels: 64,
+        render_window_ms: 15.0,
+        thread_count: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(12),
+        ignore_velocity_max: 0, // 关闭忽略力度范围
+        ..RealtimeConfig::default()
+    }
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            soundfonts: vec![],
+            realtime: default_realtime(),
+            render: RenderConfig::default(),
+            midi_port_slot: None,
+            start_minimized: false,
+            keep_engine_on_failure: default_keep_engine_on_failure(),
+            unregister_port_on_exit: false,
+            panic_hotkey: default_panic_hotkey(),
+            theme: ThemeMode::System,
+            accent_color: None,
+            status_auto_hide_secs: 0,
+            window_width: None,
+            window_height: None,
+            window_x: None,
+            window_y: None,
+        }
+    }
 }
 
 impl AppSettings {
     pub fn load() -> Self {
-        if let Ok(data) = fs::read_to_string("xxsynth_settings.json") {
-            if let Ok(settings) = serde_json::from_str(&data) {
-                return settings;
+        let Ok(data) = fs::read_to_string(SETTINGS_FILE) else {
+            return Self::default();
+        };
+
+        match Self::parse(&data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                // 无法识别的设置文件先备份再重置，避免用户的配置被直接覆盖
+                let backup = format!("{}.bak", SETTINGS_FILE);
+                log::warn!("读取设置失败: {}，已备份至 {} 并恢复默认设置。", e, backup);
+                let _ = fs::write(&backup, data);
+                Self::default()
             }
         }
-        // 默认值
-        Self {
-            soundfonts: vec![],
-            udp_port: 44444,
-            total_channels: 64,
-            render_window_ms: 15.0,
-            thread_count: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(12),
-            interpolator: 0,
-            ignore_velocity_min: 0,
-            ignore_velocity_max: 0,
+    }
+
+    fn parse(data: &str) -> Result<Self, String> {
+        let mut value: serde_json::Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+
+        // 没有 version 字段的是最早的版本 0
+        let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        if version > SETTINGS_VERSION {
+            return Err(format!("设置文件版本 {} 高于当前支持的版本 {}", version, SETTINGS_VERSION));
         }
+
+        migrate(&mut value, version);
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+
+    // 由保存的设置构造实时引擎配置，超出范围的值 (例如手动编辑过的设置文件) 在这里修正
+    pub fn realtime_config(&self) -> RealtimeConfig {
+        let mut realtime_config = self.realtime.clone();
+        realtime_config.udp_port_count = realtime_config.udp_port_count.max(1);
+        realtime_config.driver_port_count = realtime_config.driver_port_count.clamp(1, MAX_DRIVER_PORTS);
+        realtime_config.gain = realtime_config.gain.clamp(0.0, MAX_GAIN);
+        realtime_config.stop_fade_ms = realtime_config.stop_fade_ms.min(MAX_STOP_FADE_MS);
+        realtime_config.ignore_key_max = realtime_config.ignore_key_max.min(MAX_MIDI_KEY);
+        realtime_config.ignore_key_min = realtime_config.ignore_key_min.min(realtime_config.ignore_key_max);
+        realtime_config
     }
 
     pub fn save(&self) {
         if let Ok(data) = serde_json::to_string_pretty(self) {
-            let _ = fs::write("xxsynth_settings.json", data);
+            let _ = fs::write(SETTINGS_FILE, data);
         }
     }
-}
\ No newline at end of file
+}
+
+fn preset_path(name: &str) -> Result<PathBuf, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("预设名称不能为空".to_string());
+    }
+    if name.chars().any(|c| matches!(c, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|')) {
+        return Err("预设名称不能包含 \\ / : * ? \" < > |".to_string());
+    }
+    Ok(PathBuf::from(PRESETS_DIR).join(format!("{}.json", name)))
+}
+
+/// 已保存的音色库布局预设名称，按名称排序
+pub fn list_soundfont_presets() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(PRESETS_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort_by_key(|n| n.to_lowercase());
+    names
+}
+
+/// 把当前的音色库列表保存为命名预设，同名预设会被覆盖
+pub fn save_soundfont_preset(name: &str, soundfonts: &[SoundfontEntry]) -> Result<(), String> {
+    let path = preset_path(name)?;
+    let preset = SoundfontPreset { soundfonts: soundfonts.to_vec() };
+    let data = serde_json::to_string_pretty(&preset).map_err(|e| e.to_string())?;
+    fs::create_dir_all(PRESETS_DIR).map_err(|e| format!("无法创建预设目录: {}", e))?;
+    fs::write(&path, data).map_err(|e| format!("无法写入 {}: {}", path.display(), e))
+}
+
+pub fn load_soundfont_preset(name: &str) -> Result<Vec<SoundfontEntry>, String> {
+    let path = preset_path(name)?;
+    let data = fs::read_to_string(&path).map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+    let preset: SoundfontPreset = serde_json::from_str(&data).map_err(|e| format!("预设文件格式错误: {}", e))?;
+    Ok(preset.soundfonts)
+}
+
+// 恢复默认设置前备份当前的设置文件，只保留一份
+pub fn backup_for_undo() {
+    if let Err(e) = fs::copy(SETTINGS_FILE, UNDO_FILE) {
+        log::warn!("备份设置文件失败: {}", e);
+    }
+}
+
+pub fn has_undo() -> bool {
+    std::path::Path::new(UNDO_FILE).is_file()
+}
+
+/// 读取恢复默认前备份的设置，读取后删除备份
+pub fn take_undo() -> Option<AppSettings> {
+    let data = fs::read_to_string(UNDO_FILE).ok()?;
+    let _ = fs::remove_file(UNDO_FILE);
+    match AppSettings::parse(&data) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            log::warn!("读取设置备份失败: {}", e);
+            None
+        }
+    }
+}
+
+// 将旧版本的设置 JSON 逐级升级到当前版本
+fn migrate(value: &mut serde_json::Value, from: u32) {
+    if from < 1 {
+        // v0 -> v1：音色库列表从路径字符串改为 SoundfontEntry 对象
+        if let Some(list) = value.get_mut("soundfonts").and_then(|v| v.as_array_mut()) {
+            for item in list.iter_mut() {
+                if let Some(path) = item.as_str() {
+                    *item = serde_json::to_value(SoundfontEntry::new(PathBuf::from(path))).unwrap_or_default();
+                }
+            }
+        }
+        log::info!("已将设置文件从 v0 迁移到 v1");
+    }
+    if from < 2 {
+        // v1 -> v2：实时参数从顶层的平铺字段移到 realtime 对象中，插值算法从数字改为枚举名
+        if let Some(map) = value.as_object_mut() {
+            let mut realtime = serde_json::Map::new();
+            for key in REALTIME_KEYS_V1 {
+                if let Some(v) = map.remove(key) {
+                    realtime.insert(key.to_string(), v);
+                }
+            }
+            if let Some(interpolator) = realtime.get_mut("interpolator") {
+                let name = if interpolator.as_u64() == Some(1) { "Linear" } else { "Nearest" };
+                *interpolator = serde_json::Value::from(name);
+            }
+            // 缺少的字段 (例如很早的设置文件) 由 RealtimeConfig 的默认值补上
+            map.insert("realtime".to_string(), serde_json::Value::Object(realtime));
+        }
+        log::info!("已将设置文件从 v1 迁移到 v2");
+    }
+
+    value["version"] = serde_json::Value::from(SETTINGS_VERSION);
+}
+
+// 与 xxsynth_winmm.dll 共享的驱动配置文件 (%APPDATA%\xxsynth\driver.ini)
+pub fn driver_config_path() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("xxsynth").join("driver.ini"))
+}
+
+// 将驱动需要知道的参数写入共享文件，驱动在宿主打开端口 (MODM_OPEN) 时读取
+pub fn write_driver_config(cfg: &RealtimeConfig) {
+    let Some(path) = driver_config_path() else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let data = format!(
+        "udp_port={}\ntransport={}\nport_count={}\ntimestamps={}\nsequence=1\n",
+        cfg.udp_port,
+        cfg.transport.config_value(),
+        cfg.driver_port_count,
+        if cfg.timestamped_packets { 1 } else { 0 }
+    );
+    if let Err(e) = fs::write(&path, data) {
+        log::error!("写入驱动配置失败 {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// SF2 中每条预设头 (phdr) 与采样头 (shdr) 记录的字节数，两个列表末尾各有一条结束记录
+const SF2_PHDR_SIZE: u32 = 38;
+const SF2_SHDR_SIZE: u32 = 46;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SoundfontFormat {
+    Sf2,
+    Sf3, // 采样用 Ogg Vorbis 压缩的 SF2，文件头版本号 (ifil) 的主版本为 3
+    Sfz,
+}
+
+// SF2 中的一个预设 (乐器)
+#[derive(Clone, Debug)]
+pub struct PresetInfo {
+    pub name: String,
+    pub bank: u16,    // 128 为打击乐
+    pub program: u16,
+}
+
+// 音色库加载后的元数据，用于在列表中显示并帮助排查 "SFZ 没有声音" 之类的问题
+#[derive(Clone, Debug)]
+pub struct SoundfontInfo {
+    pub format: SoundfontFormat,
+    pub presets: Option<usize>,      // SF2 的预设数
+    pub regions: Option<usize>,      // SFZ 的区域 (region) 数
+    pub samples: Option<usize>,      // SF2 的采样数 / SFZ 引用的采样文件数
+    pub sample_dir: Option<PathBuf>, // SFZ 采样文件所在的目录
+    pub missing_samples: usize,      // SFZ 引用但找不到的采样文件数
+    pub preset_list: Vec<PresetInfo>, // SF2 的全部预设，按 Bank / Program 排序
+    pub sample_seconds: Option<f64>, // SF2 全部采样的总时长 (秒)，用于估算加载后占用的内存
+    pub sample_file_bytes: Option<u64>, // SFZ 引用的采样文件的总大小
+}
+
+impl SoundfontInfo {
+    // 在音色库列表中显示的一行摘要
+    pub fn summary(&self) -> String {
+        let count = |n: Option<usize>| n.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string());
+        match self.format {
+            SoundfontFormat::Sf2 => format!("SF2 · {} 个预设 · {} 个采样", count(self.presets), count(self.samples)),
+            SoundfontFormat::Sf3 => format!("SF3 (压缩) · {} 个预设 · {} 个采样", count(self.presets), count(self.samples)),
+            SoundfontFormat::Sfz => format!("SFZ · {} 个区域 · {} 个采样文件", count(self.regions), count(self.samples)),
+        }
+    }
+
+    /// 估算按给定的输出采样率加载后采样占用的内存 (字节)
+    /// xsynth 把采样解码为 32 位浮点并重采样到输出采样率；只加载部分 Bank / Preset 时实际占用更少
+    pub fn estimated_memory(&self, sample_rate: u32) -> Option<u64> {
+        match self.format {
+            SoundfontFormat::Sf2 => self.sample_seconds.map(|secs| (secs * sample_rate as f64 * 4.0) as u64),
+            // SF3 的采样头记录的是压缩数据中的位置，无法据此得到时长
+            SoundfontFormat::Sf3 => None,
+            // 采样文件多为 16 位 PCM，解码为浮点后约为文件大小的两倍 (没有计入重采样)
+            SoundfontFormat::Sfz => self.sample_file_bytes.map(|bytes| bytes * 2),
+        }
+    }
+}
+
+/// 读取音色库的元数据；只读取文件头与索引，不加载采样数据
+/// 解析失败时只记录警告，对应的数量显示为未知
+pub fn read_info(path: &Path) -> SoundfontInfo {
+    let is_sfz = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sfz"));
+    let mut info = SoundfontInfo {
+        format: if is_sfz { SoundfontFormat::Sfz } else { SoundfontFormat::Sf2 },
+        presets: None,
+        regions: None,
+        samples: None,
+        sample_dir: None,
+        missing_samples: 0,
+        preset_list: Vec::new(),
+        sample_seconds: None,
+        sample_file_bytes: None,
+    };
+
+    let result = if is_sfz { read_sfz_info(path, &mut info) } else { read_sf2_info(path, &mut info) };
+    if let Err(e) = result {
+        log::warn!("无法读取音色库信息 {}: {}", path.display(), e);
+    }
+    info
+}
+
+// SFZ：解析全部区域 (包括 #include 与 default_path)，统计引用的采样文件
+fn read_sfz_info(path: &Path, info: &mut SoundfontInfo) -> Result<(), String> {
+    let regions = xsynth_soundfonts::sfz::parse_soundfont(path).map_err(|e| format!("{:?}", e))?;
+
+    let samples: HashSet<&Path> = regions.iter().map(|r| r.sample_path.as_path()).collect();
+    let missing: Vec<&Path> = samples.iter().copied().filter(|p| !p.is_file()).collect();
+    for p in missing.iter().take(5) {
+        log::warn!("SFZ 采样文件不存在: {}", p.display());
+    }
+
+    info.regions = Some(regions.len());
+    info.samples = Some(samples.len());
+    info.missing_samples = missing.len();
+    info.sample_file_bytes = Some(samples.iter().filter_map(|p| std::fs::metadata(p).ok()).map(|m| m.len()).sum());
+    info.sample_dir = common_dir(samples.iter().filter_map(|p| p.parent()));
+    Ok(())
+}
+
+// 多个目录的公共上级目录
+fn common_dir<'a>(mut dirs: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut common = dirs.next()?.to_path_buf();
+    for dir in dirs {
+        while !dir.starts_with(&common) {
+            if !common.pop() {
+                return None;
+            }
+        }
+    }
+    Some(common)
+}
+
+// SF2：遍历 RIFF 块，跳过采样数据，读取 pdta 中的预设头，并根据 shdr 块的大小计算采样数
+fn read_sf2_info(path: &Path, info: &mut SoundfontInfo) -> Result<(), String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+
+    let (riff, _) = read_chunk_header(&mut file)?;
+    let mut form = [0u8; 4];
+    file.read_exact(&mut form).map_err(|e| e.to_string())?;
+    if &riff != b"RIFF" || &form != b"sfbk" {
+        return Err("不是有效的 SF2 文件".to_string());
+    }
+
+    while let Ok((id, size)) = read_chunk_header(&mut file) {
+        // RIFF 块按偶数字节对齐
+        let padded = size as i64 + (size & 1) as i64;
+        if &id != b"LIST" {
+            file.seek(SeekFrom::Current(padded)).map_err(|e| e.to_string())?;
+            continue;
+        }
+
+        let mut list_type = [0u8; 4];
+        file.read_exact(&mut list_type).map_err(|e| e.to_string())?;
+        if &list_type == b"INFO" {
+            if read_sf2_version(&mut file, size)?.0 >= 3 {
+                info.format = SoundfontFormat::Sf3;
+            }
+            continue;
+        }
+        if &list_type != b"pdta" {
+            file.seek(SeekFrom::Current(padded - 4)).map_err(|e| e.to_string())?;
+            continue;
+        }
+
+        let mut remaining = size as i64 - 4;
+        while remaining >= 8 {
+            let (sub_id, sub_size) = read_chunk_header(&mut file)?;
+            let sub_padded = sub_size as i64 + (sub_size & 1) as i64;
+            match &sub_id {
+                b"phdr" => {
+                    let mut data = vec![0u8; sub_padded as usize];
+                    file.read_exact(&mut data).map_err(|e| e.to_string())?;
+                    info.preset_list = parse_presets(&data[..sub_size as usize]);
+                    info.presets = Some(info.preset_list.len());
+                    remaining -= 8 + sub_padded;
+                    continue;
+                }
+                b"shdr" => {
+                    let mut data = vec![0u8; sub_padded as usize];
+                    file.read_exact(&mut data).map_err(|e| e.to_string())?;
+                    info.samples = Some((sub_size / SF2_SHDR_SIZE).saturating_sub(1) as usize);
+                    info.sample_seconds = Some(sample_seconds(&data[..sub_size as usize]));
+                    remaining -= 8 + sub_padded;
+                    continue;
+                }
+                _ => {}
+            }
+            file.seek(SeekFrom::Current(sub_padded)).map_err(|e| e.to_string())?;
+            remaining -= 8 + sub_padded;
+        }
+        return Ok(());
+    }
+
+    Err("找不到预设数据 (pdta) 块".to_string())
+}
+
+// 读取 INFO 列表中的版本号 (ifil：主版本 + 次版本，均为小端 u16)，读完后停在列表末尾
+fn read_sf2_version(file: &mut (impl Read + Seek), list_size: u32) -> Result<(u16, u16), String> {
+    let mut version = (2, 0);
+    let mut remaining = list_size as i64 + (list_size & 1) as i64 - 4;
+    while remaining >= 8 {
+        let (sub_id, sub_size) = read_chunk_header(file)?;
+        let sub_padded = sub_size as i64 + (sub_size & 1) as i64;
+        if &sub_id == b"ifil" && sub_size >= 4 {
+            let mut data = [0u8; 4];
+            file.read_exact(&mut data).map_err(|e| e.to_string())?;
+            version = (u16::from_le_bytes([data[0], data[1]]), u16::from_le_bytes([data[2], data[3]]));
+            file.seek(SeekFrom::Current(sub_padded - 4)).map_err(|e| e.to_string())?;
+        } else {
+            file.seek(SeekFrom::Current(sub_padded)).map_err(|e| e.to_string())?;
+        }
+        remaining -= 8 + sub_padded;
+    }
+    Ok(version)
+}
+
+// 解析 phdr 块：每条记录为 20 字节名称 + Program + Bank (均为小端 u16) + 其余索引字段，最后一条是结束记录 (EOP)
+fn parse_presets(data: &[u8]) -> Vec<PresetInfo> {
+    let records: Vec<&[u8]> = data.chunks_exact(SF2_PHDR_SIZE as usize).collect();
+    let mut presets: Vec<PresetInfo> = records[..records.len().saturating_sub(1)]
+        .iter()
+        .map(|record| {
+            let name = &record[..20];
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            PresetInfo {
+                name: String::from_utf8_lossy(&name[..end]).trim().to_string(),
+                program: u16::from_le_bytes([record[20], record[21]]),
+                bank: u16::from_le_bytes([record[22], record[23]]),
+            }
+        })
+        .collect();
+    presets.sort_by_key(|p| (p.bank, p.program));
+    presets
+}
+
+// 解析 shdr 块：每条记录为 20 字节名称 + 起点 + 终点 (以采样帧计) + 两个循环点 + 采样率 (均为小端 u32) + 其余字段
+// 返回全部采样的总时长，最后一条是结束记录 (EOS)
+fn sample_seconds(data: &[u8]) -> f64 {
+    let records: Vec<&[u8]> = data.chunks_exact(SF2_SHDR_SIZE as usize).collect();
+    let read_u32 = |record: &[u8], offset: usize| u32::from_le_bytes([record[offset], record[offset + 1], record[offset + 2], record[offset + 3]]);
+    records[..records.len().saturating_sub(1)]
+        .iter()
+        .map(|record| {
+            let frames = read_u32(record, 24).saturating_sub(read_u32(record, 20));
+            let rate = read_u32(record, 36);
+            if rate == 0 { 0.0 } else { frames as f64 / rate as f64 }
+        })
+        .sum()
+}
+
+// 读取 RIFF 块头：4 字节 ID + 4 字节小端长度
+fn read_chunk_header(reader: &mut impl Read) -> Result<([u8; 4], u32), String> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+    let id = [header[0], header[1], header[2], header[3]];
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((id, size))
+}
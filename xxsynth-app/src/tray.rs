@@ -0,0 +1,164 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+// 托盘菜单与图标发出的操作，由界面在下一帧处理
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum TrayAction {
+    Show,
+    StartEngine,
+    StopEngine,
+    Quit,
+}
+
+// 当前引擎的运行标志，由界面每帧同步。窗口隐藏时界面不一定会重绘，
+// 停止引擎与退出直接在托盘回调中清除该标志，让引擎立即停下
+type EngineSwitch = Arc<Mutex<Option<Arc<AtomicBool>>>>;
+
+// 托盘图标的边长 (像素)
+#[cfg(windows)]
+const ICON_SIZE: u32 = 32;
+
+// 系统托盘：窗口隐藏到托盘后引擎照常运行，右键菜单可以启动 / 停止引擎或退出，双击图标恢复窗口
+// 目前只在 Windows 上提供 (驱动也只支持 Windows)，其他平台 new() 返回 None
+pub struct Tray {
+    actions: Receiver<TrayAction>,
+    engine: EngineSwitch,
+    #[cfg(windows)]
+    _icon: tray_icon::TrayIcon, // 释放后图标会从托盘消失
+}
+
+impl Tray {
+    /// 创建托盘图标，当前平台不支持或创建失败时返回 None
+    pub fn new(ctx: &egui::Context) -> Option<Self> {
+        #[cfg(windows)]
+        {
+            let engine = EngineSwitch::default();
+            match build(ctx, engine.clone()) {
+                Ok((icon, actions)) => Some(Self { actions, engine, _icon: icon }),
+                Err(e) => {
+                    log::warn!("无法创建托盘图标: {}", e);
+                    None
+                }
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = ctx;
+            None
+        }
+    }
+
+    /// 取出一个待处理的操作
+    pub fn try_action(&self) -> Option<TrayAction> {
+        self.actions.try_recv().ok()
+    }
+
+    /// 同步当前引擎的运行标志 (引擎未运行时为 None)
+    pub fn set_engine(&self, is_running: Option<Arc<AtomicBool>>) {
+        *self.engine.lock().unwrap() = is_running;
+    }
+}
+
+#[cfg(windows)]
+fn build(ctx: &egui::Context, engine: EngineSwitch) -> Result<(tray_icon::TrayIcon, Receiver<TrayAction>), String> {
+    use std::sync::mpsc;
+    use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    use tray_icon::{Icon, MouseButton, TrayIconBuilder, TrayIconEvent};
+
+    let show = MenuItem::new("显示窗口", true, None);
+    let start = MenuItem::new("启动引擎", true, None);
+    let stop = MenuItem::new("停止引擎", true, None);
+    let quit = MenuItem::new("退出", true, None);
+    let menu = Menu::new();
+    menu.append_items(&[
+        &show,
+        &PredefinedMenuItem::separator(),
+        &start,
+        &stop,
+        &PredefinedMenuItem::separator(),
+        &quit,
+    ])
+    .map_err(|e| e.to_string())?;
+
+    let icon = Icon::from_rgba(icon_rgba(), ICON_SIZE, ICON_SIZE).map_err(|e| e.to_string())?;
+    let tray = TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip("XXSynth")
+        .with_icon(icon)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let (action_tx, action_rx) = mpsc::channel();
+    let ids = [
+        (show.id().clone(), TrayAction::Show),
+        (start.id().clone(), TrayAction::StartEngine),
+        (stop.id().clone(), TrayAction::StopEngine),
+        (quit.id().clone(), TrayAction::Quit),
+    ];
+
+    let menu_tx = action_tx.clone();
+    let menu_ctx = ctx.clone();
+    let menu_engine = engine.clone();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        if let Some((_, action)) = ids.iter().find(|(id, _)| *id == event.id) {
+            dispatch(&menu_ctx, &menu_tx, &menu_engine, *action);
+        }
+    }));
+
+    let click_ctx = ctx.clone();
+    TrayIconEvent::set_event_handler(Some(move |event: TrayIconEvent| {
+        if let TrayIconEvent::DoubleClick { button: MouseButton::Left, .. } = event {
+            dispatch(&click_ctx, &action_tx, &engine, TrayAction::Show);
+        }
+    }));
+
+    Ok((tray, action_rx))
+}
+
+// 事件回调运行在其他线程：操作交给界面处理并唤醒界面。恢复窗口、停止引擎与退出直接在这里完成，
+// 不依赖隐藏窗口的重绘；界面下次重绘时再做收尾 (写完录音、保存设置等)
+#[cfg(windows)]
+fn dispatch(ctx: &egui::Context, action_tx: &std::sync::mpsc::Sender<TrayAction>, engine: &EngineSwitch, action: TrayAction) {
+    let _ = action_tx.send(action);
+    match action {
+        TrayAction::Show => {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+        TrayAction::StopEngine | TrayAction::Quit => {
+            if let Some(is_running) = engine.lock().unwrap().as_ref() {
+                is_running.store(false, std::sync::atomic::Ordering::Relaxed);
+            }
+            if action == TrayAction::Quit {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+        TrayAction::StartEngine => {}
+    }
+    ctx.request_repaint();
+}
+
+// 程序化绘制的托盘图标：蓝色圆形底上的白色竖条 (类似音量条)，避免额外的图片资源
+#[cfg(windows)]
+fn icon_rgba() -> Vec<u8> {
+    let size = ICON_SIZE as i32;
+    let center = (size as f32 - 1.0) / 2.0;
+    let mut rgba = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let dist = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            let bar = [(9, 13, 18), (15, 18, 12), (21, 24, 16)].iter().any(|&(x0, x1, top)| x >= x0 && x < x1 && y >= top && y < 24);
+            let pixel = if dist > center + 0.5 {
+                [0, 0, 0, 0]
+            } else if bar {
+                [255, 255, 255, 255]
+            } else {
+                [40, 110, 220, 255]
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    rgba
+}
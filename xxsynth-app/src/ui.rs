@@ -1,6 +1,13 @@
 use eframe::egui;
+use xsynth_core::channel_group::ThreadCount;
 use crate::XXSynthApp;
-use crate::config::InterpolatorWrapper;
+use crate::audio::{send_panic, KeyActivity};
+use crate::diagnostics::{version_summary, XSYNTH_VERSIONS};
+use crate::hotkey::HotkeyBinding;
+use crate::config::{AudioChannels, ChannelRoute, ThruChannel, CurveType, InterpolatorWrapper, LoopMode, OutputFormat, RealtimeConfig, SoundfontEntry, Transport, VelocityCurve, DEFAULT_VELOCITY_GAMMA, MAX_DRIVER_PORTS, MAX_GAIN, MAX_MIDI_KEY, MAX_RENDER_WINDOW_MS, MAX_STOP_FADE_MS, MAX_VELOCITY_GAMMA, MIN_RENDER_WINDOW_MS, MIN_VELOCITY_GAMMA, SAMPLE_RATES};
+use crate::player::{MidiPlayer, MAX_PLAYBACK_SPEED, MIN_PLAYBACK_SPEED};
+use crate::settings::{self, ThemeMode};
+use crate::render::{spawn_queue_render, spawn_render, QueueItem, QueueStatus};
 
 // 将 UI 绘制逻辑独立出来
 impl XXSynthApp {
@@ -10,17 +17,24 @@ impl XXSynthApp {
         ui.separator();
 
         let mut changed = false;
+        // 本帧修改之前列表是否与引擎正在使用的一致，不一致说明还有未应用的修改
+        let list_applied = self.audio_handle.as_ref().is_some_and(|h| h.soundfonts == self.soundfonts);
 
         ui.horizontal(|ui| {
             if ui.button("➕ 添加音色文件...").clicked() {
                 if let Some(path) = rfd::FileDialog::new()
-                    .add_filter("Soundfonts", &["sf2", "sfz"])
+                    .add_filter("Soundfonts", &["sf2", "sf3", "sfz"])
                     .pick_file() 
                 {
-                    self.soundfonts.push(path);
+                    self.soundfonts.push(SoundfontEntry::new(path));
                     changed = true;
                 }
             }
+            if ui.button("📁 添加文件夹...").on_hover_text("递归查找文件夹中的 SF2 / SF3 / SFZ，按路径排序后加入列表").clicked()
+                && let Some(dir) = rfd::FileDialog::new().pick_folder()
+            {
+                self.add_soundfont_folder(&dir);
+            }
             if ui.button("\u{1F5D1} 清空列表").clicked() {
                 if !self.soundfonts.is_empty() {
                     self.soundfonts.clear();
@@ -32,10 +46,73 @@ impl XXSynthApp {
             let btn_text = "🔄 保存并应用";
             let mut btn = egui::Button::new(egui::RichText::new(btn_text));
             if self.is_dirty {
-                btn = btn.fill(egui::Color32::from_rgb(255, 127, 127));
+                btn = btn.fill(self.accent_or(egui::Color32::from_rgb(255, 127, 127)));
             }
-            if ui.add(btn).clicked() {
-                self.restart_engine();
+            if ui.add_enabled(!self.is_restarting(), btn).on_hover_text("引擎参数未修改时只热重载音色库，不会中断播放").clicked() {
+                self.apply_soundfonts();
+            }
+
+            if ui.button("🔊 测试声音").on_hover_text("在通道 1 上播放 C 大三和弦，确认音色库与输出设备正常").clicked() {
+                self.play_test_chord();
+            }
+        });
+
+        // 已加载音色库的内存估算，移除音色库并应用后应随之下降
+        let memory = crate::audio::soundfont_memory();
+        if memory.loaded > 0 {
+            ui.horizontal(|ui| {
+                let mut text = format!("音色库内存 (估算): {} · {} 个已加载", format_bytes(memory.bytes), memory.loaded);
+                if memory.unestimated > 0 {
+                    text.push_str(&format!("，{} 个无法估算", memory.unestimated));
+                }
+                ui.label(text).on_hover_text("按采样时长 × 输出采样率 × 4 字节估算；只加载部分 Bank / Preset 时实际占用更少");
+                if ui.button("🧹 卸载未使用音色")
+                    .on_hover_text("释放已不在任何通道上使用的音色库 (例如加载被中止后残留的)")
+                    .clicked()
+                {
+                    let (count, bytes) = crate::audio::release_unused_soundfonts();
+                    if count == 0 {
+                        self.set_status(log::Level::Info, "没有未使用的音色库。");
+                    } else {
+                        self.set_status(log::Level::Info, format!("已卸载 {} 个未使用的音色库，约释放 {}。", count, format_bytes(bytes)));
+                    }
+                }
+            });
+        }
+
+        // 音色库布局预设：载入后只修改列表并打上脏标记，由用户确认后再保存并应用
+        ui.horizontal(|ui| {
+            ui.label("布局预设:").on_hover_text("保存 / 切换整组音色库 (含各自的 Bank、Preset 与启用状态)");
+            egui::ComboBox::from_id_salt("sf_layout_preset")
+                .selected_text("载入...")
+                .show_ui(ui, |ui| {
+                    let names = settings::list_soundfont_presets();
+                    if names.is_empty() {
+                        ui.label(egui::RichText::new("(还没有保存的预设)").weak());
+                    }
+                    for name in names {
+                        if ui.selectable_label(false, &name).clicked() {
+                            match settings::load_soundfont_preset(&name) {
+                                Ok(soundfonts) => {
+                                    self.soundfonts = soundfonts;
+                                    changed = true;
+                                    self.set_status(log::Level::Info, format!("已载入预设 [{}]，点击【保存并应用】后生效。", name));
+                                    self.sf_preset_name = name;
+                                }
+                                Err(e) => self.set_status(log::Level::Error, format!("载入预设失败: {}", e)),
+                            }
+                        }
+                    }
+                });
+            ui.add(egui::TextEdit::singleline(&mut self.sf_preset_name).hint_text("预设名称").desired_width(140.0));
+            if ui.add_enabled(!self.sf_preset_name.trim().is_empty(), egui::Button::new("💾 保存为预设"))
+                .on_hover_text("同名预设会被覆盖")
+                .clicked()
+            {
+                match settings::save_soundfont_preset(&self.sf_preset_name, &self.soundfonts) {
+                    Ok(()) => self.set_status(log::Level::Info, format!("已保存预设 [{}]", self.sf_preset_name.trim())),
+                    Err(e) => self.set_status(log::Level::Error, format!("保存预设失败: {}", e)),
+                }
             }
         });
 
@@ -44,20 +121,103 @@ impl XXSynthApp {
         let mut to_remove = None;
         let mut move_up = None;
         let mut move_down = None;
+        let mut toggled = false;
+        let mut audition = None;
+        // 引擎当前使用的列表，与之对比得出每一项是否已生效
+        let applied = self.audio_handle.as_ref().map(|h| h.soundfonts.clone());
+
+        // 上次加载的结果，先克隆出来，避免在绘制时长时间持有锁
+        let load_results = self.sf_load_results.lock().map(|r| r.clone()).unwrap_or_default();
 
         egui::ScrollArea::vertical().show(ui, |ui| {
             let sf_len = self.soundfonts.len();
-            for (i, path) in self.soundfonts.iter().enumerate() {
+            for (i, entry) in self.soundfonts.iter_mut().enumerate() {
                 ui.horizontal(|ui| {
                     ui.label(format!("{}.", i + 1));
                     
                     if ui.add_enabled(i > 0, egui::Button::new("⬆")).clicked() { move_up = Some(i); }
                     if ui.add_enabled(i < sf_len.saturating_sub(1), egui::Button::new("⬇")).clicked() { move_down = Some(i); }
                     if ui.button("❌").clicked() { to_remove = Some(i); }
+                    toggled |= ui.checkbox(&mut entry.enabled, "").on_hover_text("启用 / 禁用该音色库").changed();
                     
-                    ui.label(egui::RichText::new(path.file_name().unwrap_or_default().to_string_lossy()).strong());
+                    let name = egui::RichText::new(entry.path.file_name().unwrap_or_default().to_string_lossy()).strong();
+                    ui.label(if entry.enabled { name } else { name.strikethrough().weak() });
+
+                    if let Some(applied) = &applied {
+                        if applied.get(i) == Some(&*entry) {
+                            ui.label(egui::RichText::new("✔ 已应用").small().color(egui::Color32::from_rgb(0, 160, 0)));
+                        } else {
+                            ui.label(egui::RichText::new("● 待应用").small().color(egui::Color32::from_rgb(230, 160, 0)))
+                                .on_hover_text("该项的修改还没有交给引擎，点击【保存并应用】后生效");
+                        }
+                    }
+
+                    if let Some((_, Err(err))) = load_results.iter().find(|(p, _)| *p == entry.path) {
+                        ui.label(egui::RichText::new("❌").color(egui::Color32::RED))
+                            .on_hover_text(format!("加载失败: {}", err));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new(entry.path.to_string_lossy()).small().weak());
+                });
+                if entry.enabled
+                    && let Some((_, Ok(info))) = load_results.iter().find(|(p, _)| *p == entry.path)
+                {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(info.summary()).small());
+                        if let Some(dir) = &info.sample_dir {
+                            ui.label(egui::RichText::new(format!("采样目录: {}", dir.display())).small().weak());
+                        }
+                        if info.missing_samples > 0 {
+                            ui.label(egui::RichText::new(format!("⚠ 缺少 {} 个采样文件", info.missing_samples)).small().color(egui::Color32::RED))
+                                .on_hover_text("SFZ 中 sample= 指向的文件不存在，对应的音符不会发声。请检查采样目录与 default_path 设置，详情见日志");
+                        }
+                    });
+                    if !info.preset_list.is_empty() {
+                        egui::CollapsingHeader::new(format!("预设列表 ({})", info.preset_list.len()))
+                            .id_salt(("sf_presets", i))
+                            .show(ui, |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("🔍");
+                                    ui.add(egui::TextEdit::singleline(&mut self.preset_filter).hint_text("按名称或 Bank:Program 搜索").desired_width(200.0));
+                                });
+                                let filter = self.preset_filter.trim().to_lowercase();
+                                egui::ScrollArea::vertical().id_salt(("sf_preset_list", i)).max_height(200.0).show(ui, |ui| {
+                                    for preset in &info.preset_list {
+                                        let number = format!("{:03}:{:03}", preset.bank, preset.program);
+                                        if !filter.is_empty() && !preset.name.to_lowercase().contains(&filter) && !number.contains(&filter) {
+                                            continue;
+                                        }
+                                        let text = egui::RichText::new(format!("{}  {}", number, preset.name)).monospace();
+                                        if ui.selectable_label(false, text).on_hover_text("点击试听 (会切换通道 1 的音色，打击乐使用通道 10)").clicked() {
+                                            audition = Some((preset.bank, preset.program));
+                                        }
+                                    }
+                                });
+                            });
+                    }
+                }
+                ui.horizontal(|ui| {
+                    changed |= optional_u8_field(ui, "仅 Bank:", &mut entry.bank, 0..=128);
+                    ui.add_space(10.0);
+                    changed |= optional_u8_field(ui, "仅 Preset:", &mut entry.preset, 0..=127);
+                    ui.add_space(10.0);
+                    ui.label("插值:").on_hover_text("可为单个音色库单独指定插值算法，例如体积大的鼓组用最近邻省 CPU，主奏钢琴用线性");
+                    egui::ComboBox::from_id_salt(("sf_interpolator", i))
+                        .selected_text(entry.interpolator.map_or("跟随全局".to_string(), |interp| interp.to_string()))
+                        .show_ui(ui, |ui| {
+                            changed |= ui.selectable_value(&mut entry.interpolator, None, "跟随全局").changed();
+                            for interp in InterpolatorWrapper::ALL {
+                                changed |= ui.selectable_value(&mut entry.interpolator, Some(interp), interp.to_string()).changed();
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("包络曲线:").on_hover_text("音量包络各阶段的变化曲线，修改后需保存并应用");
+                    changed |= curve_combo(ui, ("sf_attack", i), "起音", &mut entry.envelope.attack);
+                    changed |= curve_combo(ui, ("sf_decay", i), "衰减", &mut entry.envelope.decay);
+                    changed |= curve_combo(ui, ("sf_release", i), "释音", &mut entry.envelope.release);
                 });
-                ui.label(egui::RichText::new(path.to_string_lossy()).small().weak());
                 ui.separator();
             }
         });
@@ -65,22 +225,297 @@ impl XXSynthApp {
         // 处理队列修改操作并打上脏标记
         if let Some(i) = move_up {
             self.soundfonts.swap(i, i - 1);
-            changed = true;
         }
         if let Some(i) = move_down {
             self.soundfonts.swap(i, i + 1);
-            changed = true;
         }
         if let Some(i) = to_remove {
             self.soundfonts.remove(i);
-            changed = true;
+        }
+        if let Some((bank, program)) = audition {
+            self.audition_preset(bank, program);
         }
 
-        if changed {
+        // 调整顺序、启用 / 禁用、移除只是重新组合已加载的音色库 (有缓存，几乎不需要读盘)，引擎运行时直接生效
+        // 列表中还有其他未应用的修改时不能顺带应用，只打上脏标记
+        let rearranged = toggled || move_up.is_some() || move_down.is_some() || to_remove.is_some();
+        if rearranged && !changed && list_applied && self.can_hot_reload_soundfonts() {
+            self.apply_soundfonts();
+        } else if changed || rearranged {
             self.is_dirty = true;
         }
     }
 
+    // 通过运行中的引擎播放测试和弦，无法出声时给出提示
+    fn play_test_chord(&mut self) {
+        let Some(handle) = self.audio_handle.as_ref().filter(|h| h.is_running.load(std::sync::atomic::Ordering::Relaxed)) else {
+            self.set_status(log::Level::Warn, "引擎未运行，请先在【实时设置】中启动引擎。");
+            return;
+        };
+        let loaded_any = self.sf_load_results.lock().map(|r| r.iter().any(|(_, res)| res.is_ok())).unwrap_or(false);
+        if !loaded_any {
+            if self.is_dirty {
+                self.set_status(log::Level::Warn, "没有已加载的音色库，请先点击【保存并应用】。");
+            } else {
+                self.set_status(log::Level::Warn, "没有已加载的音色库，请先添加并启用至少一个音色文件。");
+            }
+            return;
+        }
+
+        handle.play_test_chord();
+        self.set_status(log::Level::Info, "正在播放测试和弦 (C - E - G)...");
+    }
+
+    // 在预设浏览器中点击某个预设时试听
+    fn audition_preset(&mut self, bank: u16, program: u16) {
+        let Some(handle) = self.audio_handle.as_ref().filter(|h| h.is_running.load(std::sync::atomic::Ordering::Relaxed)) else {
+            self.set_status(log::Level::Warn, "引擎未运行，请先在【实时设置】中启动引擎。");
+            return;
+        };
+        handle.audition_preset(bank, program);
+        self.set_status(log::Level::Info, format!("正在试听预设 Bank {} / Program {}", bank, program));
+    }
+
+    // 虚拟 MIDI 端口注册失败 / 冲突时的提示条
+    pub(crate) fn ui_port_banner(&mut self, ui: &mut egui::Ui) {
+        let warn = egui::Color32::from_rgb(230, 160, 0);
+        ui.horizontal(|ui| {
+            match &self.port_registration {
+                crate::PortRegistration::Unregistered => {
+                    ui.colored_label(warn, "⚠ 虚拟端口已卸载，宿主程序中将看不到 XXSynth 端口。");
+                    if ui.button("🔌 重新注册").clicked() {
+                        self.retry_port_registration();
+                    }
+                }
+                crate::PortRegistration::NoFreeSlot => {
+                    ui.colored_label(warn, "⚠ 注册表中的 midi1 ~ midi9 均已被其他驱动占用，宿主程序中将看不到 XXSynth 端口。");
+                    if ui.button("🔍 重新检测").clicked() {
+                        self.retry_port_registration();
+                    }
+                }
+                _ => {
+                    ui.colored_label(warn, "⚠ 未以管理员身份运行，虚拟端口未注册。");
+                    if ui.button("🛡 以管理员身份重试").clicked() {
+                        self.retry_port_registration();
+                    }
+                }
+            }
+        });
+    }
+
+    // 关于窗口：程序与实际链接的 xsynth 各组件版本，反馈问题时附上
+    pub(crate) fn ui_about_window(&mut self, ctx: &egui::Context) {
+        let mut copy = false;
+        egui::Window::new("关于 XXSynth")
+            .open(&mut self.show_about)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("about_versions").num_columns(2).spacing([20.0, 4.0]).show(ui, |ui| {
+                    ui.label("XXSynth");
+                    ui.label(egui::RichText::new(env!("CARGO_PKG_VERSION")).monospace());
+                    ui.end_row();
+                    for (name, version) in XSYNTH_VERSIONS {
+                        ui.label(name);
+                        ui.label(egui::RichText::new(version).monospace());
+                        ui.end_row();
+                    }
+                });
+                ui.add_space(5.0);
+                copy = ui.button("📋 复制版本信息").clicked();
+            });
+        if copy {
+            ctx.copy_text(version_summary());
+            self.set_status(log::Level::Info, "版本信息已复制到剪贴板。");
+        }
+    }
+
+    // 全局紧急静音快捷键：开关与重新绑定，点击 "更改" 后按下新的组合，Esc 取消
+    fn ui_panic_hotkey(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = self.panic_hotkey.is_some();
+            if ui.checkbox(&mut enabled, "全局紧急静音快捷键")
+                .on_hover_text("程序不在前台或隐藏到托盘时也能触发，立即停止所有通道的声音")
+                .changed()
+            {
+                self.panic_hotkey = enabled.then(HotkeyBinding::default);
+                self.capturing_hotkey = false;
+                self.apply_panic_hotkey();
+                self.save_settings();
+            }
+            let Some(binding) = &self.panic_hotkey else { return };
+
+            if self.capturing_hotkey {
+                ui.label(egui::RichText::new("请按下新的组合 (Esc 取消)...").italics());
+                let pressed = ui.input(|i| i.events.iter().find_map(|e| match e {
+                    egui::Event::Key { key, pressed: true, modifiers, .. } => Some((*key, *modifiers)),
+                    _ => None,
+                }));
+                match pressed {
+                    Some((egui::Key::Escape, _)) => self.capturing_hotkey = false,
+                    Some((key, modifiers)) => {
+                        if let Some(new_binding) = HotkeyBinding::from_key(key, modifiers) {
+                            self.panic_hotkey = Some(new_binding);
+                            self.capturing_hotkey = false;
+                            self.apply_panic_hotkey();
+                            self.save_settings();
+                        }
+                    }
+                    None => {}
+                }
+            } else {
+                ui.label(egui::RichText::new(binding.label()).monospace().strong());
+                if ui.button("更改").on_hover_text("字母与数字键需要配合 Ctrl 或 Alt，F1 ~ F24 可以单独使用").clicked() {
+                    self.capturing_hotkey = true;
+                }
+            }
+            if let Some(e) = &self.hotkey_error {
+                ui.colored_label(egui::Color32::RED, format!("⚠ {}", e));
+            }
+        });
+    }
+
+    // 外观：主题与强调色，修改后立即生效并保存
+    fn ui_appearance(&mut self, ui: &mut egui::Ui) {
+        ui.add_space(10.0);
+        ui.label(egui::RichText::new("外观").strong());
+        ui.horizontal(|ui| {
+            ui.label("主题:");
+            let mut changed = false;
+            for mode in ThemeMode::ALL {
+                changed |= ui.radio_value(&mut self.theme, mode, mode.label()).changed();
+            }
+            if changed {
+                self.apply_theme(ui.ctx());
+                self.save_settings();
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut custom = self.accent_color.is_some();
+            if ui.checkbox(&mut custom, "自定义强调色").on_hover_text("用于底部的运行状态与“需要应用”的按钮").changed() {
+                self.accent_color = custom.then_some([90, 140, 230]);
+                self.save_settings();
+            }
+            if let Some(color) = &mut self.accent_color
+                && egui::color_picker::color_edit_button_srgb(ui, color).changed()
+            {
+                self.save_settings();
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut auto_hide = self.status_auto_hide_secs > 0;
+            if ui.checkbox(&mut auto_hide, "状态栏消息自动清除")
+                .on_hover_text("消息显示一段时间后从状态栏清除，仍可点击状态栏的 🕘 查看最近的消息")
+                .changed()
+            {
+                self.status_auto_hide_secs = if auto_hide { 10 } else { 0 };
+                self.save_settings();
+            }
+            if auto_hide
+                && ui.add(egui::DragValue::new(&mut self.status_auto_hide_secs).range(1..=600).suffix(" 秒")).changed()
+            {
+                self.save_settings();
+            }
+        });
+    }
+
+    // 恢复默认设置的确认对话框
+    fn ui_reset_dialog(&mut self, ctx: &egui::Context) {
+        if !self.confirm_reset {
+            return;
+        }
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("恢复默认设置")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("所有实时参数将恢复为默认值，引擎会随之重启。");
+                ui.label("当前设置会先备份，之后可以撤销一次。");
+                ui.checkbox(&mut self.reset_clear_soundfonts, "同时清空音色库列表");
+                ui.add_space(10.0);
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("确定恢复").clicked();
+                    cancelled = ui.button("取消").clicked();
+                });
+            });
+        if confirmed {
+            self.confirm_reset = false;
+            self.reset_to_defaults(self.reset_clear_soundfonts);
+        } else if cancelled {
+            self.confirm_reset = false;
+        }
+    }
+
+    // 状态栏的消息历史按钮，点击弹出最近的消息，错误与警告按级别着色
+    pub(crate) fn ui_status_history(&mut self, ui: &mut egui::Ui) {
+        let has_error = self.status_history.iter().any(|e| e.level == log::Level::Error);
+        let icon = if has_error { egui::RichText::new("🕘").color(log_level_color(log::Level::Error)) } else { egui::RichText::new("🕘") };
+        ui.menu_button(icon, |ui| {
+            ui.set_min_width(360.0);
+            ui.horizontal(|ui| {
+                ui.strong("最近的消息");
+                if ui.small_button("🧹 清空").clicked() {
+                    self.status_history.clear();
+                }
+            });
+            ui.separator();
+            if self.status_history.is_empty() {
+                ui.weak("(无)");
+            }
+            egui::ScrollArea::vertical().id_salt("status_history_scroll").max_height(300.0).show(ui, |ui| {
+                for entry in self.status_history.iter().rev() {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(egui::RichText::new(format!("{:>8.1}", entry.elapsed)).monospace().weak());
+                        ui.colored_label(log_level_color(entry.level), &entry.message);
+                    });
+                }
+            });
+        })
+        .response
+        .on_hover_text("查看最近的状态栏消息");
+    }
+
+    // 日志面板：按级别过滤，新日志到来时自动滚动到底部
+    pub(crate) fn ui_log_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.strong("日志");
+            ui.separator();
+            ui.label("级别:");
+            egui::ComboBox::from_id_salt("log_level")
+                .selected_text(self.log_level.as_str())
+                .show_ui(ui, |ui| {
+                    for level in [log::Level::Error, log::Level::Warn, log::Level::Info] {
+                        ui.selectable_value(&mut self.log_level, level, level.as_str());
+                    }
+                });
+            ui.checkbox(&mut self.log_autoscroll, "自动滚动");
+            if ui.button("🧹 清空").clicked() {
+                crate::logger::clear();
+            }
+        });
+        ui.separator();
+
+        let entries = crate::logger::entries(self.log_level);
+        egui::ScrollArea::vertical()
+            .id_salt("log_scroll")
+            .auto_shrink([false, false])
+            .stick_to_bottom(self.log_autoscroll)
+            .show(ui, |ui| {
+                for entry in &entries {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(egui::RichText::new(format!("{:>8.3}", entry.elapsed)).monospace().weak());
+                        ui.label(egui::RichText::new(entry.level.as_str()).monospace().color(log_level_color(entry.level)));
+                        ui.label(&entry.message);
+                    });
+                }
+            });
+
+        // 后台线程写入的日志不会触发重绘，打开面板时定期刷新
+        ui.ctx().request_repaint_after(std::time::Duration::from_millis(250));
+    }
+
     pub(crate) fn ui_realtime(&mut self, ui: &mut egui::Ui) {
         ui.heading("实时播放参数");
         ui.label("修改参数后点击下方【应用更改】即可重启引擎并保存到本地。");
@@ -89,33 +524,333 @@ impl XXSynthApp {
         let is_running = self.is_running();
         let mut cfg_changed = false;
 
+        if let Some(handle) = &self.audio_handle {
+            ui.horizontal(|ui| {
+                ui.label("活跃复音数:");
+                ui.label(egui::RichText::new(handle.voice_count.load(std::sync::atomic::Ordering::Relaxed).to_string()).strong().monospace());
+            });
+            ui.horizontal(|ui| {
+                ui.label("封包速率:");
+                let rate = handle.packet_rate.load(std::sync::atomic::Ordering::Relaxed);
+                ui.label(egui::RichText::new(format!("{} 包/秒", rate)).strong().monospace());
+                ui.separator();
+                ui.label("丢包:").on_hover_text("仅 UDP 模式下可检测，根据驱动附加的序号统计，引擎重启后清零");
+                let dropped = handle.packets_dropped.load(std::sync::atomic::Ordering::Relaxed);
+                let text = egui::RichText::new(dropped.to_string()).strong().monospace();
+                if dropped > 0 {
+                    ui.label(text.color(ui.visuals().warn_fg_color));
+                } else {
+                    ui.label(text);
+                }
+            });
+            packet_rate_sparkline(ui, &self.packet_rate_history);
+            ui.horizontal(|ui| {
+                ui.label("渲染负载:").on_hover_text("xsynth 渲染一个缓冲区所用的时间占可用时间的比例，接近 100% 时就会出现爆音");
+                let load = handle.renderer_load();
+                let color = if load < 0.7 {
+                    egui::Color32::from_rgb(0, 180, 0)
+                } else if load < 0.9 {
+                    ui.visuals().warn_fg_color
+                } else {
+                    ui.visuals().error_fg_color
+                };
+                ui.add(egui::ProgressBar::new(load.clamp(0.0, 1.0)).fill(color).desired_width(150.0).text(format!("{:.0}%", load * 100.0)));
+                ui.separator();
+                ui.label("欠载:").on_hover_text("音频回调取数据时缓冲区已被读空的次数，每次都会听到爆音，引擎重启后清零");
+                let underruns = handle.underruns.load(std::sync::atomic::Ordering::Relaxed);
+                let text = egui::RichText::new(underruns.to_string()).strong().monospace();
+                if underruns > 0 {
+                    ui.label(text.color(ui.visuals().warn_fg_color));
+                } else {
+                    ui.label(text);
+                }
+                // 刚发生欠载时闪烁提示
+                if self.underrun_warning_until.is_some() && (ui.input(|i| i.time) * 4.0) as i64 % 2 == 0 {
+                    ui.colored_label(ui.visuals().error_fg_color, "⚠ 出现爆音，请调大缓冲区或降低复音层数");
+                }
+            });
+            ui.add_space(5.0);
+        }
+
+        // 全局音量直接发送给运行中的引擎，不需要重启
+        ui.horizontal(|ui| {
+            ui.label("全局音量:");
+            let resp = ui.add(egui::Slider::new(&mut self.realtime_config.gain, 0.0..=MAX_GAIN).fixed_decimals(2))
+                .on_hover_text("按比例缩放每个通道的音量 (CC 7)，结果最高为 127，音量已经较大的通道放大的幅度会更小");
+            if resp.changed()
+                && let Some(handle) = &mut self.audio_handle
+            {
+                handle.set_gain(self.realtime_config.gain);
+            }
+            // 拖动结束后再保存，避免拖动过程中频繁写盘
+            if resp.drag_stopped() || (resp.changed() && !resp.dragged()) {
+                self.save_settings();
+            }
+        });
+
+        // 复音层数上限同样实时生效，CPU 吃紧时可以边播放边调低
+        ui.horizontal(|ui| {
+            ui.label("复音层数上限:");
+            let resp = ui.add(egui::Slider::new(&mut self.realtime_config.layer_limit, 0..=64))
+                .on_hover_text("0 为不限制。调低后只影响之后的新音符，正在发声的音符不会被切断");
+            if resp.changed()
+                && let Some(handle) = &mut self.audio_handle
+            {
+                handle.set_layer_limit(self.realtime_config.layer_limit);
+            }
+            if resp.drag_stopped() || (resp.changed() && !resp.dragged()) {
+                self.save_settings();
+            }
+            if self.realtime_config.layer_limit == 0 {
+                ui.label(egui::RichText::new("(不限制)").weak());
+            }
+        });
+
+        // 效果开关：切换后热重载音色库
+        if ui.checkbox(&mut self.realtime_config.use_effects, "启用音色库效果")
+            .on_hover_text("启用音色库自带的滤波器等效果，会增加 CPU 占用。\n注意：XSynth 目前没有混响 / 合唱效果器。")
+            .changed()
+        {
+            if let Some(handle) = &mut self.audio_handle {
+                handle.set_use_effects(self.realtime_config.use_effects, self.soundfonts.clone());
+                self.set_status(log::Level::Info, "正在按新的效果设置重新加载音色库...");
+            }
+            self.save_settings();
+        }
+
+        // 触后开关：实时生效，极端音符量下关闭可以省去额外的表情事件
+        if ui.checkbox(&mut self.realtime_config.aftertouch, "处理触后 (表情)")
+            .on_hover_text("通道触后与复音触后按力度缩放通道表情 (CC 11)。\nXSynth 不支持单个按键的音量调制，复音触后作用于整个通道。")
+            .changed()
+        {
+            if let Some(handle) = &mut self.audio_handle {
+                handle.set_aftertouch(self.realtime_config.aftertouch);
+            }
+            self.save_settings();
+        }
+
+        // GM 打击乐通道：实时生效，黑乐谱通常不遵守 GM，默认关闭
+        if ui.checkbox(&mut self.realtime_config.gm_drums, "通道 10 作为打击乐 (GM/GS)")
+            .on_hover_text("按 GM/GS 标准，每个驱动端口的通道 10 使用音色库的打击乐预设 (Bank 128)。\n播放普通 GM 乐曲时开启；黑乐谱通常把通道 10 当普通乐器用，保持关闭。\n设置了通道重映射时，作用于通道 10 重映射后的目标通道。")
+            .changed()
+        {
+            if let Some(handle) = &mut self.audio_handle {
+                handle.set_gm_drums(self.realtime_config.gm_drums);
+            }
+            self.save_settings();
+        }
+
+        // 力度曲线：实时生效，右侧为映射关系的预览
+        ui.horizontal(|ui| {
+            ui.label("力度曲线:").on_hover_text("映射 NoteOn 力度，键盘手感太轻或太重时调整。\n忽略力度范围按映射前的原始力度判断。");
+            let curve = &mut self.realtime_config.velocity_curve;
+            let mut changed = false;
+            let mut save = false;
+            egui::ComboBox::from_id_salt("velocity_curve")
+                .selected_text(curve.label())
+                .show_ui(ui, |ui| {
+                    for option in [VelocityCurve::Linear, VelocityCurve::Exponential, VelocityCurve::Gamma(DEFAULT_VELOCITY_GAMMA)] {
+                        let selected = std::mem::discriminant(curve) == std::mem::discriminant(&option);
+                        if ui.selectable_label(selected, option.label()).clicked() && !selected {
+                            *curve = option;
+                            changed = true;
+                            save = true;
+                        }
+                    }
+                });
+            if let VelocityCurve::Gamma(gamma) = curve {
+                let resp = ui.add(egui::DragValue::new(gamma).range(MIN_VELOCITY_GAMMA..=MAX_VELOCITY_GAMMA).speed(0.01).fixed_decimals(2))
+                    .on_hover_text("小于 1 时轻触也更响，大于 1 时需要更用力");
+                changed |= resp.changed();
+                // 拖动结束后再保存，避免拖动过程中频繁写盘
+                save |= resp.drag_stopped() || (resp.changed() && !resp.dragged());
+            }
+            velocity_curve_preview(ui, curve);
+
+            if changed
+                && let Some(handle) = &mut self.audio_handle
+            {
+                handle.set_velocity_curve(self.realtime_config.velocity_curve);
+            }
+            if save {
+                self.save_settings();
+            }
+        });
+
+        // 音高过滤：实时生效，丢弃范围外的 NoteOn
+        ui.horizontal(|ui| {
+            ui.label("保留音高:").on_hover_text("只发声该范围内的音符，可去掉黑乐谱中听不见的超低 / 超高音，节省复音数。\n也可用作键盘分区。NoteOff 不受影响。");
+            let cfg = &mut self.realtime_config;
+            let min_resp = ui.add(egui::Slider::new(&mut cfg.ignore_key_min, 0..=MAX_MIDI_KEY).custom_formatter(|v, _| note_name(v as u8)));
+            ui.label("至");
+            let max_resp = ui.add(egui::Slider::new(&mut cfg.ignore_key_max, 0..=MAX_MIDI_KEY).custom_formatter(|v, _| note_name(v as u8)));
+            // 保持 最小值 <= 最大值，拖动哪一端就推动另一端
+            if min_resp.changed() && cfg.ignore_key_min > cfg.ignore_key_max {
+                cfg.ignore_key_max = cfg.ignore_key_min;
+            } else if max_resp.changed() && cfg.ignore_key_max < cfg.ignore_key_min {
+                cfg.ignore_key_min = cfg.ignore_key_max;
+            }
+            if !cfg.is_key_filter_enabled() {
+                ui.label(egui::RichText::new("(已关闭)").weak());
+            }
+
+            let changed = min_resp.changed() || max_resp.changed();
+            if changed
+                && let Some(handle) = &mut self.audio_handle
+            {
+                handle.set_key_range(self.realtime_config.ignore_key_min, self.realtime_config.ignore_key_max);
+            }
+            // 拖动结束后再保存，避免拖动过程中频繁写盘
+            if [&min_resp, &max_resp].iter().any(|r| r.drag_stopped() || (r.changed() && !r.dragged())) {
+                self.save_settings();
+            }
+        });
+        ui.add_space(5.0);
+
         {
             let cfg = &mut self.realtime_config;
 
             // 移除了 striped(true) 以去掉灰白条
             egui::Grid::new("realtime_grid").num_columns(2).spacing([40.0, 10.0]).show(ui, |ui| {
-                ui.label("UDP 监听端口:");
+                ui.label("传输协议:");
+                ui.horizontal(|ui| {
+                    cfg_changed |= ui.radio_value(&mut cfg.transport, Transport::Udp, "UDP (默认，延迟最低)")
+                        .on_hover_text("极端音符量下可能丢包，导致挂音或漏音")
+                        .changed();
+                    cfg_changed |= ui.radio_value(&mut cfg.transport, Transport::Tcp, "TCP (保证顺序与送达)")
+                        .on_hover_text("适合黑乐谱等超大音符量，延迟略高；驱动在下次打开端口时切换")
+                        .changed();
+                });
+                ui.end_row();
+
+                ui.label("事件时间戳:");
+                cfg_changed |= ui.checkbox(&mut cfg.timestamped_packets, "按驱动时间戳排布事件")
+                    .on_hover_text("驱动为每条消息附带时间戳，引擎延迟一个渲染窗口后按原有间隔发声，\n减少密集段落的时间抖动。驱动在下次打开端口时生效")
+                    .changed();
+                ui.end_row();
+
+                ui.label("监听端口:");
                 cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.udp_port)).changed();
                 ui.end_row();
+                if let Some(e) = cfg.udp_port_error() {
+                    validation_row(ui, &e, egui::Color32::RED);
+                } else if let Some(w) = cfg.udp_port_warning() {
+                    validation_row(ui, &w, egui::Color32::from_rgb(230, 160, 0));
+                }
+
+                ui.label("端口数量:");
+                ui.horizontal(|ui| {
+                    cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.udp_port_count).range(1..=8)).changed();
+                    if cfg.udp_port_count > 1 {
+                        let ports = cfg.udp_ports();
+                        ui.label(egui::RichText::new(format!(
+                            "端口 {} ~ {}，每个端口 {} 个通道",
+                            ports[0],
+                            ports[ports.len() - 1],
+                            cfg.total_channels / cfg.udp_port_count as u32
+                        )).weak());
+                    }
+                }).response.on_hover_text("供多个 MIDI 程序同时使用，每个程序发往不同的端口，互不干扰");
+                ui.end_row();
 
                 ui.label("总通道数:");
-                cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.total_channels).range(16..=256)).changed();
+                let resp = ui.add(egui::DragValue::new(&mut cfg.total_channels).range(16..=256));
+                cfg_changed |= resp.changed();
+                // 输入结束后向上取整到 16 的倍数，每个驱动端口正好对应 16 个通道
+                if (resp.drag_stopped() || resp.lost_focus()) && !cfg.total_channels.is_multiple_of(16) {
+                    cfg.total_channels = (cfg.total_channels.div_ceil(16) * 16).min(256);
+                    cfg_changed = true;
+                }
+                ui.end_row();
+
+                ui.label("通道偏移:");
+                let resp = ui.add(egui::DragValue::new(&mut cfg.channel_base_offset).range(0..=240))
+                    .on_hover_text("驱动端口 1 的通道 1 对应的引擎通道 (从 0 开始)，每个监听端口内各自偏移。\n同时运行多个实例分担通道时，用它把各实例的通道区间错开，例如第二个实例从 128 开始。\n通道重映射指定的目标通道不受影响。");
+                cfg_changed |= resp.changed();
+                // 输入结束后向下取整到 16 的倍数，与驱动端口对齐
+                if (resp.drag_stopped() || resp.lost_focus()) && !cfg.channel_base_offset.is_multiple_of(16) {
+                    cfg.channel_base_offset -= cfg.channel_base_offset % 16;
+                    cfg_changed = true;
+                }
+                ui.end_row();
+                if let Some(e) = cfg.channel_offset_error() {
+                    validation_row(ui, &e, egui::Color32::RED);
+                }
+
+                ui.label("有效端口数:");
+                ui.label(format!("{} (每个端口 16 个通道)", cfg.usable_driver_ports()))
+                    .on_hover_text("引擎按 通道偏移 + 端口ID * 16 + 通道 路由驱动发来的消息，超出总通道数的端口会被丢弃");
+                ui.end_row();
+                if let Some(e) = cfg.total_channels_error() {
+                    validation_row(ui, &e, egui::Color32::RED);
+                }
+
+                ui.label("驱动端口数量:");
+                cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.driver_port_count).range(1..=MAX_DRIVER_PORTS))
+                    .on_hover_text("宿主程序中显示的 XXSynth Port 数量，每个端口对应 16 个通道。\n宿主通常只在启动时枚举设备，修改后需重启宿主程序")
+                    .changed();
+                ui.end_row();
+                if let Some(w) = cfg.driver_port_warning() {
+                    validation_row(ui, &w, egui::Color32::from_rgb(230, 160, 0));
+                }
+
+                ui.label("采样率:");
+                cfg_changed |= egui::ComboBox::from_id_salt("rt_sample_rate")
+                    .selected_text(format!("{} Hz", cfg.sample_rate))
+                    .show_ui(ui, |ui| {
+                        let mut c = false;
+                        for rate in SAMPLE_RATES {
+                            c |= ui.selectable_value(&mut cfg.sample_rate, rate, format!("{} Hz", rate)).changed();
+                        }
+                        c
+                    }).inner.unwrap_or(false);
+                ui.end_row();
+
+                ui.label("输出声道:");
+                cfg_changed |= egui::ComboBox::from_id_salt("rt_audio_channels")
+                    .selected_text(cfg.audio_channels.label())
+                    .show_ui(ui, |ui| {
+                        let mut c = false;
+                        for channels in AudioChannels::ALL {
+                            c |= ui.selectable_value(&mut cfg.audio_channels, channels, channels.label()).changed();
+                        }
+                        c
+                    }).inner.unwrap_or(false);
                 ui.end_row();
 
                 ui.label("缓冲区大小 (ms):");
-                cfg_changed |= ui.add(egui::Slider::new(&mut cfg.render_window_ms, 1.0..=100.0).text("ms")).changed();
+                ui.horizontal(|ui| {
+                    cfg_changed |= ui.add(egui::Slider::new(&mut cfg.render_window_ms, MIN_RENDER_WINDOW_MS..=MAX_RENDER_WINDOW_MS).logarithmic(true).text("ms")).changed();
+                    ui.label(format!("总延迟约 {:.1} ms", cfg.estimated_latency_ms()))
+                        .on_hover_text("缓冲区 + 声卡缓冲 (按约 10ms 估算)，开启事件时间戳时再加一个缓冲区的排布延迟。\n缓冲区越小延迟越低，但越容易爆音。");
+                });
                 ui.end_row();
+                if let Some(w) = cfg.render_window_warning() {
+                    validation_row(ui, &w, egui::Color32::from_rgb(230, 160, 0));
+                }
 
-                ui.label("多线程数量:");
+                ui.label("停止时淡出 (ms):");
+                cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.stop_fade_ms).range(0..=MAX_STOP_FADE_MS))
+                    .on_hover_text("停止引擎前先释放所有音符并等待尾音淡出，避免爆音。\n0 为立即切断 (与渲染的“禁用声音淡出”相同)")
+                    .changed();
+                ui.end_row();
+
+                // xsynth-realtime 只接受一个线程设置 (用于按键)，通道始终各自独立线程，这里只做说明
+                ui.label("通道多线程:");
+                ui.label(egui::RichText::new("每个通道独立线程 (实时模式固定)").weak())
+                    .on_hover_text("实时合成器总是为每个通道开一个线程，无法调整；渲染导出可以单独设置通道多线程");
+                ui.end_row();
+
+                ui.label("按键多线程:").on_hover_text("每个通道固定使用独立线程，这里设置通道内按键并行渲染的线程数，黑乐谱受益最明显");
                 ui.horizontal(|ui| {
-                    let max_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(16);
+                    let max_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(16).max(2);
                     cfg_changed |= ui.radio_value(&mut cfg.thread_count, 1, "单线程").changed();
                     cfg_changed |= ui.radio_value(&mut cfg.thread_count, 0, "自动").changed();
                     
                     let is_custom = cfg.thread_count > 1;
                     let mut custom_clicked = is_custom;
                     if ui.radio(custom_clicked, "自定义:").clicked() {
-                        if !is_custom { cfg.thread_count = max_threads / 2; }
+                        if !is_custom { cfg.thread_count = (max_threads / 2).max(2); }
                         custom_clicked = true;
                         cfg_changed = true;
                     }
@@ -136,17 +871,47 @@ impl XXSynthApp {
                     }).inner.unwrap_or(false);
                 ui.end_row();
 
-                ui.label("忽略力度范围:");
+                ui.label("忽略力度范围:").on_hover_text("按 MIDI 端发来的原始力度判断，不受力度曲线影响");
                 ui.horizontal(|ui| {
                     cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.ignore_velocity_min).range(0..=127)).changed();
                     ui.label("至");
                     cfg_changed |= ui.add(egui::DragValue::new(&mut cfg.ignore_velocity_max).range(0..=127)).changed();
-                });
+                    if !cfg.is_ignore_range_enabled() {
+                        ui.label(egui::RichText::new("(已关闭)").weak());
+                    }
+                }).response.on_hover_text("力度落在该范围内的音符不会发声，可减轻黑乐谱中大量弱音的负担。\n两端都为 0 时关闭此功能；力度为 0 的 NoteOn 始终视为 NoteOff，不受影响。");
                 if cfg.ignore_velocity_min > cfg.ignore_velocity_max {
                     cfg.ignore_velocity_max = cfg.ignore_velocity_min;
                 }
                 ui.end_row();
+
+                ui.label("复音数超限时:");
+                cfg_changed |= ui.checkbox(&mut cfg.fade_out_killing, "淡出被挤掉的声音")
+                    .on_hover_text("复音层数达到上限时，xsynth 会挤掉最早的声音。\n关闭 (默认)：直接切断，CPU 占用最低，但可能有轻微爆音。\n开启：被挤掉的声音快速淡出，更干净，但淡出期间仍需渲染，复音数长期顶满的黑乐谱下 CPU 占用会明显升高。\nxsynth 只在创建通道时读取此项，修改后需重启引擎。")
+                    .changed();
+                ui.end_row();
+
+                ui.label("线程优先级:");
+                cfg_changed |= ui.checkbox(&mut cfg.high_priority, "提高音频线程优先级")
+                    .on_hover_text("提高接收与处理 MIDI 的线程的调度优先级，系统繁忙时不容易被抢占而断声。\n核心数较少的机器上可能让界面变卡，默认关闭。\n启动引擎时生效，结果会写入日志。")
+                    .changed();
+                ui.end_row();
+
+                ui.label("PCM 输出:");
+                let mut sink = cfg.pcm_sink.clone().unwrap_or_default();
+                if ui.add(egui::TextEdit::singleline(&mut sink).hint_text("留空关闭，例如 tcp://127.0.0.1:5555"))
+                    .on_hover_text("在声卡之外，把实时输出以原始 PCM 发往 tcp://主机:端口、udp://主机:端口 或命名管道 (例如 \\\\.\\pipe\\xxsynth)。\n格式为 32 位小端浮点 (f32le)、双声道交错、采样率与引擎一致，例如：\nffmpeg -f f32le -ar 48000 -ac 2 -i \"tcp://127.0.0.1:5555?listen\" out.flac")
+                    .changed()
+                {
+                    cfg.pcm_sink = Some(sink.trim().to_string()).filter(|s| !s.is_empty());
+                    cfg_changed = true;
+                }
+                ui.end_row();
             });
+
+            ui.add_space(10.0);
+            cfg_changed |= ui_channel_routes(ui, cfg);
+            cfg_changed |= ui_midi_thru(ui, cfg, &mut self.thru_ports, &mut self.thru_edit_port);
         }
 
         if cfg_changed {
@@ -154,34 +919,323 @@ impl XXSynthApp {
         }
 
         ui.add_space(20.0);
-        
+
+        // 上次启动失败：给出可能的原因，提供重试与换端口，而不是停在一条错误信息上
+        if !is_running && let Some(failure) = &self.start_failure {
+            let mut retry = false;
+            let mut switch_to = None;
+            ui.colored_label(egui::Color32::RED, format!("❌ 引擎启动失败: {}", failure.message));
+            if !failure.ports_in_use.is_empty() {
+                let ports: Vec<String> = failure.ports_in_use.iter().map(|p| p.to_string()).collect();
+                ui.label(format!("端口 {} 已被其他程序占用，可能是另一个 XXSynth 实例或其他 MIDI 软件。", ports.join(", ")));
+            }
+            ui.horizontal(|ui| {
+                retry = ui.button("🔁 重试").clicked();
+                if let Some(port) = failure.suggested_port {
+                    switch_to = ui.button(format!("改用端口 {}", port))
+                        .on_hover_text("修改监听端口后重启引擎，驱动会在宿主下次打开端口时使用新端口")
+                        .clicked()
+                        .then_some(port);
+                }
+            });
+            if let Some(port) = switch_to {
+                self.realtime_config.udp_port = port;
+                retry = true;
+            }
+            if retry {
+                self.restart_engine();
+            }
+            ui.add_space(10.0);
+        }
+
         ui.horizontal(|ui| {
-            // 带有小红点/变色提示的重启按钮
-            let btn_text = "🔄 应用更改并重启";
+            // 带有小红点/变色提示的重启按钮，重启期间禁用，避免重复重启
+            let restarting = self.is_restarting();
+            let btn_text = if restarting { "⏳ 正在重启..." } else { "🔄 应用更改并重启" };
             let mut btn = egui::Button::new(egui::RichText::new(btn_text).heading());
             if self.is_dirty {
-                btn = btn.fill(egui::Color32::from_rgb(255, 127, 127));
+                btn = btn.fill(self.accent_or(egui::Color32::from_rgb(255, 127, 127)));
             }
 
-            if ui.add_sized([200.0, 40.0], btn).clicked() {
+            // 参数有误时禁用，避免旧引擎被停掉后新引擎又启动失败
+            let is_valid = self.realtime_config.validate().is_ok();
+            if ui.add_enabled_ui(is_valid && !restarting, |ui| ui.add_sized([200.0, 40.0], btn)).inner
+                .on_disabled_hover_text("请先修正上方标红的参数")
+                .clicked()
+            {
                 self.restart_engine();
             }
 
             if is_running {
                 ui.add_space(10.0);
                 if ui.add_sized([100.0, 40.0], egui::Button::new("⏹ 停止引擎")).clicked() {
-                    if let Some(mut handle) = self.audio_handle.take() {
-                        handle.stop();
+                    self.stop_engine();
+                    self.set_status(log::Level::Info, "音频引擎已手动停止。");
+                }
+
+                ui.add_space(10.0);
+                if ui.add_sized([140.0, 40.0], egui::Button::new("🔇 全部静音 (Panic)"))
+                    .on_hover_text("切断所有通道的发声并复位控制器，用于清除挂音")
+                    .clicked()
+                {
+                    match send_panic(&self.realtime_config) {
+                        Ok(()) => self.set_status(log::Level::Info, "已发送全部静音指令。"),
+                        Err(e) => self.set_status(log::Level::Error, format!("发送静音指令失败: {}", e)),
                     }
-                    self.status_message = "音频引擎已手动停止。".to_string();
+                }
+            }
+        });
+        if ui.checkbox(&mut self.keep_engine_on_failure, "新设置启动失败时恢复原设置继续运行")
+            .on_hover_text("例如端口被占用时，引擎会按修改前的设置重新启动，避免演出中断声")
+            .changed()
+        {
+            self.save_settings();
+        }
+
+        ui.add_space(10.0);
+        ui.horizontal(|ui| {
+            if ui.button("♻ 恢复默认设置").on_hover_text("将实时参数恢复为默认值并重启引擎，可撤销一次").clicked() {
+                self.confirm_reset = true;
+                self.reset_clear_soundfonts = false;
+            }
+            if crate::settings::has_undo() && ui.button("↩ 撤销恢复默认").clicked() {
+                self.undo_reset();
+            }
+            if ui.button("📋 复制诊断信息").on_hover_text("复制当前配置、音色库加载结果与系统信息，便于反馈问题").clicked() {
+                ui.ctx().copy_text(self.diagnostics_report());
+                self.set_status(log::Level::Info, "诊断信息已复制到剪贴板。");
+            }
+        });
+
+        // 回环自检：不需要 DAW，从本机发送测试和弦确认 端口 -> 引擎 -> 发声 整条链路
+        ui.horizontal(|ui| {
+            let testing = self.self_test_running.load(std::sync::atomic::Ordering::SeqCst);
+            if ui.add_enabled(is_running && !testing, egui::Button::new("🩺 一键自检"))
+                .on_hover_text("向引擎的监听端口发送测试和弦，检查引擎是否收到封包并发声")
+                .on_disabled_hover_text("需要先启动引擎")
+                .clicked()
+            {
+                self.start_self_test();
+            }
+            if testing {
+                ui.spinner();
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+            } else if let Some(result) = self.self_test_result.lock().unwrap().as_ref() {
+                match result {
+                    Ok(msg) => ui.colored_label(egui::Color32::from_rgb(0, 160, 0), format!("✅ {}", msg)),
+                    Err(msg) => ui.colored_label(egui::Color32::RED, format!("❌ {}", msg)),
+                };
+            }
+        });
+
+        // 压力测试：逐步提高音符速率直到欠载，测出当前配置能承受多少音符
+        ui.horizontal(|ui| {
+            let benchmarking = self.benchmark.running.load(std::sync::atomic::Ordering::SeqCst);
+            if benchmarking {
+                if ui.button("⏹ 停止测试").clicked() {
+                    self.benchmark.running.store(false, std::sync::atomic::Ordering::SeqCst);
+                }
+                ui.spinner();
+                ui.label(format!("正在测试 {} 音符/秒...", self.benchmark.current_rate.load(std::sync::atomic::Ordering::Relaxed)));
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+            } else if ui.add_enabled(is_running, egui::Button::new("📈 压力测试"))
+                .on_hover_text("向引擎发送速率逐步提高的音符，直到出现欠载，测出当前线程数与缓冲设置下可持续的最高音符速率\n测试会通过输出设备发出声音，约需数十秒")
+                .on_disabled_hover_text("需要先启动引擎")
+                .clicked()
+            {
+                self.start_benchmark();
+            }
+        });
+        if !self.benchmark.running.load(std::sync::atomic::Ordering::SeqCst) {
+            let result = self.benchmark.result.lock().unwrap();
+            match result.as_ref() {
+                Some(Ok(report)) => {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(report.lines().nth(1).unwrap_or_default()).strong());
+                        if ui.small_button("📋 复制报告").clicked() {
+                            ui.ctx().copy_text(report.clone());
+                        }
+                    });
+                    egui::CollapsingHeader::new("压力测试报告").id_salt("benchmark_report").show(ui, |ui| {
+                        ui.label(egui::RichText::new(report).monospace());
+                    });
+                }
+                Some(Err(msg)) => {
+                    ui.colored_label(egui::Color32::RED, format!("❌ {}", msg));
+                }
+                None => {}
+            }
+        }
+
+        // 托盘：隐藏窗口后引擎继续运行，双击托盘图标或右键菜单恢复
+        if self.tray.is_some() {
+            ui.horizontal(|ui| {
+                if ui.button("📥 最小化到托盘").clicked() {
+                    ui.ctx().send_viewport_cmd(egui::ViewportCommand::Visible(false));
+                }
+                if ui.checkbox(&mut self.start_minimized, "启动时最小化到托盘").changed() {
+                    self.save_settings();
+                }
+            });
+        }
+
+        // 虚拟端口清理：卸载程序前删除注册表中的驱动项
+        if cfg!(windows) {
+            ui.horizontal(|ui| {
+                let registered = matches!(self.port_registration, crate::PortRegistration::Registered(_));
+                if ui.add_enabled(registered, egui::Button::new("🗑 卸载虚拟端口"))
+                    .on_hover_text("从注册表删除 XXSynth 的 MIDI 驱动项。删除程序前请先执行，否则其他程序枚举 MIDI 设备时可能出错。")
+                    .clicked()
+                {
+                    self.unregister_port();
+                }
+                if ui.checkbox(&mut self.unregister_port_on_exit, "退出时卸载虚拟端口").changed() {
+                    self.save_settings();
+                }
+            });
+            self.ui_panic_hotkey(ui);
+        }
+        self.ui_appearance(ui);
+        self.ui_reset_dialog(ui.ctx());
+
+        if self.is_running() {
+            ui.add_space(20.0);
+            ui.separator();
+            self.ui_player(ui);
+            ui.add_space(10.0);
+            self.ui_recorder(ui);
+        }
+    }
+
+    // 内置 MIDI 播放器：直接把 MIDI 文件送进运行中的引擎，无需外部宿主
+    fn ui_player(&mut self, ui: &mut egui::Ui) {
+        ui.heading("播放 MIDI");
+
+        ui.horizontal(|ui| {
+            if ui.button("📂 打开 MIDI...").clicked()
+                && let Some(path) = rfd::FileDialog::new().add_filter("MIDI", &["mid", "midi"]).pick_file()
+                && let Some(handle) = &self.audio_handle
+            {
+                // 先关闭旧的播放器，避免两个文件同时发声
+                self.midi_player = None;
+                self.render_preview = false;
+                match MidiPlayer::load(&path, handle.packet_sender()) {
+                    Ok(player) => {
+                        player.set_speed(self.player_speed);
+                        player.play();
+                        self.set_status(log::Level::Info, format!("正在播放 {}", path.display()));
+                        self.midi_player = Some(player);
+                    }
+                    Err(e) => self.set_status(log::Level::Error, e),
+                }
+            }
+
+            if let Some(player) = &self.midi_player {
+                ui.label(egui::RichText::new(player.path.file_name().unwrap_or_default().to_string_lossy()).strong());
+            }
+        });
+
+        let mut close_player = false;
+        if let Some(player) = &self.midi_player {
+            let is_playing = player.is_playing.load(std::sync::atomic::Ordering::Relaxed);
+
+            ui.horizontal(|ui| {
+                if is_playing {
+                    if ui.button("⏸ 暂停").clicked() { player.pause(); }
+                } else if ui.button("▶ 播放").clicked() {
+                    player.play();
+                }
+                if ui.button("⏹ 停止").clicked() { close_player = true; }
+
+                // 进度条：拖动结束后才跳转，避免拖动过程中反复补发控制器
+                let mut pos = player.position();
+                let resp = ui.add(egui::Slider::new(&mut pos, 0.0..=player.duration.max(0.001)).show_value(false));
+                if resp.drag_stopped() || (resp.changed() && !resp.dragged()) {
+                    player.seek(pos);
+                }
+                ui.label(format!("{} / {}", format_time(player.position()), format_time(player.duration)));
+            });
+
+            // 倍速：只改变事件的时间间隔，不改变音高；显示的时间仍是乐曲内的时间
+            ui.horizontal(|ui| {
+                ui.label("倍速:");
+                if ui.add(egui::Slider::new(&mut self.player_speed, MIN_PLAYBACK_SPEED..=MAX_PLAYBACK_SPEED)
+                    .logarithmic(true)
+                    .fixed_decimals(2)
+                    .suffix("x"))
+                    .on_hover_text("放慢可以让密集的段落更容易听清，也能减轻引擎负担")
+                    .changed()
+                {
+                    player.set_speed(self.player_speed);
+                }
+                if self.player_speed != 1.0 && ui.button("重置").clicked() {
+                    self.player_speed = 1.0;
+                    player.set_speed(1.0);
+                }
+            });
+        }
+        if close_player {
+            self.midi_player = None;
+        }
+    }
+
+    // 实时录音：把听到的声音直接保存为 WAV
+    fn ui_recorder(&mut self, ui: &mut egui::Ui) {
+        ui.heading("录音");
+
+        ui.horizontal(|ui| {
+            if let Some(recorder) = &self.recorder {
+                if ui.button("⏹ 停止录制").clicked() {
+                    self.stop_recording();
+                    return;
+                }
+                let secs = recorder.started_at.elapsed().as_secs_f64();
+                let mb = recorder.bytes_written.load(std::sync::atomic::Ordering::Relaxed) as f64 / 1024.0 / 1024.0;
+                ui.label(egui::RichText::new(format!("⏺ {}  {:.1} MB", format_time(secs), mb)).color(egui::Color32::RED));
+            } else if ui.button("⏺ 开始录制").on_hover_text("录音期间会额外占用一份合成 CPU").clicked()
+                && let Some(path) = rfd::FileDialog::new().add_filter("WAV", &["wav"]).save_file()
+                && let Some(handle) = &self.audio_handle
+            {
+                match handle.start_recording(&path) {
+                    Ok(recorder) => {
+                        self.set_status(log::Level::Info, format!("正在录音到 {}", path.display()));
+                        self.recorder = Some(recorder);
+                    }
+                    Err(e) => self.set_status(log::Level::Error, e),
+                }
+            }
+        });
+
+        // MIDI 录制：记录收到的事件而不是声音，可以之后换音色库重新渲染
+        ui.horizontal(|ui| {
+            if let Some(capture) = &self.midi_capture {
+                if ui.button("⏹ 停止录制 MIDI").clicked() {
+                    self.stop_midi_capture();
+                    return;
+                }
+                let secs = capture.started_at.elapsed().as_secs_f64();
+                let events = capture.event_count.load(std::sync::atomic::Ordering::Relaxed);
+                ui.label(egui::RichText::new(format!("⏺ {}  {} 个事件", format_time(secs), events)).color(egui::Color32::RED));
+            } else if ui.button("🎹 录制 MIDI")
+                .on_hover_text("把收到的 MIDI 事件按到达时刻保存为 MIDI 文件 (Type-1，每个驱动端口一个音轨)，停止时写入")
+                .clicked()
+                && let Some(path) = rfd::FileDialog::new().add_filter("MIDI", &["mid"]).save_file()
+                && let Some(handle) = &self.audio_handle
+            {
+                match handle.start_midi_capture(&path) {
+                    Ok(capture) => {
+                        self.set_status(log::Level::Info, format!("正在录制 MIDI 到 {}", path.display()));
+                        self.midi_capture = Some(capture);
+                    }
+                    Err(e) => self.set_status(log::Level::Error, e),
                 }
             }
         });
     }
 
     pub(crate) fn ui_render(&mut self, ui: &mut egui::Ui) {
-        ui.heading("离线渲染 (MIDI -> WAV)");
-        ui.label("设置渲染参数并调用底层的 xsynth-render 来完成急速渲染。");
+        ui.heading("离线渲染 (MIDI -> 音频文件)");
+        ui.label("设置渲染参数并调用 xsynth-render 来完成急速渲染。");
         ui.separator();
 
         let cfg = &mut self.render_config;
@@ -189,7 +1243,11 @@ impl XXSynthApp {
         ui.horizontal(|ui| {
             ui.label("输入 MIDI:");
             if ui.button("📂 选择文件").clicked() {
-                if let Some(path) = rfd::FileDialog::new().add_filter("MIDI", &["mid", "midi"]).pick_file() {
+                let mut dialog = rfd::FileDialog::new().add_filter("MIDI", &["mid", "midi"]);
+                if let Some(dir) = existing_parent(&cfg.midi_path) {
+                    dialog = dialog.set_directory(dir);
+                }
+                if let Some(path) = dialog.pick_file() {
                     cfg.midi_path = path.to_string_lossy().to_string();
                 }
             }
@@ -197,14 +1255,49 @@ impl XXSynthApp {
         });
 
         ui.horizontal(|ui| {
-            ui.label("输出 WAV:");
+            ui.label("输出格式:");
+            let prev_format = cfg.output_format;
+            egui::ComboBox::from_id_salt("output_format").selected_text(cfg.output_format.label()).show_ui(ui, |ui| {
+                for format in OutputFormat::ALL {
+                    ui.selectable_value(&mut cfg.output_format, format, format.label());
+                }
+            });
+            // 切换格式时顺带修正输出文件的扩展名
+            if cfg.output_format != prev_format {
+                cfg.output_path = std::path::Path::new(&cfg.output_path)
+                    .with_extension(cfg.output_format.extension())
+                    .to_string_lossy()
+                    .to_string();
+            }
+            if cfg.output_format != OutputFormat::Wav {
+                ui.label(egui::RichText::new("需要 ffmpeg").small().weak())
+                    .on_hover_text("先渲染为 WAV，再调用 ffmpeg 转码，ffmpeg 需在系统 PATH 中");
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("输出文件:");
             if ui.button("💾 保存位置").clicked() {
-                if let Some(path) = rfd::FileDialog::new().add_filter("WAV", &["wav"]).set_file_name("out.wav").save_file() {
+                let ext = cfg.output_format.extension();
+                let mut dialog = rfd::FileDialog::new()
+                    .add_filter(ext.to_uppercase(), &[ext])
+                    .set_file_name(format!("out.{}", ext));
+                // 默认打开上次的输出目录
+                if let Some(dir) = existing_parent(&cfg.output_path) {
+                    dialog = dialog.set_directory(dir);
+                }
+                if let Some(path) = dialog.save_file() {
                     cfg.output_path = path.to_string_lossy().to_string();
                 }
             }
             ui.label(&cfg.output_path);
         });
+        if !cfg.output_extension_matches() {
+            ui.colored_label(
+                egui::Color32::from_rgb(220, 50, 50),
+                format!("⚠ 输出文件扩展名与所选格式不一致，应为 .{}", cfg.output_format.extension()),
+            );
+        }
 
         ui.add_space(15.0);
 
@@ -232,11 +1325,11 @@ impl XXSynthApp {
             ui.end_row();
 
             ui.label("通道多线程:");
-            ui.text_edit_singleline(&mut cfg.channel_threading).on_hover_text("填 none, auto, 或正整数");
+            thread_count_field(ui, &mut cfg.channel_threading);
             ui.end_row();
 
             ui.label("按键多线程:");
-            ui.text_edit_singleline(&mut cfg.key_threading).on_hover_text("填 none, auto, 或正整数");
+            thread_count_field(ui, &mut cfg.key_threading);
             ui.end_row();
             
             ui.label("其他处理:");
@@ -246,118 +1339,536 @@ impl XXSynthApp {
                 ui.checkbox(&mut cfg.linear_envelope, "使用线性包络");
             });
             ui.end_row();
+
+            ui.label("循环渲染:");
+            ui.horizontal(|ui| {
+                let mut looping = cfg.loop_mode != LoopMode::Off;
+                if ui.checkbox(&mut looping, "无缝循环")
+                    .on_hover_text("以 MIDI 最后一个事件为循环点处理渲染结果，适合循环播放的氛围音乐")
+                    .changed()
+                {
+                    cfg.loop_mode = if looping { LoopMode::WrapTail } else { LoopMode::Off };
+                }
+                if looping {
+                    ui.radio_value(&mut cfg.loop_mode, LoopMode::WrapTail, "尾音叠加到开头")
+                        .on_hover_text("循环点之后的释音叠加回开头，循环衔接处不会突然断掉");
+                    ui.radio_value(&mut cfg.loop_mode, LoopMode::Trim, "在循环点截断")
+                        .on_hover_text("直接丢弃循环点之后的尾音");
+                }
+            });
+            ui.end_row();
+
+            ui.label("渲染器:");
+            ui.checkbox(&mut cfg.use_external_renderer, "使用外部 xsynth-render 程序")
+                .on_hover_text("默认使用内置渲染器 (基于 xsynth-core)；需要使用其他版本的 xsynth-render 时可改用外部程序");
+            ui.end_row();
+
+            ui.label("xsynth-render 路径:");
+            ui.add_enabled_ui(cfg.use_external_renderer, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("📂 选择程序").clicked() {
+                        let mut dialog = rfd::FileDialog::new();
+                        if cfg!(windows) {
+                            dialog = dialog.add_filter("xsynth-render", &["exe"]);
+                        }
+                        if let Some(dir) = cfg.xsynth_render_path.as_ref().and_then(|p| p.parent()) {
+                            dialog = dialog.set_directory(dir);
+                        }
+                        if let Some(path) = dialog.pick_file() {
+                            cfg.xsynth_render_path = Some(path);
+                        }
+                    }
+                    match &cfg.xsynth_render_path {
+                        Some(path) => {
+                            ui.label(path.to_string_lossy());
+                            if ui.small_button("✖").on_hover_text("改回从 PATH / 同级目录查找").clicked() {
+                                cfg.xsynth_render_path = None;
+                            }
+                        }
+                        None => {
+                            ui.label(egui::RichText::new("自动查找 (PATH / 同级目录)").weak());
+                        }
+                    }
+                });
+            });
+            ui.end_row();
         });
+        let renderer_error = cfg.launch_error();
+        if let Some(e) = &renderer_error {
+            ui.colored_label(egui::Color32::from_rgb(220, 50, 50), format!("⚠ {}", e));
+        }
 
         ui.add_space(20.0);
 
-        if ui.add_sized([200.0, 40.0], egui::Button::new(egui::RichText::new("🚀 开始渲染").heading())).clicked() {
-            if self.soundfonts.is_empty() {
-                self.status_message = "错误：渲染需要至少加载一个音色库！".to_string();
-                return;
+        ui.horizontal(|ui| {
+            let start = ui.add_enabled_ui(renderer_error.is_none(), |ui| {
+                ui.add_sized([200.0, 40.0], egui::Button::new(egui::RichText::new("🚀 开始渲染").heading()))
+            }).inner;
+            if start.clicked() {
+                if !self.soundfonts.iter().any(|sf| sf.enabled) {
+                    self.set_status(log::Level::Error, "错误：渲染需要至少加载一个音色库！");
+                    return;
+                }
+                if self.render_config.midi_path.is_empty() {
+                    self.set_status(log::Level::Error, "错误：请先选择输入的 MIDI 文件！");
+                    return;
+                }
+
+                self.begin_render("正在渲染...");
+
+                // 克隆参数丢进渲染子线程
+                let sfs = self.soundfonts.iter().filter(|sf| sf.enabled).map(|sf| sf.path.clone()).collect();
+                spawn_render(self.render_config.clone(), sfs, self.render_shared());
+            }
+
+            if ui.add_sized([140.0, 40.0], egui::Button::new("➕ 添加到队列")).clicked() {
+                if self.render_config.midi_path.is_empty() || self.render_config.output_path.is_empty() {
+                    self.set_status(log::Level::Error, "错误：请先选择输入的 MIDI 文件和输出路径！");
+                } else {
+                    self.render_queue.lock().unwrap().push(QueueItem {
+                        midi_path: self.render_config.midi_path.clone(),
+                        output_path: self.render_config.output_path.clone(),
+                        status: QueueStatus::Pending,
+                    });
+                }
+            }
+
+            let preview_label = if self.render_preview { "⏹ 停止试听" } else { "🎧 试听" };
+            if ui.add_sized([100.0, 40.0], egui::Button::new(preview_label))
+                .on_hover_text("通过实时引擎播放 MIDI 的前 10 秒，先听听当前音色库的效果。\n使用实时设置中的参数，与渲染参数 (层数、插值等) 可能不同。")
+                .clicked()
+            {
+                self.toggle_render_preview();
             }
-            if self.render_config.midi_path.is_empty() {
-                self.status_message = "错误：请先选择输入的 MIDI 文件！".to_string();
+        });
+
+        self.ui_render_queue(ui);
+    }
+
+    // 标记渲染开始并重置进度，同时保存渲染参数，下次启动时可直接再次渲染
+    fn begin_render(&mut self, status: &str) {
+        self.save_settings();
+        self.is_rendering.store(true, std::sync::atomic::Ordering::SeqCst);
+        self.render_started = Some(std::time::Instant::now());
+        self.render_result = None;
+        *self.render_progress.lock().unwrap() = 0.0;
+        *self.render_secs.lock().unwrap() = 0.0;
+        self.set_status(log::Level::Info, status);
+    }
+
+    // 批量渲染队列：共用当前的音色库与渲染参数
+    fn ui_render_queue(&mut self, ui: &mut egui::Ui) {
+        let queue = self.render_queue.clone();
+        let mut queue = queue.lock().unwrap();
+        if queue.is_empty() {
+            return;
+        }
+
+        ui.add_space(20.0);
+        ui.separator();
+        let pending = queue.iter().filter(|item| item.status == QueueStatus::Pending).count();
+        let finished = queue.iter().filter(|item| matches!(item.status, QueueStatus::Done | QueueStatus::Failed(_))).count();
+        ui.heading(format!("渲染队列 (已完成 {} / {})", finished, queue.len()));
+
+        let mut to_remove = None;
+        egui::ScrollArea::vertical().id_salt("render_queue").max_height(200.0).show(ui, |ui| {
+            for (i, item) in queue.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button("❌").clicked() { to_remove = Some(i); }
+                    match &item.status {
+                        QueueStatus::Pending => { ui.label("⏳ 等待中"); }
+                        QueueStatus::Rendering => { ui.label("🎬 渲染中"); }
+                        QueueStatus::Done => { ui.colored_label(egui::Color32::from_rgb(0, 160, 0), "✅ 完成"); }
+                        QueueStatus::Failed(e) => { ui.colored_label(egui::Color32::RED, "❌ 失败").on_hover_text(e); }
+                    }
+                    let name = std::path::Path::new(&item.midi_path).file_name().unwrap_or_default().to_string_lossy().to_string();
+                    ui.label(egui::RichText::new(name).strong()).on_hover_text(format!("{}\n→ {}", item.midi_path, item.output_path));
+                });
+            }
+        });
+        if let Some(i) = to_remove {
+            queue.remove(i);
+        }
+
+        ui.horizontal(|ui| {
+            let renderer_ok = self.render_config.launch_error().is_none();
+            if ui.add_enabled(pending > 0 && renderer_ok, egui::Button::new(format!("🚀 渲染队列 ({} 个)", pending))).clicked() {
+                if !self.soundfonts.iter().any(|sf| sf.enabled) {
+                    self.set_status(log::Level::Error, "错误：渲染需要至少加载一个音色库！");
+                    return;
+                }
+                drop(queue);
+                self.begin_render("正在渲染队列...");
+                let sfs = self.soundfonts.iter().filter(|sf| sf.enabled).map(|sf| sf.path.clone()).collect();
+                spawn_queue_render(self.render_config.clone(), sfs, self.render_queue.clone(), self.render_shared());
                 return;
             }
+            if ui.button("🧹 清除已完成").clicked() {
+                queue.retain(|item| !matches!(item.status, QueueStatus::Done | QueueStatus::Failed(_)));
+            }
+        });
+    }
+
+    // 键盘可视化：按住的键按通道着色
+    pub(crate) fn ui_keyboard(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("键盘");
+            ui.checkbox(&mut self.keyboard_popout, "弹出可视化")
+                .on_hover_text("在单独的无边框窗口中显示键盘，可以拖到直播采集用的显示器上。\n在窗口上拖动可移动，双击关闭。");
+        });
+        ui.separator();
+
+        let Some(handle) = &self.audio_handle else {
+            ui.label("引擎未运行。");
+            return;
+        };
+        if self.keyboard_popout {
+            ui.label("键盘已在单独的窗口中显示。");
+            return;
+        }
+
+        let width = ui.available_width();
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(width, keyboard_height(width)), egui::Sense::hover());
+        draw_keyboard(ui, rect, &handle.key_activity);
+
+        ui.add_space(10.0);
+        ui.label(egui::RichText::new("颜色按通道区分 (每 16 个通道循环一次)。").small().weak());
+
+        // 保持高刷新率，让按键变化跟得上
+        ui.ctx().request_repaint_after(std::time::Duration::from_millis(16));
+    }
+
+    // 弹出的键盘窗口：无边框，只读取共享的按键状态，与主窗口在同一线程绘制
+    pub(crate) fn ui_keyboard_viewport(&mut self, ctx: &egui::Context) {
+        if !self.keyboard_popout {
+            return;
+        }
+        let activity = self.audio_handle.as_ref().map(|h| h.key_activity.clone());
+        let mut close = false;
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("keyboard_popout"),
+            egui::ViewportBuilder::default()
+                .with_title("XXSynth 键盘")
+                .with_inner_size([1200.0, 160.0])
+                .with_decorations(false),
+            |ctx, _| {
+                egui::CentralPanel::default().frame(egui::Frame::NONE).show(ctx, |ui| {
+                    let (rect, resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+                    match &activity {
+                        Some(activity) => draw_keyboard(ui, rect, activity),
+                        None => {
+                            ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, "引擎未运行", egui::FontId::proportional(16.0), ui.visuals().weak_text_color());
+                        }
+                    }
+                    // 没有标题栏，拖动窗口内任意位置来移动，双击关闭
+                    if resp.drag_started() {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::StartDrag);
+                    }
+                    close |= resp.double_clicked();
+                });
+                close |= ctx.input(|i| i.viewport().close_requested());
+                ctx.request_repaint_after(std::time::Duration::from_millis(16));
+            },
+        );
+        if close {
+            self.keyboard_popout = false;
+        }
+    }
+}
+
+// 128 个键共 75 个白键，键盘高度按白键宽度计算
+fn keyboard_height(width: f32) -> f32 {
+    (width / 75.0 * 6.0).clamp(60.0, 200.0)
+}
+
+// 在给定区域内画出 128 键的键盘，黑键的宽度与高度都为白键的 0.6 倍
+fn draw_keyboard(ui: &egui::Ui, rect: egui::Rect, activity: &KeyActivity) {
+    let painter = ui.painter_at(rect);
+    let white_width = rect.width() / 75.0;
+    let height = rect.height();
+
+    let is_black = |key: usize| matches!(key % 12, 1 | 3 | 6 | 8 | 10);
+    let channel_color = |ch: u32| egui::Color32::from(egui::ecolor::Hsva::new((ch % 16) as f32 / 16.0, 0.8, 0.95, 1.0));
+
+    let mut white_index = 0;
+    let mut black_keys = Vec::new();
+    for key in 0..128 {
+        if is_black(key) {
+            // 黑键跨在前一个白键的右边缘上
+            let x = rect.left() + white_index as f32 * white_width - white_width * 0.3;
+            black_keys.push((key, egui::Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2(white_width * 0.6, height * 0.6))));
+            continue;
+        }
+        let key_rect = egui::Rect::from_min_size(
+            egui::pos2(rect.left() + white_index as f32 * white_width, rect.top()),
+            egui::vec2(white_width, height),
+        );
+        let fill = activity.held_channel(key).map(channel_color).unwrap_or(egui::Color32::WHITE);
+        painter.rect_filled(key_rect, 0.0, fill);
+        painter.rect_stroke(key_rect, 0.0, egui::Stroke::new(1.0, egui::Color32::GRAY), egui::StrokeKind::Inside);
+        white_index += 1;
+    }
+    // 黑键最后画，盖在白键上面
+    for (key, key_rect) in black_keys {
+        let fill = activity.held_channel(key).map(channel_color).unwrap_or(egui::Color32::BLACK);
+        painter.rect_filled(key_rect, 0.0, fill);
+    }
+}
+
+// 日志级别对应的颜色
+fn log_level_color(level: log::Level) -> egui::Color32 {
+    match level {
+        log::Level::Error => egui::Color32::RED,
+        log::Level::Warn => egui::Color32::from_rgb(230, 160, 0),
+        _ => egui::Color32::GRAY,
+    }
+}
+
+// 渲染的线程设置：单线程 / 自动 / 自定义线程数，对应 xsynth-render 的 none / auto / 正整数
+fn thread_count_field(ui: &mut egui::Ui, value: &mut ThreadCount) {
+    ui.horizontal(|ui| {
+        let max_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(16).max(2);
+        ui.radio_value(value, ThreadCount::None, "单线程");
+        ui.radio_value(value, ThreadCount::Auto, "自动");
+        let is_custom = matches!(value, ThreadCount::Manual(_));
+        if ui.radio(is_custom, "自定义:").clicked() && !is_custom {
+            *value = ThreadCount::Manual((max_threads / 2).max(2));
+        }
+        if let ThreadCount::Manual(n) = value {
+            ui.add(egui::DragValue::new(n).range(1..=max_threads));
+        }
+    });
+}
+
+// 可选数值输入框：勾选后才可编辑，未勾选表示 None
+fn optional_u8_field(ui: &mut egui::Ui, label: &str, value: &mut Option<u8>, range: std::ops::RangeInclusive<u8>) -> bool {
+    let mut changed = false;
+    let mut enabled = value.is_some();
+    if ui.checkbox(&mut enabled, label).changed() {
+        *value = if enabled { Some(*range.start()) } else { None };
+        changed = true;
+    }
+    if let Some(v) = value {
+        changed |= ui.add(egui::DragValue::new(v).range(range)).changed();
+    }
+    changed
+}
+
+// 包络曲线下拉框
+fn curve_combo(ui: &mut egui::Ui, id: impl std::hash::Hash, label: &str, value: &mut CurveType) -> bool {
+    let mut changed = false;
+    ui.label(label);
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(value.label())
+        .width(60.0)
+        .show_ui(ui, |ui| {
+            for curve in CurveType::ALL {
+                changed |= ui.selectable_value(value, curve, curve.label()).changed();
+            }
+        });
+    changed
+}
+
+// 路径所在的目录，目录不存在时返回 None
+fn existing_parent(path: &str) -> Option<&std::path::Path> {
+    std::path::Path::new(path).parent().filter(|dir| dir.is_dir())
+}
+
+// 把秒数格式化为 分:秒
+fn format_time(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
 
-            self.is_rendering.store(true, std::sync::atomic::Ordering::SeqCst);
-            *self.render_progress.lock().unwrap() = 0.0;
-            self.status_message = "正在渲染...".to_string();
-
-            // 克隆参数丢进渲染子线程
-            let midi = self.render_config.midi_path.clone();
-            let out = self.render_config.output_path.clone();
-            let sfs = self.soundfonts.clone();
-            let sample_rate = self.render_config.sample_rate;
-            let audio_channels = self.render_config.audio_channels.clone();
-            let layers = self.render_config.layers;
-            let channel_threading = self.render_config.channel_threading.clone();
-            let key_threading = self.render_config.key_threading.clone();
-            let apply_limiter = self.render_config.apply_limiter;
-            let disable_fade_out = self.render_config.disable_fade_out;
-            let linear_envelope = self.render_config.linear_envelope;
-            let interpolation = self.render_config.interpolation.clone();
-
-            let is_rendering_clone = self.is_rendering.clone();
-            let progress_clone = self.render_progress.clone();
-            let error_clone = self.render_error.clone();
-
-            std::thread::spawn(move || {
-                use std::process::{Command, Stdio};
-                use std::io::Read;
-
-                let mut cmd = Command::new("xsynth-render"); // 会自动查找 PATH 或同级目录下的 xsynth-render(.exe)
-                
-                cmd.arg(&midi);
-                for sf in &sfs { cmd.arg(sf); }
-                cmd.arg("-o").arg(&out);
-                cmd.arg("-s").arg(sample_rate.to_string());
-                cmd.arg("-c").arg(&audio_channels);
-                cmd.arg("-l").arg(layers.to_string());
-                cmd.arg("--channel-threading").arg(&channel_threading);
-                cmd.arg("--key-threading").arg(&key_threading);
-                if apply_limiter { cmd.arg("-L"); }
-                if disable_fade_out { cmd.arg("--disable-fade-out"); }
-                if linear_envelope { cmd.arg("--linear-envelope"); }
-                cmd.arg("-I").arg(&interpolation);
-
-                // 在 Windows 环境下隐藏 xsynth-render 拉起时可能带来的黑框
-                #[cfg(target_os = "windows")]
+// 字节数显示为 KB / MB / GB
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    let kb = bytes as f64 / 1024.0;
+    if kb < 1024.0 {
+        format!("{:.0} KB", kb)
+    } else if kb < 1024.0 * 1024.0 {
+        format!("{:.1} MB", kb / 1024.0)
+    } else {
+        format!("{:.2} GB", kb / 1024.0 / 1024.0)
+    }
+}
+
+// MIDI 直通：选择外部 MIDI 输出端口，并按驱动端口勾选转发过去的通道，返回是否有修改
+fn ui_midi_thru(ui: &mut egui::Ui, cfg: &mut RealtimeConfig, ports: &mut Option<Vec<String>>, edit_port: &mut u8) -> bool {
+    let mut changed = false;
+    let thru_count = if cfg.midi_thru_port.is_some() { cfg.midi_thru_channels.len() } else { 0 };
+    egui::CollapsingHeader::new(format!("MIDI 直通 ({} 个通道)", thru_count))
+        .id_salt("midi_thru")
+        .show(ui, |ui| {
+            // 首次展开时读取端口列表
+            let ports = ports.get_or_insert_with(crate::midithru::output_ports);
+            ui.label(egui::RichText::new("勾选的通道原样转发到外部 MIDI 输出端口 (例如硬件音源)，不在本程序中合成。\n端口断开时这些通道的消息会被丢弃，设备恢复后自动重连。").weak());
+
+            ui.horizontal(|ui| {
+                ui.label("输出端口:");
+                let selected = cfg.midi_thru_port.clone().unwrap_or_else(|| "(关闭)".to_string());
+                egui::ComboBox::from_id_salt("midi_thru_port").selected_text(selected).show_ui(ui, |ui| {
+                    changed |= ui.selectable_value(&mut cfg.midi_thru_port, None, "(关闭)").changed();
+                    for name in ports.iter() {
+                        changed |= ui.selectable_value(&mut cfg.midi_thru_port, Some(name.clone()), name).changed();
+                    }
+                });
+                if ui.button("🔄").on_hover_text("重新读取系统的 MIDI 输出端口").clicked() {
+                    *ports = crate::midithru::output_ports();
+                }
+                // 保存的端口当前不存在 (设备未连接) 时提示，引擎仍会在设备出现后自动连接
+                if let Some(name) = &cfg.midi_thru_port
+                    && !ports.contains(name)
                 {
-                    use std::os::windows::process::CommandExt;
-                    cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-                }
-
-                cmd.stdout(Stdio::piped());
-                cmd.stderr(Stdio::piped());
-
-                if let Ok(mut child) = cmd.spawn() {
-                    // xsynth-render 通常将进度日志用 indicatif 库输出在 stderr 中
-                    if let Some(stderr) = child.stderr.take() {
-                        let mut byte_reader = stderr.bytes();
-                        let mut buffer = String::new();
-                        
-                        // 逐字节读取 stderr 并在遇到 \r 或 \n 时解析进度
-                        while let Some(Ok(b)) = byte_reader.next() {
-                            if b == b'\r' || b == b'\n' {
-                                if let Some(idx) = buffer.find("%") {
-                                    // 往前寻找数字来匹配百分比值
-                                    let mut start_idx = idx;
-                                    while start_idx > 0 && buffer.as_bytes()[start_idx - 1].is_ascii_digit() {
-                                        start_idx -= 1;
-                                    }
-                                    if let Ok(pct) = buffer[start_idx..idx].parse::<f32>() {
-                                        if let Ok(mut p) = progress_clone.lock() {
-                                            *p = pct / 100.0;
-                                        }
-                                    }
-                                }
-                                buffer.clear();
-                            } else {
-                                buffer.push(b as char);
-                            }
+                    ui.colored_label(egui::Color32::from_rgb(230, 160, 0), "⚠ 当前未找到该端口");
+                }
+            });
+            if let Some(e) = cfg.midi_thru_error() {
+                ui.colored_label(egui::Color32::RED, format!("⚠ {}", e));
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("驱动端口:");
+                let mut port = *edit_port + 1;
+                if ui.add(egui::DragValue::new(&mut port).range(1..=MAX_DRIVER_PORTS)).changed() {
+                    *edit_port = port - 1;
+                }
+                ui.label("直通通道:");
+                for channel in 0..16u8 {
+                    let position = cfg.midi_thru_channels.iter().position(|t| t.port == *edit_port && t.channel == channel);
+                    if ui.selectable_label(position.is_some(), (channel + 1).to_string()).clicked() {
+                        match position {
+                            Some(i) => { cfg.midi_thru_channels.remove(i); }
+                            None => cfg.midi_thru_channels.push(ThruChannel { port: *edit_port, channel }),
                         }
+                        changed = true;
                     }
-                    
-                    let status = child.wait();
-                    if status.is_err() || !status.unwrap().success() {
-                         if let Ok(mut err) = error_clone.lock() {
-                            *err = Some("错误：渲染进程异常退出！请检查 xsynth-render 工具。".to_string());
-                         }
-                    } else {
-                         if let Ok(mut err) = error_clone.lock() {
-                            *err = Some(format!("渲染完成！音频已保存至 {}", out));
-                         }
+                }
+                if !cfg.midi_thru_channels.is_empty() && ui.button("🧹 清空").clicked() {
+                    cfg.midi_thru_channels.clear();
+                    changed = true;
+                }
+            });
+            if !cfg.midi_thru_channels.is_empty() {
+                let mut list: Vec<_> = cfg.midi_thru_channels.iter().map(|t| (t.port, t.channel)).collect();
+                list.sort();
+                let text: Vec<String> = list.iter().map(|(p, c)| format!("{}-{}", p + 1, c + 1)).collect();
+                ui.label(egui::RichText::new(format!("已直通 (端口-通道): {}", text.join(", "))).weak());
+            }
+        });
+    changed
+}
+
+// 通道重映射表：界面上的端口、通道均从 1 开始显示，返回是否有修改
+fn ui_channel_routes(ui: &mut egui::Ui, cfg: &mut RealtimeConfig) -> bool {
+    let mut changed = false;
+    egui::CollapsingHeader::new(format!("通道重映射 ({} 条)", cfg.channel_routes.len()))
+        .id_salt("channel_routes")
+        .show(ui, |ui| {
+            ui.label(egui::RichText::new("把驱动端口上某个 MIDI 通道的消息发往指定的引擎通道，未列出的按 端口 * 16 + 通道 路由。\n适合只用一个端口的宿主驱动多通道引擎。").weak());
+
+            let channels_per_socket = (cfg.total_channels / cfg.udp_port_count.max(1) as u32).max(1);
+            let mut to_remove = None;
+            egui::Grid::new("channel_routes_grid").num_columns(4).spacing([20.0, 6.0]).show(ui, |ui| {
+                ui.label("端口");
+                ui.label("通道");
+                ui.label("→ 引擎通道");
+                ui.end_row();
+                for (i, route) in cfg.channel_routes.iter_mut().enumerate() {
+                    let mut port = route.port + 1;
+                    let mut channel = route.channel + 1;
+                    let mut target = route.target + 1;
+                    if ui.add(egui::DragValue::new(&mut port).range(1..=MAX_DRIVER_PORTS)).changed() {
+                        route.port = port - 1;
+                        changed = true;
                     }
-                } else {
-                    if let Ok(mut err) = error_clone.lock() {
-                        *err = Some("错误：找不到 xsynth-render！请确保它放置在同级目录或已添加到系统 PATH 中。".to_string());
+                    if ui.add(egui::DragValue::new(&mut channel).range(1..=16)).changed() {
+                        route.channel = channel - 1;
+                        changed = true;
+                    }
+                    if ui.add(egui::DragValue::new(&mut target).range(1..=channels_per_socket)).changed() {
+                        route.target = target - 1;
+                        changed = true;
                     }
+                    if ui.button("❌").clicked() {
+                        to_remove = Some(i);
+                    }
+                    ui.end_row();
                 }
-                
-                // 渲染流程结束，解除模态锁
-                is_rendering_clone.store(false, std::sync::atomic::Ordering::SeqCst);
             });
-        }
-    }
-}
\ No newline at end of file
+            if let Some(i) = to_remove {
+                cfg.channel_routes.remove(i);
+                changed = true;
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("➕ 添加").clicked() {
+                    // 默认接在上一条之后：同一端口的下一个通道，发往下一个引擎通道
+                    let route = match cfg.channel_routes.last() {
+                        Some(last) => ChannelRoute {
+                            port: last.port,
+                            channel: (last.channel + 1) % 16,
+                            target: (last.target + 1) % channels_per_socket,
+                        },
+                        None => ChannelRoute { port: 0, channel: 0, target: 0 },
+                    };
+                    cfg.channel_routes.push(route);
+                    changed = true;
+                }
+                if !cfg.channel_routes.is_empty() && ui.button("🧹 清空").clicked() {
+                    cfg.channel_routes.clear();
+                    changed = true;
+                }
+            });
+            if let Some(w) = cfg.channel_routes_warning() {
+                ui.label(egui::RichText::new(w).small().color(egui::Color32::from_rgb(230, 160, 0)));
+            }
+        });
+    changed
+}
+
+// 音高的音名，中央 C (60) 记为 C4
+fn note_name(key: u8) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    format!("{}{} ({})", NAMES[key as usize % 12], key as i32 / 12 - 1, key)
+}
+
+// 力度曲线的预览图：横轴为原始力度，纵轴为映射后的力度
+fn velocity_curve_preview(ui: &mut egui::Ui, curve: &VelocityCurve) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(48.0, 48.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    painter.line_segment([rect.left_bottom(), rect.right_top()], egui::Stroke::new(1.0, ui.visuals().weak_text_color()));
+    let points: Vec<egui::Pos2> = (1..=127u8)
+        .map(|v| egui::pos2(
+            rect.left() + v as f32 / 127.0 * rect.width(),
+            rect.bottom() - curve.apply(v) as f32 / 127.0 * rect.height(),
+        ))
+        .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, ui.visuals().selection.bg_fill)));
+}
+
+// 最近一分钟封包速率的走势图，纵轴按窗口内的最大值缩放
+fn packet_rate_sparkline(ui: &mut egui::Ui, history: &std::collections::VecDeque<u64>) {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width().min(300.0), 32.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let peak = history.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let step = rect.width() / (crate::PACKET_RATE_HISTORY - 1) as f32;
+    // 最新的数据靠右对齐
+    let offset = crate::PACKET_RATE_HISTORY - history.len();
+    let points: Vec<egui::Pos2> = history
+        .iter()
+        .enumerate()
+        .map(|(i, &rate)| egui::pos2(rect.left() + (offset + i) as f32 * step, rect.bottom() - rate as f32 / peak * (rect.height() - 2.0) - 1.0))
+        .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, ui.visuals().selection.bg_fill)));
+    response.on_hover_text(format!("最近 {} 秒的封包速率，峰值 {} 包/秒", history.len(), peak as u64));
+}
+
+// 在表格中输出一行参数检查提示，显示在对应输入框的下方
+fn validation_row(ui: &mut egui::Ui, text: &str, color: egui::Color32) {
+    ui.label("");
+    ui.label(egui::RichText::new(text).small().color(color));
+    ui.end_row();
+}
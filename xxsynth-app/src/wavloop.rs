@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use crate::config::LoopMode;
+use crate::player::midi_duration;
+
+// 循环长度 (乐曲本身) 至少要占渲染结果的这个比例，尾音更长时叠加会覆盖大半个循环，听起来像混响失控
+const MIN_LOOP_FRACTION: f64 = 0.5;
+
+/// 把渲染好的 WAV 处理成可无缝循环的音频，原地改写文件
+/// 循环点取 MIDI 最后一个事件的时刻，之后的部分视为释音尾巴：
+/// WrapTail 把尾巴叠加回开头 (下一轮循环开始时上一轮的尾音仍在继续)，Trim 直接在循环点截断
+pub fn apply_loop(wav_path: &Path, midi_path: &Path, mode: LoopMode) -> Result<(), String> {
+    if mode == LoopMode::Off {
+        return Ok(());
+    }
+
+    let loop_secs = midi_duration(midi_path)?;
+    let (spec, mut samples) = read_samples(wav_path)?;
+    let channels = spec.channels as usize;
+    let total_frames = samples.len() / channels;
+    let total_secs = total_frames as f64 / spec.sample_rate as f64;
+
+    if loop_secs <= 0.0 {
+        return Err("错误：无法确定循环点 (MIDI 没有任何事件)".to_string());
+    }
+    if loop_secs < total_secs * MIN_LOOP_FRACTION {
+        return Err(format!(
+            "错误：乐曲长 {:.1} 秒，但尾音长达 {:.1} 秒，不适合做成循环",
+            loop_secs,
+            total_secs - loop_secs
+        ));
+    }
+
+    let loop_frames = ((loop_secs * spec.sample_rate as f64).round() as usize).min(total_frames);
+    let loop_len = loop_frames * channels;
+    if mode == LoopMode::WrapTail {
+        let (head, tail) = samples.split_at_mut(loop_len);
+        // 尾巴比循环短 (上面已经检查过)，只需叠加一次
+        for (dst, src) in head.iter_mut().zip(tail.iter()) {
+            *dst = (*dst + *src).clamp(-1.0, 1.0);
+        }
+    }
+    samples.truncate(loop_len);
+
+    log::info!(
+        "循环处理完成：循环长度 {:.3} 秒，{} {:.3} 秒尾音",
+        loop_secs,
+        if mode == LoopMode::WrapTail { "叠加" } else { "截去" },
+        total_secs - loop_frames as f64 / spec.sample_rate as f64
+    );
+    write_samples(wav_path, spec, &samples)
+}
+
+// 读取全部采样并统一换算为 -1.0 ~ 1.0 的浮点数
+fn read_samples(path: &Path) -> Result<(WavSpec, Vec<f32>), String> {
+    let mut reader = WavReader::open(path).map_err(|e| format!("错误：无法读取渲染结果: {}", e))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 / scale)).collect()
+        }
+    };
+    samples.map(|s| (spec, s)).map_err(|e| format!("错误：无法读取渲染结果: {}", e))
+}
+
+// 按原来的格式写回，先写临时文件再替换，失败时不破坏原文件
+fn write_samples(path: &Path, spec: WavSpec, samples: &[f32]) -> Result<(), String> {
+    let tmp = path.with_extension("loop.tmp");
+    let write = || -> Result<(), hound::Error> {
+        let mut writer = WavWriter::create(&tmp, spec)?;
+        match spec.sample_format {
+            SampleFormat::Float => {
+                for &s in samples {
+                    writer.write_sample(s)?;
+                }
+            }
+            SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                for &s in samples {
+                    writer.write_sample((s * scale).round().clamp(-scale, scale - 1.0) as i32)?;
+                }
+            }
+        }
+        writer.finalize()
+    };
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(format!("错误：无法写入循环处理结果: {}", e));
+    }
+    std::fs::rename(&tmp, path).map_err(|e| format!("错误：无法替换渲染结果: {}", e))
+}
@@ -1,6 +1,11 @@
 use once_cell::sync::Lazy;
-use std::net::UdpSocket;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::PathBuf;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 // --- 手动定义必要的 Windows API 常量和结构体，彻底摆脱 windows-sys 依赖问题 ---
 pub const MODM_GETNUMDEVS: u32 = 1;
@@ -11,12 +16,26 @@ pub const MODM_PREPARE: u32 = 5;
 pub const MODM_UNPREPARE: u32 = 6;
 pub const MODM_DATA: u32 = 7;
 pub const MODM_LONGDATA: u32 = 8;
+pub const MODM_RESET: u32 = 9;
 
 pub const MMSYSERR_NOERROR: u32 = 0;
-pub const MMSYSERR_NOTSUPPORTED: u32 = 11;
+pub const MMSYSERR_BADDEVICEID: u32 = 2;
+pub const MMSYSERR_NOTSUPPORTED: u32 = 8;
+pub const MMSYSERR_INVALPARAM: u32 = 11;
+pub const MIDIERR_UNPREPARED: u32 = 64;
+
+pub const MHDR_DONE: u32 = 0x00000001;
+pub const MHDR_PREPARED: u32 = 0x00000002;
+pub const MHDR_INQUEUE: u32 = 0x00000004;
 
 pub const MOD_MIDIPORT: u16 = 1;
 
+pub const DRV_FREE: u32 = 0x0006;
+
+pub const MOM_OPEN: u32 = 0x3C7;
+pub const MOM_CLOSE: u32 = 0x3C8;
+pub const MOM_DONE: u32 = 0x3C9;
+
 #[repr(C)]
 pub struct MIDIOUTCAPSW {
     pub w_mid: u16,
@@ -29,10 +48,300 @@ pub struct MIDIOUTCAPSW {
     pub w_channel_mask: u16,
     pub dw_support: u32,
 }
+
+#[repr(C)]
+pub struct MIDIHDR {
+    pub lp_data: *mut u8,
+    pub dw_buffer_length: u32,
+    pub dw_bytes_recorded: u32,
+    pub dw_user: usize,
+    pub dw_flags: u32,
+    pub lp_next: *mut MIDIHDR,
+    pub reserved: usize,
+    pub dw_offset: u32,
+    pub dw_reserved: [usize; 8],
+}
+
+#[repr(C)]
+pub struct MIDIOPENDESC {
+    pub h_midi: usize,
+    pub dw_callback: usize,
+    pub dw_instance: usize,
+    pub dn_dev_node: usize,
+}
+
+#[cfg(windows)]
+#[link(name = "winmm")]
+unsafe extern "system" {
+    fn DriverCallback(
+        dw_callback: usize,
+        dw_flags: u32,
+        h_device: usize,
+        dw_msg: u32,
+        dw_user: usize,
+        dw_param1: usize,
+        dw_param2: usize,
+    ) -> i32;
+}
 // --------------------------------------------------------------------------
 
-// 全局复用的 UDP Socket，用于将 MIDI 数据极速发送给后台的 EXE 引擎
-static SOCKET: Lazy<Mutex<Option<UdpSocket>>> = Lazy::new(|| Mutex::new(None));
+// --- UDP 封包格式 (需与 xxsynth-app/src/audio.rs 保持一致) ---
+// 短消息: [端口ID, 状态字节, 数据1, 数据2]，端口ID 永远小于 0x80
+// 带时间戳的短消息: 短消息后再附带 8 字节 (小端) 的时间戳，即设备打开以来的微秒数，引擎按长度区分
+// 长消息: [PACKET_SYSEX, 端口ID, 长度低位, 长度高位, SysEx 原始字节...]
+// 控制类封包的首字节均 >= 0x80，以此与短消息区分
+// TCP 模式下每个封包前再加 2 字节 (小端) 的长度，以便在字节流中分帧
+// UDP 模式下可再包一层序号: [PACKET_SEQUENCED, 序号, 上述任一封包...]，引擎据此统计丢包
+// 引擎另外接受 MIDI 2.0 UMP 封包 [0x84, UMP 字...]，供支持 MIDI 2.0 的宿主直接发送；
+// WinMM 只会向驱动传递 MIDI 1.0 消息，因此驱动不会发出 UMP
+pub const PACKET_SYSEX: u8 = 0x80;
+// 单个 UDP 包能携带的最大 SysEx 长度 (扣除 SysEx 头部与序号封装)
+pub const MAX_SYSEX_LEN: usize = 65507 - 4 - 2;
+// 紧急静音: [PACKET_PANIC, 端口ID]，引擎会切断该端口全部通道的发声并复位控制器
+pub const PACKET_PANIC: u8 = 0x81;
+// 带序号的封包，序号在 0 ~ 255 间循环
+pub const PACKET_SEQUENCED: u8 = 0x83;
+
+// 引擎默认监听端口，共享配置缺失或无法解析时使用
+pub const DEFAULT_UDP_PORT: u16 = 44444;
+
+// 最多提供的 MIDI 端口数，也是共享配置缺失时的默认值
+pub const MAX_PORTS: u32 = 16;
+
+// 带时间戳短消息末尾附带的字节数
+pub const TIMESTAMP_BYTES: usize = 8;
+
+// 连接引擎时最多等待的时间，避免引擎未启动时卡住宿主
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+// UDP 发送缓冲区满时暂存封包的上限 (个数与总字节数)，超出后丢弃并计数，保证宿主线程不会被拖住
+const PENDING_LIMIT: usize = 4096;
+const PENDING_BYTES_LIMIT: usize = 1 << 20;
+// 关闭设备 / 卸载驱动时等待暂存封包发完的最长时间，避免丢掉最后的 NoteOff 与复位
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+// 发送失败或暂存队列溢出而丢弃的封包数，可通过导出函数 XXSynthDroppedPackets 查询
+static DROPPED_PACKETS: AtomicU64 = AtomicU64::new(0);
+
+// 发送通道：UDP 延迟最低；TCP 保证顺序与送达，连接断开后在下次打开设备时重连
+enum Link {
+    Udp(UdpSocket),
+    Tcp(Option<TcpStream>),
+}
+
+// 全局复用的 Socket 以及目标地址，用于将 MIDI 数据极速发送给后台的 EXE 引擎
+struct Sender {
+    link: Link,
+    target: SocketAddr,
+    sequenced: bool, // UDP 模式下是否给封包编号 (旧版引擎不认识序号封装，由共享配置开启)
+    next_seq: u8,
+    pending: VecDeque<Vec<u8>>, // UDP 发送会阻塞时暂存的封包，按顺序补发
+    pending_bytes: usize,
+}
+
+impl Sender {
+    fn open(target: SocketAddr, use_tcp: bool) -> Option<Self> {
+        let link = if use_tcp {
+            let stream = TcpStream::connect_timeout(&target, TCP_CONNECT_TIMEOUT).ok()?;
+            let _ = stream.set_nodelay(true);
+            Link::Tcp(Some(stream))
+        } else {
+            // 绑定任意本地端口发送，非阻塞模式下缓冲区满时立即返回 WouldBlock 而不是卡住宿主
+            let socket = UdpSocket::bind("127.0.0.1:0").ok()?;
+            let _ = socket.set_nonblocking(true);
+            Link::Udp(socket)
+        };
+        Some(Self { link, target, sequenced: false, next_seq: 0, pending: VecDeque::new(), pending_bytes: 0 })
+    }
+
+    // 协议与目标都没变且连接仍然可用时，打开设备可以直接沿用
+    fn is_reusable(&self, target: SocketAddr, use_tcp: bool) -> bool {
+        self.target == target
+            && match &self.link {
+                Link::Udp(_) => !use_tcp,
+                Link::Tcp(stream) => use_tcp && stream.is_some(),
+            }
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        match &mut self.link {
+            Link::Udp(_) if self.sequenced => {
+                let mut wrapped = Vec::with_capacity(2 + packet.len());
+                wrapped.extend_from_slice(&[PACKET_SEQUENCED, self.next_seq]);
+                wrapped.extend_from_slice(packet);
+                self.next_seq = self.next_seq.wrapping_add(1);
+                self.send_datagram(&wrapped);
+            }
+            Link::Udp(_) => self.send_datagram(packet),
+            Link::Tcp(stream) => {
+                let Some(s) = stream else { return };
+                // 长度前缀与封包合并为一次写入
+                let mut framed = Vec::with_capacity(2 + packet.len());
+                framed.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+                framed.extend_from_slice(packet);
+                if s.write_all(&framed).is_err() {
+                    // 引擎已关闭连接，等下次打开设备时重连
+                    *stream = None;
+                }
+            }
+        }
+    }
+
+    // 发送一个 UDP 包：先补发之前暂存的封包，仍有剩余时排在其后以保持顺序
+    // 缓冲区满时重试一次，仍然失败则暂存，等下一次发送或关闭设备时补发
+    // 驱动里不开后台线程：宿主卸载 DLL 时线程可能仍在运行，会在已卸载的代码中崩溃
+    fn send_datagram(&mut self, datagram: &[u8]) {
+        self.flush_pending();
+        let Link::Udp(socket) = &self.link else { return };
+        if self.pending.is_empty() {
+            for _ in 0..2 {
+                match socket.send_to(datagram, self.target) {
+                    Ok(_) => return,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                    Err(_) => {
+                        DROPPED_PACKETS.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+        }
+        if self.pending.len() >= PENDING_LIMIT || self.pending_bytes + datagram.len() > PENDING_BYTES_LIMIT {
+            DROPPED_PACKETS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.pending_bytes += datagram.len();
+        self.pending.push_back(datagram.to_vec());
+    }
+
+    // 按顺序补发暂存的封包，缓冲区仍满时停下等待下一轮
+    fn flush_pending(&mut self) {
+        let Link::Udp(socket) = &self.link else { return };
+        while let Some(datagram) = self.pending.front() {
+            match socket.send_to(datagram, self.target) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(_) => {
+                    DROPPED_PACKETS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.pending_bytes -= datagram.len();
+            self.pending.pop_front();
+        }
+    }
+
+    // 在限定时间内反复补发，直到暂存队列清空；超时后剩余的封包在 Sender 释放时计入丢弃数
+    fn drain_pending(&mut self) {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        self.flush_pending();
+        while !self.pending.is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+            self.flush_pending();
+        }
+    }
+}
+
+impl Drop for Sender {
+    // 切换协议或目标时尚未补发的封包无法再送达，计入丢弃数
+    fn drop(&mut self) {
+        DROPPED_PACKETS.fetch_add(self.pending.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// 调试用：返回驱动加载以来因发送失败或暂存队列溢出而丢弃的封包数
+#[unsafe(no_mangle)]
+pub extern "system" fn XXSynthDroppedPackets() -> u64 {
+    DROPPED_PACKETS.load(Ordering::Relaxed)
+}
+
+static SOCKET: Lazy<Mutex<Option<Sender>>> = Lazy::new(|| Mutex::new(None));
+
+// 与 EXE 共享的驱动配置文件 (%APPDATA%\xxsynth\driver.ini)，由 EXE 在启动引擎时写入
+fn driver_config_path() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("xxsynth").join("driver.ini"))
+}
+
+// 读取共享配置中的 key=value 项
+fn read_driver_config(key: &str) -> Option<String> {
+    let data = std::fs::read_to_string(driver_config_path()?).ok()?;
+    data.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(k, _)| k.trim() == key)
+        .map(|(_, v)| v.trim().to_string())
+}
+
+fn read_target_port() -> u16 {
+    read_driver_config("udp_port")
+        .and_then(|v| v.parse::<u16>().ok())
+        .filter(|&p| p != 0)
+        .unwrap_or(DEFAULT_UDP_PORT)
+}
+
+// 向宿主提供的端口数，限制在 1 ~ MAX_PORTS，设备 ID 始终为连续的 0 ~ N-1
+fn read_port_count() -> u32 {
+    read_driver_config("port_count")
+        .and_then(|v| v.parse::<u32>().ok())
+        .map(|n| n.clamp(1, MAX_PORTS))
+        .unwrap_or(MAX_PORTS)
+}
+
+// 共享配置中选择了 TCP 时返回 true，缺失时默认 UDP
+fn read_use_tcp() -> bool {
+    read_driver_config("transport").is_some_and(|v| v.eq_ignore_ascii_case("tcp"))
+}
+
+// 共享配置中开启了事件时间戳时返回 true，缺失时默认关闭
+fn read_use_timestamps() -> bool {
+    read_driver_config("timestamps").is_some_and(|v| v == "1")
+}
+
+// 引擎支持序号封装时会在共享配置中写入 sequence=1，缺失时不编号
+fn read_use_sequence() -> bool {
+    read_driver_config("sequence").is_some_and(|v| v == "1")
+}
+
+// 每个设备打开时宿主提供的回调信息，用于回送 MOM_OPEN / MOM_DONE / MOM_CLOSE
+#[derive(Clone, Copy)]
+struct ClientCallback {
+    callback: usize,
+    flags: u32,
+    h_midi: usize,
+    instance: usize,
+}
+
+static CLIENTS: Lazy<Mutex<[Option<ClientCallback>; MAX_PORTS as usize]>> = Lazy::new(|| Mutex::new([None; MAX_PORTS as usize]));
+
+// 每个设备最近一次的通道消息状态字节，用于还原省略了状态字节的 running status 消息
+static RUNNING_STATUS: Lazy<Mutex<[u8; MAX_PORTS as usize]>> = Lazy::new(|| Mutex::new([0; MAX_PORTS as usize]));
+
+// 每个设备打开的时刻，开启事件时间戳时作为时间戳的起点
+static OPENED_AT: Lazy<Mutex<[Option<Instant>; MAX_PORTS as usize]>> = Lazy::new(|| Mutex::new([None; MAX_PORTS as usize]));
+
+// 是否在短消息后附带时间戳，在打开设备时从共享配置读取
+static USE_TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+
+// 状态字节后面跟随的数据字节数
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF => 1,        // 音色切换、通道触后
+        0x80..=0xEF => 2,        // 其他通道消息
+        0xF1 | 0xF3 => 1,        // MTC 四分帧、乐曲选择
+        0xF2 => 2,               // 乐曲位置指针
+        _ => 0,                  // 其余系统消息
+    }
+}
+
+// 通知宿主 (窗口/线程/函数/事件回调均由 DriverCallback 统一分发)
+fn notify_client(u_device_id: u32, msg: u32, param1: usize) {
+    let client = CLIENTS.lock().unwrap().get(u_device_id as usize).copied().flatten();
+    if let Some(c) = client {
+        #[cfg(windows)]
+        unsafe {
+            DriverCallback(c.callback, c.flags, c.h_midi, msg, c.instance, param1, 0);
+        }
+        #[cfg(not(windows))]
+        let _ = (c.callback, c.flags, c.h_midi, c.instance, msg, param1);
+    }
+}
 
 // Windows 多媒体驱动生命周期回调
 #[unsafe(no_mangle)]
@@ -44,7 +353,17 @@ pub unsafe extern "system" fn DriverProc(
     _param2: usize,
 ) -> usize {
     match u_msg {
-        0x0001 | 0x0002 | 0x0003 | 0x0004 | 0x0005 | 0x0006 => 1,
+        // 驱动即将被卸载：发完暂存的封包后释放 Socket
+        DRV_FREE => {
+            if let Ok(mut sock) = SOCKET.lock() {
+                if let Some(sender) = sock.as_mut() {
+                    sender.drain_pending();
+                }
+                *sock = None;
+            }
+            1
+        }
+        0x0001 | 0x0002 | 0x0003 | 0x0004 | 0x0005 => 1,
         _ => 0,
     }
 }
@@ -52,15 +371,18 @@ pub unsafe extern "system" fn DriverProc(
 // 核心：处理所有的 MIDI 消息
 #[unsafe(no_mangle)]
 pub unsafe extern "system" fn modMessage(
-    u_device_id: u32, // 宿主请求的设备ID (0~15)
+    u_device_id: u32, // 宿主请求的设备ID (0 ~ 端口数-1)
     u_msg: u32,
     _user: usize,
     param1: usize,
-    _param2: usize,
+    param2: usize,
 ) -> u32 {
     match u_msg {
-        // 宿主询问支持多少个设备？答：16个
-        MODM_GETNUMDEVS => 16,
+        // 宿主询问支持多少个设备？答：共享配置中设置的端口数 (默认 16 个)
+        MODM_GETNUMDEVS => read_port_count(),
+
+        // 超出端口数的设备 ID 一律拒绝
+        MODM_GETDEVCAPS | MODM_OPEN if u_device_id >= read_port_count() => MMSYSERR_BADDEVICEID,
 
         // 宿主获取设备信息（名字会显示在 Domino 里）
         MODM_GETDEVCAPS => {
@@ -87,33 +409,169 @@ pub unsafe extern "system" fn modMessage(
 
         // 宿主准备打开设备
         MODM_OPEN => {
-            let mut sock = SOCKET.lock().unwrap();
-            if sock.is_none() {
-                // 绑定任意本地端口发送
-                *sock = UdpSocket::bind("127.0.0.1:0").ok();
+            {
+                let mut sock = SOCKET.lock().unwrap();
+                // 每次打开设备都重新读取目标端口与协议，这样用户在界面里改了设置后重新打开即可生效
+                let target = SocketAddr::from(([127, 0, 0, 1], read_target_port()));
+                let use_tcp = read_use_tcp();
+                if !sock.as_ref().is_some_and(|sender| sender.is_reusable(target, use_tcp)) {
+                    *sock = Sender::open(target, use_tcp);
+                }
+                if let Some(sender) = sock.as_mut() {
+                    sender.sequenced = read_use_sequence();
+                }
+            }
+            USE_TIMESTAMPS.store(read_use_timestamps(), Ordering::Relaxed);
+            if let Some(opened_at) = OPENED_AT.lock().unwrap().get_mut(u_device_id as usize) {
+                *opened_at = Some(Instant::now());
+            }
+
+            // 记录宿主的回调方式 (dwFlags 的高 16 位即 CALLBACK_* 类型)
+            // SAFETY: winmm 在 MODM_OPEN 中通过 param1 传入 MIDIOPENDESC，调用期间有效
+            if let Some(desc) = unsafe { (param1 as *const MIDIOPENDESC).as_ref() }
+                && let Some(slot) = CLIENTS.lock().unwrap().get_mut(u_device_id as usize)
+            {
+                *slot = Some(ClientCallback {
+                    callback: desc.dw_callback,
+                    flags: ((param2 as u32) >> 16) & 0xFFFF,
+                    h_midi: desc.h_midi,
+                    instance: desc.dw_instance,
+                });
+            }
+            if let Some(status) = RUNNING_STATUS.lock().unwrap().get_mut(u_device_id as usize) {
+                *status = 0;
             }
+            notify_client(u_device_id, MOM_OPEN, 0);
             MMSYSERR_NOERROR
         }
 
         // 宿主发送短 MIDI 消息
         MODM_DATA => {
-            if let Some(sock) = SOCKET.lock().unwrap().as_ref() {
-                let msg = param1 as u32;
-                let status = (msg & 0xFF) as u8;
-                let data1 = ((msg >> 8) & 0xFF) as u8;
-                let data2 = ((msg >> 16) & 0xFF) as u8;
-
-                // 封包格式：[端口ID, 状态字节, 数据1, 数据2]
-                let packet = [u_device_id as u8, status, data1, data2];
-                
-                // 无阻塞发给 44444 端口 (后台引擎监听端口)
-                let _ = sock.send_to(&packet, "127.0.0.1:44444");
+            if let Some(sender) = SOCKET.lock().unwrap().as_mut() {
+                let bytes = (param1 as u32).to_le_bytes();
+
+                // 首字节不是状态字节时为 running status，沿用该设备上一条通道消息的状态字节
+                let (status, data) = {
+                    let mut running = RUNNING_STATUS.lock().unwrap();
+                    let running = running.get_mut(u_device_id as usize);
+                    if bytes[0] >= 0x80 {
+                        if let Some(running) = running {
+                            // 通道消息更新 running status，系统公共消息清除它，实时消息 (0xF8 及以上) 不影响
+                            if bytes[0] < 0xF0 {
+                                *running = bytes[0];
+                            } else if bytes[0] < 0xF8 {
+                                *running = 0;
+                            }
+                        }
+                        (bytes[0], &bytes[1..3])
+                    } else {
+                        (running.map(|r| *r).unwrap_or(0), &bytes[0..2])
+                    }
+                };
+                if status < 0x80 {
+                    // 没有可以沿用的状态字节，丢弃
+                    return MMSYSERR_NOERROR;
+                }
+
+                // 封包格式：[端口ID, 状态字节, 数据...]，只携带该状态实际需要的数据字节
+                let len = data_len(status);
+                let mut packet = [0u8; 4 + TIMESTAMP_BYTES];
+                packet[0] = u_device_id as u8;
+                packet[1] = status;
+                packet[2..2 + len].copy_from_slice(&data[..len]);
+                let mut size = 2 + len;
+
+                // 开启时间戳时附带设备打开以来的微秒数
+                if USE_TIMESTAMPS.load(Ordering::Relaxed)
+                    && let Some(opened_at) = OPENED_AT.lock().unwrap().get(u_device_id as usize).copied().flatten()
+                {
+                    let micros = opened_at.elapsed().as_micros() as u64;
+                    packet[size..size + TIMESTAMP_BYTES].copy_from_slice(&micros.to_le_bytes());
+                    size += TIMESTAMP_BYTES;
+                }
+
+                // 发给后台引擎监听端口
+                sender.send(&packet[..size]);
             }
             MMSYSERR_NOERROR
         }
 
-        MODM_CLOSE | MODM_PREPARE | MODM_UNPREPARE => MMSYSERR_NOERROR,
-        MODM_LONGDATA => MMSYSERR_NOTSUPPORTED, // 长消息(SysEx)暂不处理
+        // 宿主准备/释放长消息缓冲区，需要正确维护 MHDR_PREPARED 标记，否则 midiOutLongMsg 会报错
+        MODM_PREPARE => {
+            // SAFETY: winmm 在 MODM_PREPARE 中通过 param1 传入宿主的 MIDIHDR，调用期间有效
+            match unsafe { (param1 as *mut MIDIHDR).as_mut() } {
+                Some(hdr) => {
+                    hdr.dw_flags |= MHDR_PREPARED;
+                    MMSYSERR_NOERROR
+                }
+                None => MMSYSERR_INVALPARAM,
+            }
+        }
+        MODM_UNPREPARE => {
+            // SAFETY: 同 MODM_PREPARE，param1 为宿主仍持有的 MIDIHDR
+            match unsafe { (param1 as *mut MIDIHDR).as_mut() } {
+                Some(hdr) => {
+                    hdr.dw_flags &= !MHDR_PREPARED;
+                    MMSYSERR_NOERROR
+                }
+                None => MMSYSERR_INVALPARAM,
+            }
+        }
+
+        // 宿主发送长消息 (SysEx，例如 GM/GS/XG Reset)
+        MODM_LONGDATA => {
+            // SAFETY: param1 为宿主传入的 MIDIHDR，在我们设置 MHDR_DONE 交还之前都归驱动使用
+            let Some(hdr) = (unsafe { (param1 as *mut MIDIHDR).as_mut() }) else {
+                return MMSYSERR_INVALPARAM;
+            };
+            if hdr.dw_flags & MHDR_PREPARED == 0 {
+                return MIDIERR_UNPREPARED;
+            }
+
+            let len = (hdr.dw_buffer_length as usize).min(MAX_SYSEX_LEN);
+            if !hdr.lp_data.is_null()
+                && len > 0
+                && let Some(sender) = SOCKET.lock().unwrap().as_mut()
+            {
+                // SAFETY: lp_data 非空，len 不超过宿主给出的 dw_buffer_length，缓冲区在交还前有效
+                let data = unsafe { std::slice::from_raw_parts(hdr.lp_data, len) };
+
+                // 封包格式：[PACKET_SYSEX, 端口ID, 长度(小端 u16), 数据...]
+                let mut packet = Vec::with_capacity(4 + len);
+                packet.push(PACKET_SYSEX);
+                packet.push(u_device_id as u8);
+                packet.extend_from_slice(&(len as u16).to_le_bytes());
+                packet.extend_from_slice(data);
+
+                sender.send(&packet);
+            }
+
+            // 数据已经发出，立即把缓冲区交还给宿主
+            hdr.dw_flags &= !MHDR_INQUEUE;
+            hdr.dw_flags |= MHDR_DONE;
+            notify_client(u_device_id, MOM_DONE, param1);
+            MMSYSERR_NOERROR
+        }
+
+        // 宿主请求复位 (例如停止播放时)：通知引擎清空该端口的所有通道，避免挂音
+        MODM_RESET => {
+            if let Some(sender) = SOCKET.lock().unwrap().as_mut() {
+                sender.send(&[PACKET_PANIC, u_device_id as u8]);
+            }
+            MMSYSERR_NOERROR
+        }
+
+        MODM_CLOSE => {
+            // 暂存的封包通常是最后的 NoteOff 与复位，关闭前发完
+            if let Some(sender) = SOCKET.lock().unwrap().as_mut() {
+                sender.drain_pending();
+            }
+            notify_client(u_device_id, MOM_CLOSE, 0);
+            if let Some(slot) = CLIENTS.lock().unwrap().get_mut(u_device_id as usize) {
+                *slot = None;
+            }
+            MMSYSERR_NOERROR
+        }
         _ => MMSYSERR_NOTSUPPORTED,
     }
 }
\ No newline at end of file